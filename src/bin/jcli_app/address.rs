@@ -1,7 +1,14 @@
+use bech32::{Bech32, ToBase32 as _};
+use cardano::util::hex;
 use chain_addr::{AddressReadable, Discrimination, Kind};
 use chain_crypto::bech32::Bech32 as _;
-use chain_crypto::{AsymmetricKey, Ed25519Extended, PublicKey};
-use jcli_app::utils::key_parser::parse_pub_key;
+use chain_crypto::{AsymmetricKey, Ed25519Extended, PublicKey, SecretKey};
+use jcli_app::utils::{
+    io,
+    key_parser::{parse_address_pub_key, AddressKeyError},
+};
+use std::io::BufRead;
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -16,6 +23,24 @@ pub enum Address {
 
     /// create an address from the the single public key
     Account(AccountArgs),
+
+    /// create an address from a spending public key and a delegation
+    /// public key, each given by name
+    Group(GroupArgs),
+
+    /// check a list of addresses, one per line, and report their
+    /// discrimination and kind
+    Validate(ValidateArgs),
+
+    /// compare two addresses component by component: kind,
+    /// discrimination, spending key and delegation key. Fails if the
+    /// keys of the addresses differ
+    Diff(DiffArgs),
+
+    /// create an address for each line of a file: the public key,
+    /// optionally followed by the delegation key, the bech32 prefix and
+    /// the network, separated by commas
+    Batch(BatchArgs),
 }
 
 #[derive(StructOpt)]
@@ -24,96 +49,626 @@ pub struct InfoArgs {
     /// and info that can be extracted from
     #[structopt(name = "ADDRESS")]
     address: AddressReadable,
+
+    /// also display the raw bytes of the address, hex encoded, including
+    /// the header byte with the discrimination and the kind
+    #[structopt(long = "raw-bytes")]
+    raw_bytes: bool,
+
+    /// display the info as a JSON object
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+#[derive(StructOpt)]
+pub struct ValidateArgs {
+    /// the file to read the addresses from, one address per line.
+    /// If not set, the addresses are read from the standard input
+    #[structopt(long = "input", name = "FILE", parse(from_os_str))]
+    input: Option<PathBuf>,
+
+    /// stop at the first invalid address instead of reporting all of them
+    #[structopt(long = "fail-fast")]
+    fail_fast: bool,
+}
+
+#[derive(StructOpt)]
+pub struct BatchArgs {
+    /// the file to read the lines from, `pubkey[,delegation_key][,prefix][,network]'.
+    /// If not set, the lines are read from the standard input
+    #[structopt(long = "input", name = "FILE", parse(from_os_str))]
+    input: Option<PathBuf>,
+
+    /// the bech32 prefix of the addresses of the lines not giving one
+    /// (default follows the network of the address: `ca' or `ta')
+    #[structopt(long = "prefix", name = "PREFIX")]
+    prefix: Option<String>,
+
+    /// the network of the addresses of the lines not giving one
+    #[structopt(flatten)]
+    network: NetworkArgs,
+}
+
+#[derive(StructOpt)]
+pub struct DiffArgs {
+    /// the first address to compare, in bech32 format
+    #[structopt(name = "ADDRESS")]
+    address: AddressReadable,
+
+    /// the second address to compare, in bech32 format
+    #[structopt(name = "OTHER_ADDRESS")]
+    other: AddressReadable,
 }
 
 #[derive(StructOpt)]
 pub struct SingleArgs {
     /// A public key in bech32 encoding with the key type prefix
-    #[structopt(name = "PUBLIC_KEY", parse(try_from_str = "parse_pub_key"))]
-    key: PublicKey<Ed25519Extended>,
+    ///
+    /// If the key is derived from a secret key (see `--secret' and
+    /// `--secret-file'), this parameter is the delegation key.
+    #[structopt(name = "PUBLIC_KEY", parse(try_from_str = "parse_address_pub_key"))]
+    key: Option<PublicKey<Ed25519Extended>>,
 
     /// A public key in bech32 encoding with the key type prefix
-    #[structopt(name = "DELEGATION_KEY", parse(try_from_str = "parse_pub_key"))]
+    #[structopt(name = "DELEGATION_KEY", parse(try_from_str = "parse_address_pub_key"))]
     delegation: Option<PublicKey<Ed25519Extended>>,
 
-    /// set the discrimination type to testing (default is production)
-    #[structopt(long = "testing")]
-    testing: bool,
+    #[structopt(flatten)]
+    secret: SecretKeyArgs,
+
+    #[structopt(flatten)]
+    network: NetworkArgs,
 }
 
 #[derive(StructOpt)]
 pub struct AccountArgs {
     /// A public key in bech32 encoding with the key type prefix
-    #[structopt(name = "PUBLIC_KEY", parse(try_from_str = "parse_pub_key"))]
-    key: PublicKey<Ed25519Extended>,
+    #[structopt(name = "PUBLIC_KEY", parse(try_from_str = "parse_address_pub_key"))]
+    key: Option<PublicKey<Ed25519Extended>>,
+
+    #[structopt(flatten)]
+    secret: SecretKeyArgs,
+
+    #[structopt(flatten)]
+    network: NetworkArgs,
+
+    /// also display the account identifier used by the ledger and the
+    /// REST API, hex encoded
+    #[structopt(long = "show-account-id")]
+    show_account_id: bool,
+}
+
+#[derive(StructOpt)]
+pub struct GroupArgs {
+    /// the public key spending from the address, in bech32 encoding with
+    /// the key type prefix
+    #[structopt(
+        long = "spending-key",
+        name = "SPENDING_KEY",
+        parse(try_from_str = "parse_address_pub_key")
+    )]
+    spending_key: PublicKey<Ed25519Extended>,
+
+    /// the public key the stake of the address is delegated with, in
+    /// bech32 encoding with the key type prefix
+    #[structopt(
+        long = "delegation-key",
+        name = "DELEGATION_KEY",
+        parse(try_from_str = "parse_address_pub_key")
+    )]
+    delegation_key: PublicKey<Ed25519Extended>,
+
+    #[structopt(flatten)]
+    network: NetworkArgs,
+}
+
+#[derive(StructOpt)]
+pub struct SecretKeyArgs {
+    /// derive the public key from the given secret key, in bech32 encoding
+    ///
+    /// The secret key may be visible to the other users of the system
+    /// (processes list, shell history...). Prefer `--secret-file'.
+    #[structopt(long = "secret", name = "SECRET_KEY")]
+    secret: Option<String>,
+
+    /// derive the public key from the secret key, in bech32 encoding,
+    /// read from the given file
+    #[structopt(
+        long = "secret-file",
+        name = "SECRET_FILE",
+        conflicts_with = "SECRET_KEY"
+    )]
+    secret_file: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+pub struct NetworkArgs {
+    /// the network the address is for: `production' or `test'
+    /// (default is production)
+    #[structopt(long = "network", name = "NETWORK")]
+    network: Option<Network>,
 
     /// set the discrimination type to testing (default is production)
-    #[structopt(long = "testing")]
+    ///
+    /// Deprecated, use `--network test' instead.
+    #[structopt(long = "testing", conflicts_with = "NETWORK")]
     testing: bool,
+
+    /// read the network from a genesis file, `--network' or `--testing'
+    /// take precedence if given
+    #[structopt(long = "config", name = "GENESIS_FILE", parse(from_os_str))]
+    config: Option<PathBuf>,
+}
+
+/// the network the addresses are discriminated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Production,
+    Test,
+}
+
+impl std::str::FromStr for Network {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "production" => Ok(Network::Production),
+            "test" | "testing" => Ok(Network::Test),
+            _ => Err(Error::UnknownNetwork {
+                network: s.to_owned(),
+            }),
+        }
+    }
+}
+
+impl From<Network> for Discrimination {
+    fn from(network: Network) -> Self {
+        match network {
+            Network::Production => Discrimination::Production,
+            Network::Test => Discrimination::Test,
+        }
+    }
 }
 
 custom_error! {pub Error
     MultisigAddressNotSupported = "multisig addresses are not supported",
+    MissingKey = "expected a public key or a secret key",
+    TooManyKeys = "too many keys given, the public key is already derived from the secret key",
+    SecretKeyFileRead { source: std::io::Error, path: PathBuf }
+        = @{{ let _ = source; format_args!("cannot read the secret key file '{}'", path.display()) }},
+    SecretKeyMalformed { source: chain_crypto::bech32::Error } = "invalid secret key",
+    GenesisConfig { source: jcli_app::block::Error } = "cannot read the network from the genesis file",
+    AddressListRead { source: std::io::Error } = "cannot read the list of addresses",
+    InvalidAddress { line: usize, source: chain_addr::Error }
+        = @{{ let _ = source; format_args!("invalid address at line {}", line) }},
+    InvalidAddresses { count: usize } = "{count} invalid address(es) found",
+    UnknownNetwork { network: String }
+        = "unknown network '{network}', expected 'production' or 'test'",
+    KeysDiffer = "the keys of the addresses differ",
+    BatchRead { source: std::io::Error } = "cannot read the batch file",
+    InvalidBatchLines { count: usize } = "{count} invalid line(s) found",
+}
+
+custom_error! {pub BatchLineError
+    TooManyColumns { count: usize } = "expected at most 4 columns, found {count}",
+    MissingKey = "expected a public key",
+    Key { source: AddressKeyError, column: &'static str }
+        = @{{ format_args!("invalid {}: {}", column, source) }},
+    Prefix { source: bech32::Error } = "invalid bech32 prefix: {source}",
+    Network { source: Error } = "{source}",
 }
 
 impl Address {
     pub fn exec(self) -> Result<(), Error> {
         match self {
-            Address::Info(info_args) => address_info(&info_args.address)?,
+            Address::Info(info_args) => address_info(&info_args)?,
             Address::Single(single_args) => {
-                if let Some(delegation) = single_args.delegation {
-                    mk_delegation(single_args.key, single_args.testing, delegation)
+                let discrimination = single_args.network.discrimination()?;
+                match single_args.keys()? {
+                    (key, Some(delegation)) => mk_delegation(key, discrimination, delegation),
+                    (key, None) => mk_single(key, discrimination),
+                }
+            }
+            Address::Account(account_args) => {
+                let discrimination = account_args.network.discrimination()?;
+                let show_account_id = account_args.show_account_id;
+                let key = account_args.key()?;
+                if show_account_id {
+                    mk_account(key.clone(), discrimination);
+                    println!("{}", hex::encode(key.as_ref()));
                 } else {
-                    mk_single(single_args.key, single_args.testing)
+                    mk_account(key, discrimination)
                 }
             }
-            Address::Account(account_args) => mk_account(account_args.key, account_args.testing),
+            Address::Group(group_args) => {
+                let discrimination = group_args.network.discrimination()?;
+                mk_delegation(
+                    group_args.spending_key,
+                    discrimination,
+                    group_args.delegation_key,
+                )
+            }
+            Address::Validate(validate_args) => validate_args.exec()?,
+            Address::Diff(diff_args) => diff_args.exec()?,
+            Address::Batch(batch_args) => batch_args.exec()?,
         }
         Ok(())
     }
 }
 
-fn address_info(address: &AddressReadable) -> Result<(), Error> {
-    let chain_addr::Address(discrimination, kind) = address.to_address();
-    match discrimination {
-        Discrimination::Production => {
-            println!("discrimination: production");
+impl SingleArgs {
+    fn keys(
+        self,
+    ) -> Result<
+        (
+            PublicKey<Ed25519Extended>,
+            Option<PublicKey<Ed25519Extended>>,
+        ),
+        Error,
+    > {
+        match self.secret.public_key()? {
+            // the only positional parameter is then the delegation key
+            Some(_) if self.delegation.is_some() => Err(Error::TooManyKeys),
+            Some(key) => Ok((key, self.key)),
+            None => Ok((self.key.ok_or(Error::MissingKey)?, self.delegation)),
         }
-        Discrimination::Test => {
-            println!("discrimination: testing");
+    }
+}
+
+impl AccountArgs {
+    fn key(self) -> Result<PublicKey<Ed25519Extended>, Error> {
+        match (self.secret.public_key()?, self.key) {
+            (Some(_), Some(_)) => Err(Error::TooManyKeys),
+            (Some(key), None) | (None, Some(key)) => Ok(key),
+            (None, None) => Err(Error::MissingKey),
         }
     }
+}
 
-    match kind {
-        Kind::Single(single) => println!("public key: {}", single.to_bech32_str()),
-        Kind::Account(account) => println!("account: {}", account.to_bech32_str()),
-        Kind::Multisig(_) => return Err(Error::MultisigAddressNotSupported),
-        Kind::Group(pubk, groupk) => {
-            println!("public key: {}", pubk.to_bech32_str());
-            println!("group key:  {}", groupk.to_bech32_str());
+impl NetworkArgs {
+    fn discrimination(&self) -> Result<Discrimination, Error> {
+        let explicit = if self.testing {
+            eprintln!("WARNING: `--testing' is deprecated, use `--network test' instead");
+            Some(Discrimination::Test)
+        } else {
+            self.network.map(Discrimination::from)
+        };
+        let configured = match &self.config {
+            Some(path) => Some(jcli_app::block::genesis_discrimination(path)?),
+            None => None,
+        };
+        match (explicit, configured) {
+            (Some(explicit), Some(configured)) => {
+                if explicit != configured {
+                    eprintln!(
+                        "WARNING: the network given on the command line ({}) differs from the genesis file ({}), using the command line",
+                        discrimination_name(explicit),
+                        discrimination_name(configured)
+                    );
+                }
+                Ok(explicit)
+            }
+            (Some(discrimination), None) | (None, Some(discrimination)) => Ok(discrimination),
+            (None, None) => Ok(Discrimination::Production),
         }
     }
-    Ok(())
 }
 
-fn mk_single(s: PublicKey<Ed25519Extended>, testing: bool) {
-    mk_address_1(s, testing, Kind::Single)
+impl SecretKeyArgs {
+    fn public_key(&self) -> Result<Option<PublicKey<Ed25519Extended>>, Error> {
+        let bech32_str = match (&self.secret, &self.secret_file) {
+            (Some(secret), _) => {
+                eprintln!(
+                    "WARNING: passing a secret key on the command line may expose it, prefer `--secret-file'"
+                );
+                secret.clone()
+            }
+            (None, Some(path)) => {
+                std::fs::read_to_string(path).map_err(|source| Error::SecretKeyFileRead {
+                    source,
+                    path: path.clone(),
+                })?
+            }
+            (None, None) => return Ok(None),
+        };
+        let secret = SecretKey::<Ed25519Extended>::try_from_bech32_str(bech32_str.trim())?;
+        Ok(Some(secret.to_public()))
+    }
+}
+
+impl ValidateArgs {
+    fn exec(self) -> Result<(), Error> {
+        let reader =
+            io::open_file_read(&self.input).map_err(|source| Error::AddressListRead { source })?;
+        let mut invalid = 0;
+        for (index, line) in reader.lines().enumerate() {
+            let line = line.map_err(|source| Error::AddressListRead { source })?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match line.parse::<AddressReadable>() {
+                Ok(address) => {
+                    let chain_addr::Address(discrimination, kind) = address.to_address();
+                    println!(
+                        "{}: valid, discrimination: {}, kind: {}",
+                        line,
+                        discrimination_name(discrimination),
+                        kind_name(&kind)
+                    );
+                }
+                Err(source) if self.fail_fast => {
+                    return Err(Error::InvalidAddress {
+                        line: index + 1,
+                        source,
+                    });
+                }
+                Err(error) => {
+                    println!("{}: invalid, {}", line, error);
+                    invalid += 1;
+                }
+            }
+        }
+        if invalid > 0 {
+            return Err(Error::InvalidAddresses { count: invalid });
+        }
+        Ok(())
+    }
+}
+
+impl BatchArgs {
+    /// every line is checked before any address is displayed, so a
+    /// malformed file does not give a partial list
+    fn exec(self) -> Result<(), Error> {
+        let discrimination = self.network.discrimination()?;
+        let reader =
+            io::open_file_read(&self.input).map_err(|source| Error::BatchRead { source })?;
+        let mut addresses = Vec::new();
+        let mut invalid = 0;
+        for (index, line) in reader.lines().enumerate() {
+            let line = line.map_err(|source| Error::BatchRead { source })?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match batch_address(
+                line,
+                self.prefix.as_ref().map(String::as_str),
+                discrimination,
+            ) {
+                Ok(address) => addresses.push(address),
+                Err(error) => {
+                    eprintln!("line {}: {}", index + 1, error);
+                    invalid += 1;
+                }
+            }
+        }
+        if invalid > 0 {
+            return Err(Error::InvalidBatchLines { count: invalid });
+        }
+        for address in addresses {
+            println!("{}", address);
+        }
+        Ok(())
+    }
+}
+
+// the address of a line of a batch file, the columns left empty or
+// omitted taking the defaults of the command line
+fn batch_address(
+    line: &str,
+    default_prefix: Option<&str>,
+    default_discrimination: Discrimination,
+) -> Result<String, BatchLineError> {
+    let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+    if columns.len() > 4 {
+        return Err(BatchLineError::TooManyColumns {
+            count: columns.len(),
+        });
+    }
+    let column = |index: usize| {
+        columns
+            .get(index)
+            .cloned()
+            .filter(|column| !column.is_empty())
+    };
+
+    let key = column(0).ok_or(BatchLineError::MissingKey)?;
+    let key = parse_address_pub_key(key).map_err(|source| BatchLineError::Key {
+        source,
+        column: "public key",
+    })?;
+    let delegation = match column(1) {
+        Some(delegation) => {
+            Some(
+                parse_address_pub_key(delegation).map_err(|source| BatchLineError::Key {
+                    source,
+                    column: "delegation key",
+                })?,
+            )
+        }
+        None => None,
+    };
+    let discrimination = match column(3) {
+        Some(network) => network
+            .parse::<Network>()
+            .map_err(|source| BatchLineError::Network { source })?
+            .into(),
+        None => default_discrimination,
+    };
+    let kind = match delegation {
+        Some(delegation) => Kind::Group(key, delegation),
+        None => Kind::Single(key),
+    };
+    let address = chain_addr::Address(discrimination, kind);
+
+    match column(2).or(default_prefix) {
+        Some(prefix) => {
+            let bech32 = Bech32::new(prefix.to_owned(), address.to_bytes().to_base32())
+                .map_err(|source| BatchLineError::Prefix { source })?;
+            Ok(bech32.to_string())
+        }
+        None => Ok(AddressReadable::from_address(&address).to_string()),
+    }
+}
+
+impl DiffArgs {
+    fn exec(self) -> Result<(), Error> {
+        let address = self.address.to_address();
+        let other = self.other.to_address();
+        let chain_addr::Address(discrimination, ref kind) = address;
+        let chain_addr::Address(other_discrimination, ref other_kind) = other;
+        let (spending_key, delegation_key) = address_keys(kind)?;
+        let (other_spending_key, other_delegation_key) = address_keys(other_kind)?;
+
+        print_diff("kind", kind_name(kind), kind_name(other_kind));
+        print_diff(
+            "discrimination",
+            discrimination_name(discrimination),
+            discrimination_name(other_discrimination),
+        );
+        // the keys are compared on their bytes, an account identifier
+        // is then the same key as the single public key it is made of
+        let spending_match = print_key_diff("spending key", spending_key, other_spending_key);
+        let delegation_match =
+            print_key_diff("delegation key", delegation_key, other_delegation_key);
+
+        if spending_match && delegation_match {
+            Ok(())
+        } else {
+            Err(Error::KeysDiffer)
+        }
+    }
+}
+
+// the spending key and the delegation key of an address, with their
+// bytes and their bech32 encoding
+type AddressKey<'a> = Option<(&'a [u8], String)>;
+
+fn address_keys(kind: &Kind) -> Result<(AddressKey, AddressKey), Error> {
+    match kind {
+        Kind::Single(single) => Ok((Some((single.as_ref(), single.to_bech32_str())), None)),
+        Kind::Account(account) => Ok((Some((account.as_ref(), account.to_bech32_str())), None)),
+        Kind::Group(pubk, groupk) => Ok((
+            Some((pubk.as_ref(), pubk.to_bech32_str())),
+            Some((groupk.as_ref(), groupk.to_bech32_str())),
+        )),
+        Kind::Multisig(_) => Err(Error::MultisigAddressNotSupported),
+    }
+}
+
+fn print_diff(name: &str, value: &str, other: &str) {
+    if value == other {
+        println!("{}: match ({})", name, value);
+    } else {
+        println!("{}: mismatch ({}, {})", name, value, other);
+    }
+}
+
+fn print_key_diff(name: &str, key: AddressKey, other: AddressKey) -> bool {
+    let matching = key.as_ref().map(|(bytes, _)| bytes) == other.as_ref().map(|(bytes, _)| bytes);
+    let display = |key: AddressKey| key.map_or_else(|| "none".to_owned(), |(_, bech32)| bech32);
+    if matching {
+        println!("{}: match ({})", name, display(key));
+    } else {
+        println!("{}: mismatch ({}, {})", name, display(key), display(other));
+    }
+    matching
 }
 
-fn mk_delegation(s: PublicKey<Ed25519Extended>, testing: bool, d: PublicKey<Ed25519Extended>) {
-    mk_address_2(s, d, testing, Kind::Group)
+fn discrimination_name(discrimination: Discrimination) -> &'static str {
+    match discrimination {
+        Discrimination::Production => "production",
+        Discrimination::Test => "testing",
+    }
 }
 
-fn mk_account(s: PublicKey<Ed25519Extended>, testing: bool) {
-    mk_address_1(s, testing, Kind::Account)
+fn kind_name(kind: &Kind) -> &'static str {
+    match kind {
+        Kind::Single(_) => "single",
+        Kind::Group(_, _) => "group",
+        Kind::Account(_) => "account",
+        Kind::Multisig(_) => "multisig",
+    }
+}
+
+// the bytes repeated in the public keys known to be unspendable, as no
+// one can have their secret key: a value sent to an address made of
+// such a key is burnt
+const SENTINEL_KEY_BYTES: [u8; 2] = [0x00, 0xff];
+
+fn is_sentinel_key(key: &[u8]) -> bool {
+    SENTINEL_KEY_BYTES
+        .iter()
+        .any(|sentinel| key.iter().all(|byte| byte == sentinel))
 }
 
-fn mk_discrimination(testing: bool) -> Discrimination {
-    if testing {
-        Discrimination::Test
+fn address_info(args: &InfoArgs) -> Result<(), Error> {
+    let address = args.address.to_address();
+    let chain_addr::Address(ref discrimination, ref kind) = address;
+
+    // only the spending key matters, the group key of a group address
+    // does not prevent spending from it
+    let spending_key: Option<&[u8]> = match kind {
+        Kind::Single(single) => Some(single.as_ref()),
+        Kind::Account(account) => Some(account.as_ref()),
+        Kind::Multisig(_) => None,
+        Kind::Group(pubk, _) => Some(pubk.as_ref()),
+    };
+    if spending_key.map_or(false, is_sentinel_key) {
+        eprintln!(
+            "WARNING: this appears to be a burn/unspendable address, no one can spend from its key"
+        );
+    }
+
+    // the fields to display, in order, with their JSON name and the
+    // heading of their line in the text output
+    let mut fields = vec![(
+        "discrimination",
+        "discrimination: ",
+        discrimination_name(discrimination.clone()).to_owned(),
+    )];
+    match kind {
+        Kind::Single(single) => fields.push(("public_key", "public key: ", single.to_bech32_str())),
+        Kind::Account(account) => fields.push(("account", "account: ", account.to_bech32_str())),
+        Kind::Multisig(_) => return Err(Error::MultisigAddressNotSupported),
+        Kind::Group(pubk, groupk) => {
+            fields.push(("public_key", "public key: ", pubk.to_bech32_str()));
+            fields.push(("group_key", "group key:  ", groupk.to_bech32_str()));
+        }
+    }
+    if args.raw_bytes {
+        fields.push(("raw_bytes", "raw bytes: ", hex::encode(&address.to_bytes())));
+    }
+
+    if args.json {
+        let object = fields
+            .into_iter()
+            .map(|(name, _, value)| (name.to_owned(), serde_json::Value::String(value)))
+            .collect::<serde_json::Map<_, _>>();
+        println!("{}", serde_json::Value::Object(object));
     } else {
-        Discrimination::Production
+        for (_, heading, value) in fields {
+            println!("{}{}", heading, value);
+        }
     }
+    Ok(())
+}
+
+fn mk_single(s: PublicKey<Ed25519Extended>, discrimination: Discrimination) {
+    mk_address_1(s, discrimination, Kind::Single)
+}
+
+fn mk_delegation(
+    s: PublicKey<Ed25519Extended>,
+    discrimination: Discrimination,
+    d: PublicKey<Ed25519Extended>,
+) {
+    mk_address_2(s, d, discrimination, Kind::Group)
+}
+
+fn mk_account(s: PublicKey<Ed25519Extended>, discrimination: Discrimination) {
+    mk_address_1(s, discrimination, Kind::Account)
 }
 
 fn mk_address(discrimination: Discrimination, kind: Kind) {
@@ -121,23 +676,21 @@ fn mk_address(discrimination: Discrimination, kind: Kind) {
     println!("{}", AddressReadable::from_address(&address).to_string());
 }
 
-fn mk_address_1<A, F>(s: PublicKey<A>, testing: bool, f: F)
+fn mk_address_1<A, F>(s: PublicKey<A>, discrimination: Discrimination, f: F)
 where
     F: FnOnce(PublicKey<A>) -> Kind,
     A: AsymmetricKey,
 {
-    let discrimination = mk_discrimination(testing);
     let kind = f(s);
     mk_address(discrimination, kind);
 }
 
-fn mk_address_2<A1, A2, F>(s: PublicKey<A1>, d: PublicKey<A2>, testing: bool, f: F)
+fn mk_address_2<A1, A2, F>(s: PublicKey<A1>, d: PublicKey<A2>, discrimination: Discrimination, f: F)
 where
     F: FnOnce(PublicKey<A1>, PublicKey<A2>) -> Kind,
     A1: AsymmetricKey,
     A2: AsymmetricKey,
 {
-    let discrimination = mk_discrimination(testing);
     let kind = f(s, d);
     mk_address(discrimination, kind);
 }
@@ -3,6 +3,7 @@ extern crate chain_core;
 extern crate chain_impl_mockchain;
 extern crate structopt;
 
+use chain_addr::Discrimination;
 use chain_core::property::{Block as _, Deserialize, HasMessages, Serialize};
 use chain_impl_mockchain::{
     block::Block,
@@ -10,7 +11,7 @@ use chain_impl_mockchain::{
 };
 use jcli_app::utils::{error::CustomErrorFiller, io};
 use std::io::{BufRead, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
 mod yaml;
@@ -39,6 +40,20 @@ impl Genesis {
     }
 }
 
+/// read the discrimination of the addresses from the given genesis file
+pub fn genesis_discrimination(path: &Path) -> Result<Discrimination, Error> {
+    let reader = io::open_file_read(&Some(path)).map_err(|source| Error::InputInvalid {
+        source,
+        path: path.to_path_buf(),
+    })?;
+    let genesis: yaml::Genesis =
+        serde_yaml::from_reader(reader).map_err(|source| Error::GenesisFileCorrupted {
+            source,
+            filler: CustomErrorFiller,
+        })?;
+    Ok(genesis.discrimination())
+}
+
 fn init_genesis_yaml() -> Result<(), Error> {
     println!("{}", yaml::documented_example(std::time::SystemTime::now()));
     Ok(())
@@ -91,6 +91,11 @@ custom_error! {pub Error
 }
 
 impl Genesis {
+    /// the discrimination of the addresses of the blockchain
+    pub fn discrimination(&self) -> Discrimination {
+        self.blockchain_configuration.discrimination
+    }
+
     pub fn from_block(block: &Block) -> Result<Self, Error> {
         let mut messages = block.messages().peekable();
 
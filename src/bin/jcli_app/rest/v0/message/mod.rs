@@ -17,13 +17,22 @@ pub enum Message {
         /// If not provided, message will be read from stdin.
         #[structopt(short, long)]
         file: Option<PathBuf>,
+        /// Last block date, as `<epoch>.<slot>`, at which the message
+        /// can be included in a block. Past that date, the node drops
+        /// the message from its pool.
+        #[structopt(long)]
+        valid_until: Option<String>,
     },
 }
 
 impl Message {
     pub fn exec(self) {
-        let (addr, file) = match self {
-            Message::Post { addr, file } => (addr, file),
+        let (addr, file, valid_until) = match self {
+            Message::Post {
+                addr,
+                file,
+                valid_until,
+            } => (addr, file, valid_until),
         };
         let msg_hex = match file {
             Some(path) => fs::read_to_string(path).unwrap(),
@@ -34,7 +43,11 @@ impl Message {
             }
         };
         let msg_bin = hex::decode(msg_hex.trim()).unwrap();
-        let url = addr.with_segments(&["v0", "message"]).unwrap().into_url();
+        let mut url = addr.with_segments(&["v0", "message"]).unwrap().into_url();
+        if let Some(valid_until) = valid_until {
+            url.query_pairs_mut()
+                .append_pair("valid_until", &valid_until);
+        }
         reqwest::Client::new()
             .post(url)
             .header(CONTENT_TYPE, "application/octet-stream")
@@ -1,6 +1,33 @@
 use chain_crypto::bech32::{Bech32, Error};
-use chain_crypto::{AsymmetricKey, PublicKey};
+use chain_crypto::{AsymmetricKey, Ed25519, Ed25519Extended, PublicKey, PublicKeyError};
 
 pub fn parse_pub_key<A: AsymmetricKey>(bech32_str: &str) -> Result<PublicKey<A>, Error> {
     Bech32::try_from_bech32_str(bech32_str)
 }
+
+custom_error! {pub AddressKeyError
+    Bech32 { source: bech32::Error } = "invalid Bech32",
+    KeyMalformed { source: Error } = "invalid public key",
+    KeyConversion { source: PublicKeyError } = "public key cannot be used in an address",
+    UnsupportedKeyType { hrp: String } = "public key type not supported in addresses: {hrp}",
+}
+
+/// parse a public key of any of the types supported in addresses,
+/// detecting the key type from the bech32 HRP
+pub fn parse_address_pub_key(
+    bech32_str: &str,
+) -> Result<PublicKey<Ed25519Extended>, AddressKeyError> {
+    let bech32: bech32::Bech32 = bech32_str.parse()?;
+    match bech32.hrp() {
+        Ed25519Extended::PUBLIC_BECH32_HRP => Ok(parse_pub_key(bech32_str)?),
+        Ed25519::PUBLIC_BECH32_HRP => {
+            // the public keys of both schemes are the same curve point,
+            // only the secret keys differ
+            let key = parse_pub_key::<Ed25519>(bech32_str)?;
+            Ok(PublicKey::from_binary(key.as_ref())?)
+        }
+        other => Err(AddressKeyError::UnsupportedKeyType {
+            hrp: other.to_string(),
+        }),
+    }
+}
@@ -228,7 +228,7 @@ impl Blockchain {
         self.storage.read().unwrap().get_block(hash)
     }
 
-    fn block_exists(&self, block_hash: &HeaderHash) -> Result<bool, storage::Error> {
+    pub fn block_exists(&self, block_hash: &HeaderHash) -> Result<bool, storage::Error> {
         // TODO: we assume as an invariant that if a block exists on
         // disk, its ancestors exist on disk as well. Need to make
         // sure that this invariant is preserved everywhere
@@ -287,7 +287,14 @@ pub enum HandledBlock {
 
     /// the block as been acquired, disseminate to the connected
     /// network that a block has been processed
-    Acquired { header: Header },
+    ///
+    /// If the block has become the tip on a different branch,
+    /// `rolled_back` lists the blocks of the previous branch that are
+    /// no longer in the chain, most recent first.
+    Acquired {
+        header: Header,
+        rolled_back: Vec<Block>,
+    },
 }
 
 #[derive(Debug)]
@@ -333,12 +340,77 @@ pub fn handle_end_of_epoch_event(blockchain: &Blockchain) -> Result<(), HandleBl
     Ok(())
 }
 
+/// the outcome of the checks done on a block before it is applied,
+/// see `pre_validate`
+pub enum PreValidation {
+    /// the header has been verified against the leadership of its epoch
+    Verified,
+    /// the leadership of the block's epoch is not known yet, the header
+    /// is verified when the block is applied
+    Deferred,
+    /// the block is of no interest for this blockchain
+    Rejected { reason: RejectionReason },
+}
+
+/// verify the header of a block without modifying the blockchain, so
+/// it can be done under a read lock, concurrently to the application
+/// of other blocks.
+///
+/// The block is then applied with `handle_pre_validated_block` if the
+/// header has been verified.
+///
+/// The header is verified against the leadership of the parent of the
+/// block as applied to the blockchain, there is no state for the blocks
+/// not applied yet. This only helps the blocks whose parent is already
+/// applied, the header of a block whose parent is still being validated
+/// is `Deferred` to the application of the block.
+pub fn pre_validate(
+    blockchain: &Blockchain,
+    header: &Header,
+) -> Result<PreValidation, HandleBlockError> {
+    if blockchain.block_exists(&header.id())? {
+        return Ok(PreValidation::Rejected {
+            reason: RejectionReason::AlreadyPresent,
+        });
+    }
+
+    match blockchain.get_leadership_or_build(header.date().epoch, &header.parent_id()) {
+        Some(leadership) => match leadership.verify(header) {
+            Verification::Success => Ok(PreValidation::Verified),
+            Verification::Failure(err) => Ok(PreValidation::Rejected {
+                reason: RejectionReason::Consensus(err),
+            }),
+        },
+        // the parent is probably not applied yet
+        None => Ok(PreValidation::Deferred),
+    }
+}
+
 pub fn handle_block(
     blockchain: &mut Blockchain,
     block: Block,
     is_tip_candidate: bool,
 ) -> Result<HandledBlock, HandleBlockError> {
-    match header_triage(blockchain, &block.header(), is_tip_candidate)? {
+    handle_block_with(blockchain, block, is_tip_candidate, true)
+}
+
+/// same as `handle_block` for a block whose header has already been
+/// verified with `pre_validate`
+pub fn handle_pre_validated_block(
+    blockchain: &mut Blockchain,
+    block: Block,
+    is_tip_candidate: bool,
+) -> Result<HandledBlock, HandleBlockError> {
+    handle_block_with(blockchain, block, is_tip_candidate, false)
+}
+
+fn handle_block_with(
+    blockchain: &mut Blockchain,
+    block: Block,
+    is_tip_candidate: bool,
+    verify_header: bool,
+) -> Result<HandledBlock, HandleBlockError> {
+    match triage(blockchain, &block.header(), is_tip_candidate, verify_header)? {
         BlockHeaderTriage::NotOfInterest { reason } => Ok(HandledBlock::Rejected { reason }),
         BlockHeaderTriage::MissingParentOrBranch { to } => {
             // the block is not directly connected to any block
@@ -403,7 +475,12 @@ fn process_block(
         state,
     );
 
+    let mut rolled_back = Vec::new();
     if new_chain_length > tip_chain_length {
+        let current_tip = blockchain.get_tip().unwrap();
+        if current_tip != block.parent_id() {
+            rolled_back = branch_blocks_since(blockchain, &current_tip, &block.parent_id())?;
+        }
         blockchain.put_tip(branch, &block)?;
     } else {
         blockchain.put_block(&block)?;
@@ -411,13 +488,52 @@ fn process_block(
 
     Ok(HandledBlock::Acquired {
         header: block.header(),
+        rolled_back,
     })
 }
 
+/// collect the blocks of the branch ending at `from` that are not
+/// ancestors of `to` (nor `to` itself), most recent first
+fn branch_blocks_since(
+    blockchain: &Blockchain,
+    from: &HeaderHash,
+    to: &HeaderHash,
+) -> Result<Vec<Block>, storage::Error> {
+    let mut blocks = Vec::new();
+    let (mut from, _) = blockchain.get_block(from)?;
+    let (mut to, _) = blockchain.get_block(to)?;
+
+    while from.header.chain_length() > to.header.chain_length() {
+        let parent = blockchain.get_block(&from.parent_id())?.0;
+        blocks.push(from);
+        from = parent;
+    }
+    while to.header.chain_length() > from.header.chain_length() {
+        to = blockchain.get_block(&to.parent_id())?.0;
+    }
+    while from.id() != to.id() {
+        let parent = blockchain.get_block(&from.parent_id())?.0;
+        blocks.push(from);
+        from = parent;
+        to = blockchain.get_block(&to.parent_id())?.0;
+    }
+
+    Ok(blocks)
+}
+
 pub fn header_triage(
     blockchain: &Blockchain,
     header: &Header,
     is_tip_candidate: bool,
+) -> Result<BlockHeaderTriage, HandleBlockError> {
+    triage(blockchain, header, is_tip_candidate, true)
+}
+
+fn triage(
+    blockchain: &Blockchain,
+    header: &Header,
+    is_tip_candidate: bool,
+    verify_header: bool,
 ) -> Result<BlockHeaderTriage, HandleBlockError> {
     let block_id = header.id();
     let parent_id = header.parent_id();
@@ -431,7 +547,11 @@ pub fn header_triage(
 
     let (block_tip, _) = blockchain.get_block_tip()?;
 
-    if let Some(leadership) = blockchain.get_leadership_or_build(block_date.epoch, &parent_id) {
+    if !verify_header {
+        // already verified by `pre_validate`
+    } else if let Some(leadership) =
+        blockchain.get_leadership_or_build(block_date.epoch, &parent_id)
+    {
         match leadership.verify(header) {
             Verification::Success => {}
             Verification::Failure(err) => {
@@ -1,11 +1,27 @@
 mod branch;
 mod chain;
+mod orphan;
 mod process;
+mod produced;
+mod reorg;
+#[cfg(feature = "testing")]
+mod replay;
 mod tip;
+mod validation;
+mod watchdog;
 
 pub use self::branch::Branch;
 pub use self::chain::{
     handle_block, Blockchain, BlockchainR, HandleBlockError, HandledBlock, LoadError,
 };
-pub use self::process::handle_input;
+pub use self::orphan::OrphanQueue;
+pub use self::process::{
+    handle_input, BlockAppliedHooks, BlockSource, BlockTaskState, OnBlockApplied, ShutdownState,
+};
+pub use self::produced::ProducedSlots;
+pub use self::reorg::{ReorgEvent, ReorgNotifier};
+#[cfg(feature = "testing")]
+pub use self::replay::{replay, BlockOutcome, ReplayOutcome};
 pub use self::tip::{Tip, TipGetError, TipReplaceError};
+pub use self::validation::{validation_pool, ValidationPool, ValidationWorkers};
+pub use self::watchdog::watch_tip;
@@ -0,0 +1,82 @@
+use crate::blockcfg::{Block, HeaderHash};
+use chain_core::property::Header as _;
+use std::collections::VecDeque;
+
+/// Bounded queue of the blocks received from the network whose parent
+/// is not yet known.
+///
+/// Blocks are often delivered slightly out of order by the network: the
+/// parent is only a few milliseconds behind, received via another peer.
+/// Instead of dropping these blocks, they are kept here and their
+/// application is retried a limited number of times.
+pub struct OrphanQueue {
+    entries: VecDeque<OrphanEntry>,
+    capacity: usize,
+    max_retries: usize,
+}
+
+pub struct OrphanEntry {
+    pub block: Block,
+    /// the number of attempts left to apply the block
+    pub retries_left: usize,
+}
+
+impl OrphanQueue {
+    pub fn new(capacity: usize, max_retries: usize) -> Self {
+        OrphanQueue {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            max_retries,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// whether the block is queued for retries
+    pub fn contains(&self, id: &HeaderHash) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| &entry.block.header.id() == id)
+    }
+
+    /// queue the block for retries
+    ///
+    /// If the queue is full, the oldest block is evicted and returned.
+    pub fn push(&mut self, block: Block) -> Option<Block> {
+        let evicted = if self.entries.len() >= self.capacity {
+            self.entries.pop_front().map(|entry| entry.block)
+        } else {
+            None
+        };
+        if self.capacity > 0 {
+            self.entries.push_back(OrphanEntry {
+                block,
+                retries_left: self.max_retries,
+            });
+        }
+        evicted
+    }
+
+    /// queue back an entry after a failed retry, if it still has retries
+    /// left. Returns the block if it has exhausted its retry budget.
+    pub fn requeue(&mut self, mut entry: OrphanEntry) -> Option<Block> {
+        entry.retries_left = entry.retries_left.saturating_sub(1);
+        if entry.retries_left == 0 {
+            Some(entry.block)
+        } else {
+            self.entries.push_back(entry);
+            None
+        }
+    }
+
+    /// take all the queued entries out, in the order they were received
+    pub fn drain(&mut self) -> Vec<OrphanEntry> {
+        self.entries.drain(..).collect()
+    }
+}
@@ -1,18 +1,118 @@
-use crate::blockchain::chain::{self, BlockHeaderTriage, BlockchainR, HandledBlock};
-use crate::intercom::{BlockMsg, NetworkMsg, PropagateMsg};
+use crate::blockcfg::{Block, Header};
+use crate::blockchain::{
+    chain::{self, BlockHeaderTriage, Blockchain, BlockchainR, HandledBlock},
+    OrphanQueue, ProducedSlots, ReorgEvent, ReorgNotifier, ValidationPool,
+};
+use crate::fragment::Pool;
+use crate::intercom::{BlockMsg, NetworkMsg, PropagateMsg, ReplyHandle};
 use crate::rest::v0::node::stats::StatsCounter;
 use crate::utils::{
     async_msg::MessageBox,
     task::{Input, TokioServiceInfo},
 };
 
-use chain_core::property::Header as _;
+use chain_core::property::{HasMessages as _, Header as _};
+use futures::Future;
+use slog::Logger;
+use std::time::Instant;
+
+/// Network blocks more than this number of epochs older than the tip
+/// are rejected before any validation.
+const NETWORK_BLOCK_MAX_EPOCH_AGE: u32 = 2;
+
+/// where a block applied to the blockchain comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockSource {
+    /// received from a network peer
+    Network,
+    /// created by the leadership of this node
+    Leadership,
+}
+
+/// Called with each block successfully applied to the blockchain, once
+/// the lock of the blockchain is released, e.g. to update an external
+/// index of the blocks.
+pub type OnBlockApplied<'a> = &'a dyn Fn(&Block, BlockSource);
+
+/// The hooks called with each block applied to the blockchain by the
+/// block task, see `OnBlockApplied`. They are registered when starting
+/// the services of the node, before the block task runs: there is no
+/// setting for them, a hook is added by registering it in
+/// `start_services`, where the block task is started.
+#[derive(Default)]
+pub struct BlockAppliedHooks {
+    hooks: Vec<Box<dyn Fn(&Block, BlockSource) + Send>>,
+}
+
+impl BlockAppliedHooks {
+    /// add a hook, called after the ones already registered
+    pub fn register<F>(&mut self, hook: F)
+    where
+        F: Fn(&Block, BlockSource) + Send + 'static,
+    {
+        self.hooks.push(Box::new(hook));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// call the hooks with the block applied, in the order they were
+    /// registered
+    pub fn call(&self, block: &Block, source: BlockSource) {
+        for hook in self.hooks.iter() {
+            hook(block, source);
+        }
+    }
+}
+
+/// The shutdown of the node, as seen by the block task.
+pub enum ShutdownState {
+    Running,
+    /// no longer accepting new blocks, the shutdown is acknowledged once
+    /// the blocks still in the validation pool have been applied
+    Draining(ReplyHandle<()>),
+    /// no longer accepting new blocks, the shutdown has been acknowledged
+    Stopped,
+}
+
+impl ShutdownState {
+    // acknowledge the shutdown if it is waiting for the validation pool
+    // and no block is left in it
+    fn acknowledge_if_drained(&mut self, validation: &ValidationPool, logger: &Logger) {
+        if !validation.is_idle() {
+            return;
+        }
+        match std::mem::replace(self, ShutdownState::Stopped) {
+            ShutdownState::Draining(reply) => {
+                info!(logger, "the blocks being validated have been applied");
+                reply.reply_ok(());
+            }
+            state => *self = state,
+        }
+    }
+}
+
+/// The state kept by the block task from one message to the next.
+pub struct BlockTaskState {
+    pub stats_counter: StatsCounter,
+    pub network_msg_box: MessageBox<NetworkMsg>,
+    pub fragment_pool: Pool,
+    pub reorgs: ReorgNotifier,
+    pub orphans: OrphanQueue,
+    pub validation: ValidationPool,
+    pub produced: ProducedSlots,
+    /// read back the blocks of our own from the storage before they are
+    /// propagated, see `is_readable`
+    pub confirm_readable: bool,
+    pub shutdown: ShutdownState,
+}
 
 pub fn handle_input(
     info: &TokioServiceInfo,
     blockchain: &BlockchainR,
-    _stats_counter: &StatsCounter,
-    network_msg_box: &mut MessageBox<NetworkMsg>,
+    state: &mut BlockTaskState,
+    on_block_applied: Option<OnBlockApplied>,
     input: Input<BlockMsg>,
 ) {
     let bquery = match input {
@@ -26,12 +126,44 @@ pub fn handle_input(
 
     let logger = info.logger().clone();
 
+    let bquery = match state.shutdown {
+        ShutdownState::Running => bquery,
+        _ => match bquery {
+            // the blocks already validated or created by the leadership
+            // are still applied, only the new network blocks are ignored
+            msg @ BlockMsg::ValidatedNetworkBlock { .. } | msg @ BlockMsg::LeadershipBlock(_) => {
+                msg
+            }
+            // the shutdown sequence runs once, the acknowledgment of a
+            // repeated request does not wait
+            BlockMsg::Shutdown(reply) => {
+                reply.reply_ok(());
+                return;
+            }
+            _ => {
+                debug!(logger, "shutting down, ignoring the block message");
+                return;
+            }
+        },
+    };
+
     match bquery {
         BlockMsg::LeadershipExpectEndOfEpoch => {
             let blockchain = blockchain.lock_read();
             chain::handle_end_of_epoch_event(&blockchain).unwrap()
         }
         BlockMsg::LeadershipBlock(block) => {
+            let date = block.header.date();
+            if state.produced.contains(&date) {
+                // the leadership schedule is wrong, the first block of
+                // the slot may already be known to the network
+                error!(logger, "a block has already been created for this slot, dropping the new one";
+                    "id" => block.header.id().to_string(),
+                    "date" => date.to_string()
+                );
+                return;
+            }
+            let applied = on_block_applied.map(|_| block.clone());
             let mut blockchain = blockchain.lock_write();
             match chain::handle_block(&mut blockchain, block, true).unwrap() {
                 HandledBlock::Rejected { reason } => {
@@ -51,57 +183,85 @@ pub fn handle_input(
                         "the block cannot be added, missing intermediate blocks to {}", to
                     );
                 }
-                HandledBlock::Acquired { header } => {
+                HandledBlock::Acquired {
+                    header,
+                    rolled_back,
+                } => {
+                    handle_rolled_back(
+                        info,
+                        &logger,
+                        &mut state.fragment_pool,
+                        &state.reorgs,
+                        &header,
+                        rolled_back,
+                    );
+                    flush_expired(info, &logger, &mut state.fragment_pool, &header);
                     info!(logger,
                         "block added successfully to Node's blockchain";
                         "id" => header.id().to_string(),
                         "date" => header.date().to_string()
                     );
                     debug!(logger, "Header: {:?}", header);
-                    network_msg_box
-                        .try_send(NetworkMsg::Propagate(PropagateMsg::Block(header)))
-                        .unwrap_or_else(|err| {
-                            error!(logger, "cannot propagate block to network: {}", err)
-                        });
+                    state.produced.insert(date);
+                    state.stats_counter.set_last_block_time(Instant::now());
+                    state.stats_counter.add_leadership_block_produced();
+                    // the block is applied all the same, only its
+                    // propagation is given up
+                    if state.confirm_readable && !is_readable(&blockchain, &header) {
+                        error!(logger, "the block cannot be read back from the storage, not propagating it";
+                            "id" => header.id().to_string()
+                        );
+                    } else {
+                        propagate_own_block(&logger, state, header);
+                    }
+                    drop(blockchain);
+                    if let (Some(on_block_applied), Some(block)) = (on_block_applied, applied) {
+                        on_block_applied(&block, BlockSource::Leadership);
+                    }
                 }
             }
         }
         BlockMsg::NetworkBlock(block) => {
-            let mut blockchain = blockchain.lock_write();
-            match chain::handle_block(&mut blockchain, block, true).unwrap() {
-                HandledBlock::Rejected { reason } => {
-                    // TODO: drop the network peer that has sent
-                    // an invalid block.
-                    warn!(logger, "rejecting block from the network: {:?}", reason);
-                }
-                HandledBlock::MissingBranchToBlock { to } => {
-                    // This is abnormal because we have received a block
-                    // that is not connected to preceding blocks, which
-                    // should not happen as we solicit blocks in descending
-                    // order.
-                    //
-                    // TODO: drop the network peer that has sent
-                    // the wrong block.
-                    warn!(
-                        logger,
-                        "disconnected block received, missing intermediate blocks to {}", to
-                    );
-                }
-                HandledBlock::Acquired { header } => {
-                    info!(logger,
-                        "block added successfully to Node's blockchain";
-                        "id" => header.id().to_string(),
-                        "date" => format!("{}.{}", header.date().epoch, header.date().slot_id)
-                    );
-                    debug!(logger, "Header: {:?}", header);
-                    // Propagate the block to other nodes
-                    network_msg_box
-                        .try_send(NetworkMsg::Propagate(PropagateMsg::Block(header)))
-                        .unwrap_or_else(|err| {
-                            error!(logger, "cannot propagate block to network: {}", err)
-                        });
-                }
+            if is_beyond_max_age(&blockchain.lock_read(), &block.header) {
+                // TODO: penalize the network peer that has sent
+                // the block, once known here.
+                info!(logger, "rejecting block from the network, too far behind the tip";
+                    "id" => block.header.id().to_string(),
+                    "date" => block.header.date().to_string()
+                );
+                state.stats_counter.add_network_block_rejected();
+                return;
+            }
+            // the block is validated and applied right away if no
+            // validation worker is available and the blocks submitted
+            // before it have been applied
+            for (block, header_verified) in state.validation.submit(block) {
+                handle_network_block(
+                    info,
+                    &logger,
+                    blockchain,
+                    state,
+                    on_block_applied,
+                    block,
+                    !header_verified,
+                );
+            }
+        }
+        BlockMsg::ValidatedNetworkBlock { seq, validated } => {
+            for (block, header_verified) in state.validation.complete(seq, validated) {
+                handle_network_block(
+                    info,
+                    &logger,
+                    blockchain,
+                    state,
+                    on_block_applied,
+                    block,
+                    !header_verified,
+                );
             }
+            state
+                .shutdown
+                .acknowledge_if_drained(&state.validation, &logger);
         }
         BlockMsg::AnnouncedBlock(header, node_id) => {
             let blockchain = blockchain.lock_read();
@@ -121,7 +281,8 @@ pub fn handle_input(
                 }
                 BlockHeaderTriage::ProcessBlockToState => {
                     info!(logger, "Block announcement is interesting, fetch block");
-                    network_msg_box
+                    state
+                        .network_msg_box
                         .try_send(NetworkMsg::GetBlocks(node_id, vec![header.id()]))
                         .unwrap_or_else(|err| {
                             error!(logger, "cannot propagate block to network: {}", err)
@@ -129,5 +290,277 @@ pub fn handle_input(
                 }
             }
         }
+        BlockMsg::Shutdown(reply) => {
+            // the blocks still in the validation pool are applied before
+            // the shutdown is acknowledged, so the propagation of the last
+            // block received is queued to the network task by then
+            info!(logger, "no longer accepting new blocks");
+            state.shutdown = ShutdownState::Draining(reply);
+            state
+                .shutdown
+                .acknowledge_if_drained(&state.validation, &logger);
+        }
+    }
+}
+
+/// apply a block received from the network, its header is verified
+/// first unless it has been already by the validation pool
+fn handle_network_block(
+    info: &TokioServiceInfo,
+    logger: &Logger,
+    blockchain: &BlockchainR,
+    state: &mut BlockTaskState,
+    on_block_applied: Option<OnBlockApplied>,
+    block: Block,
+    verify_header: bool,
+) {
+    let mut applied = Vec::new();
+    apply_network_block(
+        info,
+        logger,
+        blockchain,
+        state,
+        &mut applied,
+        block,
+        verify_header,
+    );
+    // the lock of the blockchain is released by now
+    if let Some(on_block_applied) = on_block_applied {
+        for block in &applied {
+            on_block_applied(block, BlockSource::Network);
+        }
+    }
+}
+
+fn apply_network_block(
+    info: &TokioServiceInfo,
+    logger: &Logger,
+    blockchain: &BlockchainR,
+    state: &mut BlockTaskState,
+    applied: &mut Vec<Block>,
+    block: Block,
+    verify_header: bool,
+) {
+    let mut blockchain = blockchain.lock_write();
+    let orphan = block.clone();
+    let handled = if verify_header {
+        chain::handle_block(&mut blockchain, block, true)
+    } else {
+        chain::handle_pre_validated_block(&mut blockchain, block, true)
+    };
+    match handled.unwrap() {
+        HandledBlock::Rejected { reason } => {
+            // TODO: drop the network peer that has sent
+            // an invalid block.
+            warn!(logger, "rejecting block from the network: {:?}", reason);
+            state.stats_counter.add_network_block_rejected();
+        }
+        HandledBlock::MissingBranchToBlock { to } => {
+            // This is abnormal because we have received a block
+            // that is not connected to preceding blocks, which
+            // should not happen as we solicit blocks in descending
+            // order.
+            //
+            // TODO: drop the network peer that has sent
+            // the wrong block.
+            //
+            // The parent may also simply be a few milliseconds
+            // behind, so the block is kept for a few retries.
+            warn!(
+                logger,
+                "disconnected block received, missing intermediate blocks to {}", to
+            );
+            if let Some(evicted) = state.orphans.push(orphan) {
+                warn!(logger, "orphan block queue is full, dropping block";
+                    "id" => evicted.header.id().to_string()
+                );
+            }
+        }
+        HandledBlock::Acquired {
+            header,
+            rolled_back,
+        } => {
+            state.stats_counter.set_last_block_time(Instant::now());
+            state.stats_counter.add_network_block_applied();
+            handle_rolled_back(
+                info,
+                logger,
+                &mut state.fragment_pool,
+                &state.reorgs,
+                &header,
+                rolled_back,
+            );
+            flush_expired(info, logger, &mut state.fragment_pool, &header);
+            network_block_acquired(logger, &mut state.network_msg_box, header);
+            applied.push(orphan);
+            retry_orphans(info, logger, &mut blockchain, state, applied);
+        }
+    }
+}
+
+/// read back the block from the storage, to make sure a block of our own
+/// is not announced if it could not be stored.
+///
+/// The block is written by `handle_block` already, this confirms the
+/// storage serves it back before it is announced. The storage is not
+/// flushed to the disk, whether the block survives a crash of the node
+/// is up to the storage backend.
+fn is_readable(blockchain: &Blockchain, header: &Header) -> bool {
+    blockchain.block_exists(&header.id()).unwrap_or(false)
+}
+
+/// cheap check of the date of a block received from the network
+/// against the date of the tip, before any expensive validation
+fn is_beyond_max_age(blockchain: &Blockchain, header: &Header) -> bool {
+    let tip_epoch = match blockchain.get_block_tip() {
+        Ok((tip, _)) => tip.header.date().epoch,
+        // let the block validation report the storage issue
+        Err(_) => return false,
+    };
+    header.date().epoch + NETWORK_BLOCK_MAX_EPOCH_AGE < tip_epoch
+}
+
+/// queue the propagation of a block created by the leadership of this node
+fn propagate_own_block(logger: &Logger, state: &mut BlockTaskState, header: Header) {
+    if let Some(since) = state.network_msg_box.congested_since() {
+        // the block still goes to the queue of the network task, if there
+        // is room
+        warn!(logger, "the network task is not keeping up, the block may be propagated late";
+            "congested_for" => format!("{:?}", since.elapsed())
+        );
+    }
+    let id = header.id();
+    let date = header.date();
+    match state
+        .network_msg_box
+        .try_send(NetworkMsg::Propagate(PropagateMsg::Block(header)))
+    {
+        Ok(()) => state.stats_counter.set_last_broadcast_block(id, date),
+        Err(err) => error!(logger, "cannot propagate block to network: {}", err),
+    }
+}
+
+fn network_block_acquired(
+    logger: &Logger,
+    network_msg_box: &mut MessageBox<NetworkMsg>,
+    header: Header,
+) {
+    info!(logger,
+        "block added successfully to Node's blockchain";
+        "id" => header.id().to_string(),
+        "date" => format!("{}.{}", header.date().epoch, header.date().slot_id)
+    );
+    debug!(logger, "Header: {:?}", header);
+    // Propagate the block to other nodes
+    network_msg_box
+        .try_send(NetworkMsg::Propagate(PropagateMsg::Block(header)))
+        .unwrap_or_else(|err| error!(logger, "cannot propagate block to network: {}", err));
+}
+
+/// notify the subscribers of a branch switch and put the fragments of
+/// the blocks rolled back in the fragment pool, so they can be included
+/// in the new branch
+fn handle_rolled_back(
+    info: &TokioServiceInfo,
+    logger: &Logger,
+    fragment_pool: &mut Pool,
+    reorgs: &ReorgNotifier,
+    new_tip: &Header,
+    rolled_back: Vec<Block>,
+) {
+    match ReorgEvent::new(new_tip.id(), &rolled_back) {
+        Some(event) => reorgs.notify(event),
+        None => return,
+    }
+    info!(
+        logger,
+        "switched branch, {} blocks rolled back",
+        rolled_back.len()
+    );
+    let fragments = rolled_back
+        .iter()
+        .rev()
+        .flat_map(|block| block.messages().cloned())
+        .collect();
+    let logger = logger.clone();
+    info.spawn(fragment_pool.requeue(fragments).map(move |count| {
+        debug!(logger, "{} fragments of rolled back blocks requeued", count);
+    }));
+}
+
+/// drop the fragments of the pool expired at the date of the block just
+/// applied, they cannot be included in the next blocks
+fn flush_expired(
+    info: &TokioServiceInfo,
+    logger: &Logger,
+    fragment_pool: &mut Pool,
+    new_block: &Header,
+) {
+    let logger = logger.clone();
+    info.spawn(
+        fragment_pool
+            .flush_expired(new_block.date())
+            .map(move |count| {
+                if count > 0 {
+                    debug!(logger, "{} expired fragments removed from the pool", count);
+                }
+            }),
+    );
+}
+
+/// try again to apply the orphan blocks, now that a new block has been
+/// added to the blockchain.
+///
+/// The retries are repeated as long as some of the orphans are acquired,
+/// as they may be the parents of the other orphans. The acquired orphans
+/// are added to `applied`.
+fn retry_orphans(
+    info: &TokioServiceInfo,
+    logger: &Logger,
+    blockchain: &mut Blockchain,
+    state: &mut BlockTaskState,
+    applied: &mut Vec<Block>,
+) {
+    let mut acquired_any = true;
+
+    while acquired_any && !state.orphans.is_empty() {
+        acquired_any = false;
+        for entry in state.orphans.drain() {
+            let block = entry.block.clone();
+            match chain::handle_block(blockchain, block, true).unwrap() {
+                HandledBlock::Rejected { reason } => {
+                    warn!(
+                        logger,
+                        "rejecting orphan block from the network: {:?}", reason
+                    );
+                    state.stats_counter.add_network_block_rejected();
+                }
+                HandledBlock::MissingBranchToBlock { .. } => {
+                    if let Some(dropped) = state.orphans.requeue(entry) {
+                        info!(logger, "dropping orphan block, its parent was not received in time";
+                            "id" => dropped.header.id().to_string()
+                        );
+                    }
+                }
+                HandledBlock::Acquired {
+                    header,
+                    rolled_back,
+                } => {
+                    acquired_any = true;
+                    state.stats_counter.add_network_block_applied();
+                    handle_rolled_back(
+                        info,
+                        logger,
+                        &mut state.fragment_pool,
+                        &state.reorgs,
+                        &header,
+                        rolled_back,
+                    );
+                    flush_expired(info, logger, &mut state.fragment_pool, &header);
+                    network_block_acquired(logger, &mut state.network_msg_box, header);
+                    applied.push(entry.block);
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,67 @@
+use crate::blockcfg::BlockDate;
+use std::collections::VecDeque;
+
+/// Bounded record of the slots of the latest blocks created by the node.
+///
+/// The node is never the leader of a slot twice, so a second block for
+/// the same slot is a bug of the leadership schedule: announcing it would
+/// be announcing conflicting blocks for the slot.
+///
+/// The record is kept in memory only: the storage does not tell the blocks
+/// created by the node from the other ones, so the record starts empty and
+/// a block created for a slot before the node restarted is not known to it.
+pub struct ProducedSlots {
+    dates: VecDeque<BlockDate>,
+    capacity: usize,
+}
+
+impl ProducedSlots {
+    pub fn new(capacity: usize) -> Self {
+        ProducedSlots {
+            dates: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn contains(&self, date: &BlockDate) -> bool {
+        self.dates.contains(date)
+    }
+
+    /// record the slot of a block created by the node, the oldest slot
+    /// recorded is forgotten if the record is full
+    pub fn insert(&mut self, date: BlockDate) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.dates.len() >= self.capacity {
+            self.dates.pop_front();
+        }
+        self.dates.push_back(date);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(slot_id: u32) -> BlockDate {
+        BlockDate { epoch: 0, slot_id }
+    }
+
+    #[test]
+    fn oldest_slots_are_forgotten() {
+        let mut produced = ProducedSlots::new(2);
+        produced.insert(date(1));
+        produced.insert(date(2));
+        assert!(produced.contains(&date(1)));
+
+        produced.insert(date(3));
+        assert!(!produced.contains(&date(1)));
+        assert!(produced.contains(&date(2)));
+        assert!(produced.contains(&date(3)));
+
+        let mut disabled = ProducedSlots::new(0);
+        disabled.insert(date(1));
+        assert!(!disabled.contains(&date(1)));
+    }
+}
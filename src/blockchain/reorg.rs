@@ -0,0 +1,65 @@
+use crate::blockcfg::{Block, HeaderHash};
+use chain_core::property::Block as _;
+use futures::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+/// The blockchain tip has moved to another branch.
+#[derive(Clone, Debug)]
+pub struct ReorgEvent {
+    /// the tip before the switch
+    pub from_tip: HeaderHash,
+    /// the new tip
+    pub to_tip: HeaderHash,
+    /// the last block shared by both branches
+    pub common_ancestor: HeaderHash,
+    /// the blocks of the previous branch no longer in the chain,
+    /// most recent first
+    pub rolled_back_blocks: Vec<HeaderHash>,
+}
+
+impl ReorgEvent {
+    /// `rolled_back` are the blocks of the previous branch, most recent
+    /// first, as listed in `HandledBlock::Acquired`. Returns `None` if
+    /// there is no block rolled back.
+    pub fn new(to_tip: HeaderHash, rolled_back: &[Block]) -> Option<Self> {
+        let from_tip = rolled_back.first()?.id();
+        let common_ancestor = rolled_back.last()?.parent_id();
+        Some(ReorgEvent {
+            from_tip,
+            to_tip,
+            common_ancestor,
+            rolled_back_blocks: rolled_back.iter().map(|block| block.id()).collect(),
+        })
+    }
+}
+
+/// Dispatches the reorg events to all the subscribers.
+///
+/// The events are buffered without bound for each subscriber, which is
+/// expected to keep up as reorgs are rare.
+#[derive(Clone, Default)]
+pub struct ReorgNotifier {
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<ReorgEvent>>>>,
+}
+
+impl ReorgNotifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// get a stream of the reorg events happening from now on
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<ReorgEvent> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// send the event to the subscribers, the subscribers whose stream
+    /// has been dropped are removed
+    pub fn notify(&self, event: ReorgEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+}
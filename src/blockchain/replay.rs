@@ -0,0 +1,242 @@
+//! replay of a sequence of block messages through `handle_input`, for the
+//! tests of the processing of the blocks, only available with the
+//! `testing` feature.
+//!
+//! The blocks received from the network are validated in the block task,
+//! with no validation worker, so the outcome only depends on the order
+//! of the messages.
+
+use crate::blockcfg::{Block, HeaderHash};
+use crate::blockchain::{
+    process, validation_pool, BlockSource, BlockTaskState, BlockchainR, OrphanQueue, ProducedSlots,
+    ReorgNotifier, ShutdownState,
+};
+use crate::fragment::Pool;
+use crate::intercom::{BlockMsg, NetworkMsg};
+use crate::rest::v0::node::stats::StatsCounter;
+use crate::utils::{
+    async_msg::{self, MessageQueue},
+    task::{Input, TokioServiceInfo},
+};
+
+use chain_core::property::Header as _;
+use futures::{future, Async, Future, Stream};
+use std::cell::RefCell;
+
+// room for the messages sent to the network task while handling a single
+// block message, the queue is drained after each of them
+const NETWORK_QUEUE_LEN: usize = 64;
+const ORPHAN_QUEUE_LEN: usize = 16;
+const ORPHAN_MAX_RETRIES: usize = 3;
+const PRODUCED_SLOTS_LEN: usize = 64;
+
+/// what has become of a block of the replayed messages
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockOutcome {
+    /// the block has been applied to the blockchain
+    Applied,
+    /// the parent of the block is not known, the block is kept in the
+    /// orphan queue for its parent to arrive
+    Pending,
+    /// the block has been rejected or dropped
+    Rejected,
+}
+
+/// what the block task has done with the replayed messages
+#[derive(Default)]
+pub struct ReplayOutcome {
+    /// the messages sent to the network task, in the order they were sent
+    pub network_msgs: Vec<NetworkMsg>,
+    /// the blocks applied to the blockchain, in the order they were applied
+    pub applied: Vec<(HeaderHash, BlockSource)>,
+    /// the blocks of the `NetworkBlock` and `LeadershipBlock` messages, in
+    /// the order of the messages, with what has become of them once all
+    /// the messages have been handled
+    pub blocks: Vec<(HeaderHash, BlockOutcome)>,
+    /// the statistics of the block task, e.g. the number of rejected
+    /// network blocks
+    pub stats_counter: StatsCounter,
+}
+
+/// Feeds the messages, in order, to `handle_input` against the given
+/// blockchain and records the messages sent to the network task, the
+/// blocks applied and the outcome of each block of the messages.
+pub fn replay(
+    info: &TokioServiceInfo,
+    blockchain: &BlockchainR,
+    fragment_pool: &Pool,
+    msgs: Vec<BlockMsg>,
+) -> ReplayOutcome {
+    let (network_msg_box, mut network_queue) = async_msg::channel(NETWORK_QUEUE_LEN);
+    let (validation, _workers) = validation_pool(0, 0);
+    let mut state = BlockTaskState {
+        stats_counter: StatsCounter::default(),
+        network_msg_box,
+        fragment_pool: fragment_pool.clone(),
+        reorgs: ReorgNotifier::new(),
+        orphans: OrphanQueue::new(ORPHAN_QUEUE_LEN, ORPHAN_MAX_RETRIES),
+        validation,
+        produced: ProducedSlots::new(PRODUCED_SLOTS_LEN),
+        confirm_readable: true,
+        shutdown: ShutdownState::Running,
+    };
+
+    let applied = RefCell::new(Vec::new());
+    let on_block_applied =
+        |block: &Block, source: BlockSource| applied.borrow_mut().push((block.header.id(), source));
+    let mut network_msgs = Vec::new();
+    let mut blocks = Vec::new();
+
+    for msg in msgs {
+        match &msg {
+            BlockMsg::NetworkBlock(block) | BlockMsg::LeadershipBlock(block) => {
+                blocks.push(block.header.id())
+            }
+            _ => (),
+        }
+        process::handle_input(
+            info,
+            blockchain,
+            &mut state,
+            Some(&on_block_applied),
+            Input::Input(msg),
+        );
+        network_msgs.extend(drain(&mut network_queue));
+    }
+
+    let applied = applied.into_inner();
+    let blocks = blocks
+        .into_iter()
+        .map(|id| {
+            let outcome = if applied.iter().any(|(applied_id, _)| applied_id == &id) {
+                BlockOutcome::Applied
+            } else if state.orphans.contains(&id) {
+                BlockOutcome::Pending
+            } else {
+                BlockOutcome::Rejected
+            };
+            (id, outcome)
+        })
+        .collect();
+
+    ReplayOutcome {
+        network_msgs,
+        applied,
+        blocks,
+        stats_counter: state.stats_counter,
+    }
+}
+
+// the messages in the queue, without waiting for more
+fn drain(queue: &mut MessageQueue<NetworkMsg>) -> Vec<NetworkMsg> {
+    future::lazy(|| {
+        let mut msgs = Vec::new();
+        while let Ok(Async::Ready(Some(msg))) = queue.poll() {
+            msgs.push(msg);
+        }
+        future::ok::<_, ()>(msgs)
+    })
+    .wait()
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockcfg::{
+        Block0Date, BlockBuilder, BlockDate, ConfigParam, ConfigParams, ConsensusVersion, Message,
+    };
+    use crate::fragment::Logs;
+    use crate::start_up;
+    use crate::utils::task::TokioServiceInfo;
+    use chain_addr::Discrimination;
+    use chain_core::property::Header as _;
+    use chain_crypto::{Ed25519Extended, SecretKey};
+    use chain_impl_mockchain::milli::Milli;
+    use chain_storage::memory::MemoryBlockStore;
+    use slog::Logger;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use tokio::{runtime::Runtime, sync::mpsc};
+
+    fn block0(leader: &SecretKey<Ed25519Extended>) -> Block {
+        let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let mut ents = ConfigParams::new();
+        ents.push(ConfigParam::Block0Date(Block0Date(start.as_secs())));
+        ents.push(ConfigParam::Discrimination(Discrimination::Test));
+        ents.push(ConfigParam::ConsensusVersion(ConsensusVersion::Bft));
+        ents.push(ConfigParam::SlotsPerEpoch(10));
+        ents.push(ConfigParam::SlotDuration(10));
+        ents.push(ConfigParam::AddBftLeader(leader.to_public().into()));
+        ents.push(ConfigParam::ConsensusGenesisPraosActiveSlotsCoeff(
+            Milli::from_millis(500),
+        ));
+        ents.push(ConfigParam::KESUpdateSpeed(43200));
+
+        let mut builder = BlockBuilder::new();
+        builder.message(Message::Initial(ents));
+        builder.make_genesis_block()
+    }
+
+    fn first_block(block0: &Block, signer: &SecretKey<Ed25519Extended>) -> Block {
+        let mut builder = BlockBuilder::new();
+        builder
+            .date(BlockDate {
+                epoch: 0,
+                slot_id: 1,
+            })
+            .parent(block0.header.id())
+            .chain_length(block0.header.chain_length().next());
+        builder.make_bft_block(signer)
+    }
+
+    #[test]
+    fn invalid_blocks_are_reported_rejected() {
+        let leader = SecretKey::<Ed25519Extended>::generate(rand::thread_rng());
+        let impostor = SecretKey::<Ed25519Extended>::generate(rand::thread_rng());
+        let block0 = block0(&leader);
+        let valid = first_block(&block0, &leader);
+        let valid_id = valid.header.id();
+        let invalid = first_block(&block0, &impostor);
+        let invalid_id = invalid.header.id();
+
+        let runtime = Runtime::new().unwrap();
+        let logger = Logger::root(slog::Discard, o!());
+        let info = TokioServiceInfo::for_tests("block", logger.clone(), runtime.executor());
+        let (epoch_event, _epochs) = mpsc::channel(4);
+        let blockchain = start_up::load_blockchain(
+            block0,
+            Box::new(MemoryBlockStore::new()),
+            epoch_event,
+            &logger,
+        )
+        .unwrap();
+        let fragment_pool = Pool::new(
+            Duration::from_secs(3600),
+            None,
+            Logs::new(Duration::from_secs(3600)),
+        );
+
+        let outcome = runtime
+            .block_on(future::lazy(move || {
+                future::ok::<_, ()>(replay(
+                    &info,
+                    &blockchain,
+                    &fragment_pool,
+                    vec![
+                        BlockMsg::NetworkBlock(invalid),
+                        BlockMsg::NetworkBlock(valid),
+                    ],
+                ))
+            }))
+            .unwrap();
+
+        assert_eq!(
+            outcome.blocks,
+            vec![
+                (invalid_id.clone(), BlockOutcome::Rejected),
+                (valid_id.clone(), BlockOutcome::Applied),
+            ]
+        );
+        assert_eq!(outcome.applied, vec![(valid_id, BlockSource::Network)]);
+    }
+}
@@ -0,0 +1,211 @@
+//! validation of the blocks received from the network on a bounded
+//! pool of worker threads.
+//!
+//! The workers take the blocks from a shared queue and verify their
+//! headers under a read lock of the blockchain, while the application
+//! of the blocks to the ledger state stays serialized in the block
+//! task. The validated blocks are sent back to the block task, which
+//! applies them in the order they were submitted, so a parent is applied
+//! before its children.
+//!
+//! A header is only verified ahead if the parent of the block is applied
+//! by the time a worker takes it, see `chain::pre_validate`. The children
+//! of a block still in the pool are fully verified by the block task.
+
+use crate::blockcfg::Block;
+use crate::blockchain::chain::{self, BlockchainR, PreValidation};
+use crate::intercom::BlockMsg;
+use crate::rest::v0::node::stats::StatsCounter;
+use crate::utils::async_msg::MessageBox;
+
+use slog::Logger;
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = (u64, Block);
+
+/// the submitting end of the validation pool, owned by the block task
+pub struct ValidationPool {
+    jobs: Option<SyncSender<Job>>,
+    next_seq: u64,
+    // the validated blocks waiting for the blocks submitted before them
+    reorder: Reorder,
+}
+
+/// the worker threads of a validation pool, to start once the message
+/// box of the block task is known
+pub struct ValidationWorkers {
+    concurrency: usize,
+    jobs: Receiver<Job>,
+}
+
+/// a block ready to be applied by the block task, along with whether
+/// its header has been verified already
+pub type Validated = (Block, bool);
+
+struct Reorder {
+    next_seq: u64,
+    completed: BTreeMap<u64, Option<Validated>>,
+}
+
+/// create a validation pool of `concurrency` workers, with up to
+/// `queue_len` blocks waiting for a worker.
+///
+/// With no worker, the blocks are not taken by the pool and are to be
+/// validated by the block task itself.
+pub fn validation_pool(
+    concurrency: usize,
+    queue_len: usize,
+) -> (ValidationPool, ValidationWorkers) {
+    let (sender, receiver) = mpsc::sync_channel(queue_len);
+    let pool = ValidationPool {
+        jobs: if concurrency > 0 { Some(sender) } else { None },
+        next_seq: 0,
+        reorder: Reorder {
+            next_seq: 0,
+            completed: BTreeMap::new(),
+        },
+    };
+    let workers = ValidationWorkers {
+        concurrency,
+        jobs: receiver,
+    };
+    (pool, workers)
+}
+
+impl ValidationPool {
+    /// submit a block received from the network for validation, returns
+    /// the blocks to apply now, in order.
+    ///
+    /// With no worker, the block is returned right away to be validated
+    /// by the caller. If the queue is full, the block is not validated
+    /// ahead: it is returned, to be validated by the caller, once the
+    /// blocks submitted before it have completed.
+    pub fn submit(&mut self, block: Block) -> Vec<Validated> {
+        let jobs = match &self.jobs {
+            Some(jobs) => jobs,
+            None => return vec![(block, false)],
+        };
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        match jobs.try_send((seq, block)) {
+            Ok(()) => Vec::new(),
+            Err(TrySendError::Full((_, block))) => self.reorder.complete(seq, Some((block, false))),
+            Err(TrySendError::Disconnected((_, block))) => {
+                // the workers are gone, the blocks they had taken
+                // are lost
+                self.jobs = None;
+                let mut ready = self.reorder.drain();
+                ready.push((block, false));
+                ready
+            }
+        }
+    }
+
+    /// record the outcome of the validation of the block submitted with
+    /// `seq`, `None` if the block has been rejected, and return the blocks
+    /// to apply now, in order
+    pub fn complete(&mut self, seq: u64, validated: Option<Validated>) -> Vec<Validated> {
+        self.reorder.complete(seq, validated)
+    }
+
+    /// all the blocks submitted have been returned to the block task
+    pub fn is_idle(&self) -> bool {
+        self.jobs.is_none() || self.reorder.next_seq == self.next_seq
+    }
+}
+
+impl ValidationWorkers {
+    /// start the worker threads, the validated blocks are sent to the
+    /// block task with `BlockMsg::ValidatedNetworkBlock`
+    pub fn start(
+        self,
+        blockchain: BlockchainR,
+        stats_counter: StatsCounter,
+        block_box: MessageBox<BlockMsg>,
+        logger: Logger,
+    ) {
+        let jobs = Arc::new(Mutex::new(self.jobs));
+
+        for index in 0..self.concurrency {
+            let jobs = jobs.clone();
+            let block_box = block_box.clone();
+            let blockchain = blockchain.clone();
+            let stats_counter = stats_counter.clone();
+            let logger = logger.new(o!("validation_worker" => index));
+            thread::Builder::new()
+                .name(format!("block-validation-{}", index))
+                .spawn(move || run_worker(jobs, block_box, blockchain, stats_counter, logger))
+                .unwrap();
+        }
+    }
+}
+
+fn run_worker(
+    jobs: Arc<Mutex<Receiver<Job>>>,
+    mut block_box: MessageBox<BlockMsg>,
+    blockchain: BlockchainR,
+    stats_counter: StatsCounter,
+    logger: Logger,
+) {
+    loop {
+        // the lock is released before the validation, so the other
+        // workers can take the next blocks
+        let job = jobs.lock().unwrap().recv();
+        let (seq, block) = match job {
+            Ok(job) => job,
+            // the block task is gone
+            Err(_) => return,
+        };
+
+        let pre_validation = chain::pre_validate(&blockchain.lock_read(), &block.header);
+        let validated = match pre_validation {
+            Ok(PreValidation::Verified) => Some((block, true)),
+            Ok(PreValidation::Rejected { reason }) => {
+                // TODO: drop the network peer that has sent
+                // an invalid block.
+                warn!(logger, "rejecting block from the network: {:?}", reason);
+                stats_counter.add_network_block_rejected();
+                None
+            }
+            // the block task does the whole validation
+            Ok(PreValidation::Deferred) | Err(_) => Some((block, false)),
+        };
+
+        // waits for the block task to have room for the message,
+        // the block rejected are sent as well to keep the order
+        let msg = BlockMsg::ValidatedNetworkBlock { seq, validated };
+        if let Err(err) = block_box.send_blocking(msg) {
+            error!(logger, "cannot send validated block: {}", err);
+        }
+    }
+}
+
+impl Reorder {
+    // record the outcome of the validation of the block submitted with
+    // `seq` and return all the blocks that are now in order
+    fn complete(&mut self, seq: u64, validated: Option<Validated>) -> Vec<Validated> {
+        self.completed.insert(seq, validated);
+        let mut ready = Vec::new();
+        while let Some(validated) = self.completed.remove(&self.next_seq) {
+            self.next_seq += 1;
+            ready.extend(validated);
+        }
+        ready
+    }
+
+    // the blocks completed, in order, whether or not the blocks
+    // submitted before them have completed
+    fn drain(&mut self) -> Vec<Validated> {
+        let completed = std::mem::replace(&mut self.completed, BTreeMap::new());
+        if let Some(last) = completed.keys().next_back() {
+            self.next_seq = last + 1;
+        }
+        completed
+            .into_iter()
+            .filter_map(|(_, validated)| validated)
+            .collect()
+    }
+}
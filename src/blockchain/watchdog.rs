@@ -0,0 +1,43 @@
+use crate::rest::v0::node::stats::StatsCounter;
+
+use futures::prelude::*;
+use slog::Logger;
+use std::time::{Duration, Instant};
+use tokio::timer::Interval;
+
+// Interval between two checks of the time elapsed since the last block.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Watches the time elapsed since a block was last applied to the
+/// blockchain, as recorded in the stats counter.
+///
+/// When it exceeds `threshold`, the tip is flagged as stalled in the
+/// stats counter and a warning is logged: the node is likely isolated
+/// from the network or stuck syncing. The flag is cleared as soon as
+/// blocks are applied again.
+pub fn watch_tip(
+    stats_counter: StatsCounter,
+    threshold: Duration,
+    logger: Logger,
+) -> impl Future<Item = (), Error = ()> {
+    let err_logger = logger.clone();
+    Interval::new(Instant::now() + CHECK_INTERVAL, CHECK_INTERVAL)
+        .map_err(move |e| {
+            error!(err_logger, "stale tip watchdog timer error: {}", e);
+        })
+        .for_each(move |_| {
+            let elapsed = stats_counter.get_time_since_last_block();
+            let stalled = elapsed > threshold;
+            let was_stalled = stats_counter.set_tip_stalled(stalled);
+            if stalled && !was_stalled {
+                warn!(
+                    logger,
+                    "no block added to the blockchain for {}s, the node may be isolated or stuck syncing",
+                    elapsed.as_secs()
+                );
+            } else if !stalled && was_stalled {
+                info!(logger, "blocks are added to the blockchain again");
+            }
+            Ok(())
+        })
+}
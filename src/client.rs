@@ -4,9 +4,37 @@ use crate::intercom::{do_stream_reply, ClientMsg, Error, ReplyStreamHandle};
 use crate::utils::task::{Input, ThreadServiceInfo};
 use chain_core::property::{Block as _, HasHeader as _};
 
-pub fn handle_input(_info: &ThreadServiceInfo, blockchain: &BlockchainR, input: Input<ClientMsg>) {
+/// The requests for up to this number of blocks, such as the
+/// solicitations of the peers following the tip, are served ahead of
+/// the larger requests of the peers synchronizing.
+const SMALL_REQUEST_MAX_BLOCKS: usize = 8;
+
+/// The number of blocks of a large request served at once, the waiting
+/// requests are served before the next blocks.
+const BULK_CHUNK_LEN: usize = 64;
+
+/// whether the request is served ahead of the requests for many blocks,
+/// see `Services::spawn_with_prioritized_inputs`
+pub fn is_urgent(msg: &ClientMsg) -> bool {
+    match msg {
+        ClientMsg::GetBlocks(ids, _) => ids.len() <= SMALL_REQUEST_MAX_BLOCKS,
+        ClientMsg::GetBlocksRange(..) | ClientMsg::PullBlocksToTip(..) => false,
+        ClientMsg::GetBlockTip(_) | ClientMsg::GetHeaders(..) | ClientMsg::GetHeadersRange(..) => {
+            true
+        }
+    }
+}
+
+/// handle a request, the blocks of a large request are served in chunks:
+/// the rest of the request is given back to be served after the requests
+/// waiting meanwhile.
+pub fn handle_input(
+    _info: &ThreadServiceInfo,
+    blockchain: &BlockchainR,
+    input: Input<ClientMsg>,
+) -> Option<ClientMsg> {
     let cquery = match input {
-        Input::Shutdown => return,
+        Input::Shutdown => return None,
         Input::Input(msg) => msg,
     };
 
@@ -18,15 +46,48 @@ pub fn handle_input(_info: &ThreadServiceInfo, blockchain: &BlockchainR, input:
         ClientMsg::GetHeadersRange(checkpoints, to, handler) => {
             handler.reply(handle_get_headers_range(&blockchain, checkpoints, to))
         }
-        ClientMsg::GetBlocks(ids, handler) => do_stream_reply(handler, |handler| {
-            handle_get_blocks(&blockchain, ids, handler)
-        }),
-        ClientMsg::GetBlocksRange(from, to, handler) => do_stream_reply(handler, |handler| {
-            handle_get_blocks_range(&blockchain, from, to, handler)
-        }),
-        ClientMsg::PullBlocksToTip(from, handler) => do_stream_reply(handler, |handler| {
-            handle_pull_blocks_to_tip(&blockchain, from, handler)
-        }),
+        ClientMsg::GetBlocks(ids, handler) => return serve_blocks(&blockchain, ids, handler),
+        ClientMsg::GetBlocksRange(from, to, handler) => {
+            match block_range_ids(&blockchain, from, to) {
+                Ok(ids) => return serve_blocks(&blockchain, ids, handler),
+                Err(e) => fail_stream_reply(handler, e),
+            }
+        }
+        ClientMsg::PullBlocksToTip(from, handler) => match blocks_to_tip_ids(&blockchain, from) {
+            Ok(ids) => return serve_blocks(&blockchain, ids, handler),
+            Err(e) => fail_stream_reply(handler, e),
+        },
+    }
+    None
+}
+
+fn fail_stream_reply<T>(mut handler: ReplyStreamHandle<T>, error: Error) {
+    handler.send_error(error);
+    handler.close();
+}
+
+/// serve the first chunk of the blocks, the request for the rest of the
+/// blocks is given back
+fn serve_blocks(
+    blockchain: &BlockchainR,
+    mut ids: Vec<HeaderHash>,
+    mut handler: ReplyStreamHandle<Block>,
+) -> Option<ClientMsg> {
+    let rest = if ids.len() > BULK_CHUNK_LEN {
+        ids.split_off(BULK_CHUNK_LEN)
+    } else {
+        Vec::new()
+    };
+    match handle_get_blocks(blockchain, ids, &mut handler) {
+        Ok(()) if !rest.is_empty() => Some(ClientMsg::GetBlocks(rest, handler)),
+        Ok(()) => {
+            handler.close();
+            None
+        }
+        Err(e) => {
+            fail_stream_reply(handler, e);
+            None
+        }
     }
 }
 
@@ -101,31 +162,25 @@ fn handle_get_headers_range(
     }
 }
 
-fn handle_get_blocks_range(
+fn block_range_ids(
     blockchain: &BlockchainR,
     from: HeaderHash,
     to: HeaderHash,
-    reply: &mut ReplyStreamHandle<Block>,
-) -> Result<(), Error> {
+) -> Result<Vec<HeaderHash>, Error> {
     let blockchain = blockchain.lock_read();
 
     // FIXME: include the from block
+    let mut ids = Vec::new();
     for x in blockchain
         .storage
         .read()
         .unwrap()
         .iterate_range(&from, &to)?
     {
-        let info = x?;
-        let (blk, _) = blockchain
-            .storage
-            .read()
-            .unwrap()
-            .get_block(&info.block_hash)?;
-        reply.send(blk);
+        ids.push(x?.block_hash);
     }
 
-    Ok(())
+    Ok(ids)
 }
 
 fn handle_get_blocks(
@@ -158,13 +213,10 @@ fn handle_get_headers(
     Ok(())
 }
 
-fn handle_pull_blocks_to_tip(
+fn blocks_to_tip_ids(
     blockchain: &BlockchainR,
     mut from: Vec<HeaderHash>,
-    reply: &mut ReplyStreamHandle<Block>,
-) -> Result<(), Error> {
-    let blockchain = blockchain.lock_read();
-
+) -> Result<Vec<HeaderHash>, Error> {
     // FIXME: handle multiple from addresses
     if from.len() != 1 {
         return Err(Error::unimplemented(
@@ -173,22 +225,7 @@ fn handle_pull_blocks_to_tip(
     }
     let from = from.remove(0);
 
-    let tip = blockchain.get_tip().unwrap();
+    let tip = blockchain.lock_read().get_tip().unwrap();
 
-    for x in blockchain
-        .storage
-        .read()
-        .unwrap()
-        .iterate_range(&from, &tip)?
-    {
-        let info = x?;
-        let (blk, _) = blockchain
-            .storage
-            .read()
-            .unwrap()
-            .get_block(&info.block_hash)?;
-        reply.send(blk);
-    }
-
-    Ok(())
+    block_range_ids(blockchain, from, tip)
 }
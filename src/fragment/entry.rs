@@ -1,9 +1,66 @@
 use crate::{
-    blockcfg::{Value, ValueError},
+    blockcfg::{BlockDate, Value, ValueError},
     fragment::{Fragment, FragmentId},
 };
+use chain_impl_mockchain::certificate::CertificateContent;
 use std::time::SystemTime;
 
+/// the kind of a fragment, as reported in the pool statistics
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FragmentType {
+    Initial,
+    OldUtxoDeclaration,
+    Transaction,
+    StakeDelegation,
+    StakeKeyRegistration,
+    StakeKeyDeregistration,
+    StakePoolRegistration,
+    StakePoolRetirement,
+    /// update proposals and votes, and any other kind of fragment
+    Other,
+}
+
+impl FragmentType {
+    pub fn of(fragment: &Fragment) -> Self {
+        match fragment {
+            Fragment::Initial(_) => FragmentType::Initial,
+            Fragment::OldUtxoDeclaration(_) => FragmentType::OldUtxoDeclaration,
+            Fragment::Transaction(_) => FragmentType::Transaction,
+            Fragment::Certificate(auth) => match auth.transaction.extra.content {
+                CertificateContent::StakeDelegation(_) => FragmentType::StakeDelegation,
+                CertificateContent::StakeKeyRegistration(_) => FragmentType::StakeKeyRegistration,
+                CertificateContent::StakeKeyDeregistration(_) => {
+                    FragmentType::StakeKeyDeregistration
+                }
+                CertificateContent::StakePoolRegistration(_) => FragmentType::StakePoolRegistration,
+                CertificateContent::StakePoolRetirement(_) => FragmentType::StakePoolRetirement,
+            },
+            _ => FragmentType::Other,
+        }
+    }
+}
+
+pub(super) fn fragment_fee(fragment: &Fragment) -> Value {
+    fn balance(input: Result<Value, ValueError>, output: Result<Value, ValueError>) -> Value {
+        match (input, output) {
+            (Ok(input), Ok(output)) => Value(input.0.saturating_sub(output.0)),
+            _ => Value::zero(),
+        }
+    }
+
+    match fragment {
+        Fragment::Transaction(auth) => balance(
+            auth.transaction.total_input(),
+            auth.transaction.total_output(),
+        ),
+        Fragment::Certificate(auth) => balance(
+            auth.transaction.total_input(),
+            auth.transaction.total_output(),
+        ),
+        _ => Value::zero(),
+    }
+}
+
 pub struct PoolEntry {
     // reference of the fragment stored in the pool
     fragment_ref: FragmentId,
@@ -12,8 +69,15 @@ pub struct PoolEntry {
     fragment_fee: Value,
     /// size of the fragment in the memory pool
     fragment_size: usize,
-    /// time when the entry was added to the pool
+    /// the kind of the fragment
+    fragment_type: FragmentType,
+    /// time when the fragment was first seen by the node, which is earlier
+    /// than the time the entry was added to the pool if the fragment has
+    /// been requeued after a rollback
     received_at: SystemTime,
+    /// last block date at which the fragment can be included in a block,
+    /// if the fragment has a limited validity window
+    valid_until: Option<BlockDate>,
     /// the fee of the accumulated descendant fragments
     /// does not include the fee of this entry
     descendants_fee: Value,
@@ -30,20 +94,27 @@ pub struct PoolEntry {
 
 impl PoolEntry {
     pub fn new(fragment: &Fragment) -> Self {
+        Self::with_received_at(fragment, SystemTime::now())
+    }
+
+    pub fn with_received_at(fragment: &Fragment, received_at: SystemTime) -> Self {
         let raw = fragment.to_raw();
         let fragment_size = raw.size_bytes_plus_size();
         let fragment_ref = raw.id();
-        // TODO: the fragment fee is not yet computed. Yet we should
-        // have an explicit fee in the message. So we need to be able
-        // to extract this information without the need to compute the
-        // fee from the ledger's fee settings.
-        let fragment_fee = Value::zero();
+        // TODO: we should have an explicit fee in the message. In the
+        // meantime the fee is what the inputs leave after the outputs.
+        let fragment_fee = fragment_fee(fragment);
 
         PoolEntry {
             fragment_ref: fragment_ref,
             fragment_fee: fragment_fee,
             fragment_size: fragment_size,
-            received_at: SystemTime::now(),
+            fragment_type: FragmentType::of(fragment),
+            received_at,
+            // TODO: the fragments do not expose their validity window
+            // yet, so we cannot extract it from the fragment itself.
+            // It may be requested on submission, see `set_valid_until`.
+            valid_until: None,
 
             // when this entry is added in the pool, it has no
             // descendant
@@ -70,10 +141,31 @@ impl PoolEntry {
         &self.fragment_size
     }
     #[inline]
+    pub fn fragment_type(&self) -> FragmentType {
+        self.fragment_type
+    }
+    #[inline]
     pub fn received_at(&self) -> &SystemTime {
         &self.received_at
     }
     #[inline]
+    pub fn valid_until(&self) -> Option<&BlockDate> {
+        self.valid_until.as_ref()
+    }
+    /// set the last block date at which the fragment can be included
+    /// in a block, as requested when the fragment was submitted
+    #[inline]
+    pub fn set_valid_until(&mut self, valid_until: BlockDate) {
+        self.valid_until = Some(valid_until);
+    }
+    /// the fragment cannot be included in a block at the given date
+    #[inline]
+    pub fn is_expired_at(&self, date: &BlockDate) -> bool {
+        self.valid_until.as_ref().map_or(false, |valid_until| {
+            (valid_until.epoch, valid_until.slot_id) < (date.epoch, date.slot_id)
+        })
+    }
+    #[inline]
     pub fn with_descendants_fee(&self) -> Result<Value, ValueError> {
         self.descendants_fee + self.fragment_fee
     }
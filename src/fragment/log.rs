@@ -1,4 +1,5 @@
 use crate::{blockcfg::BlockDate, fragment::FragmentId};
+use chain_impl_mockchain::ledger;
 use jormungandr_utils::serde;
 use serde::Serialize;
 use std::time::SystemTime;
@@ -17,6 +18,74 @@ pub enum Origin {
     /// This marks the fragment is coming from the REST interface
     /// (a client wallet or another service).
     Rest,
+    /// The fragment has been imported from the pool of another node,
+    /// see `Pool::import`.
+    Import,
+}
+
+/// the reason a fragment has been rejected, for the clients to act
+/// upon programmatically.
+///
+/// The codes are part of the public API, new codes may be added but
+/// existing ones are not changed.
+#[derive(Copy, Clone, Serialize, Debug, PartialEq, Eq, Hash)]
+pub enum RejectionCode {
+    /// a witness of the fragment does not match its input
+    InvalidSignature,
+    /// the inputs do not cover the outputs and fees, or an account does
+    /// not have the funds to spend
+    InsufficientFunds,
+    /// an input is not available for spending anymore
+    DoubleSpend,
+    /// the fragment validity window has passed
+    Expired,
+    /// the fragment pays less than the minimum fee set by the node
+    /// operator, a local policy of the node and not a consensus rule
+    BelowMinimumFee,
+    /// the fragment is refused by an acceptance predicate of the node
+    /// operator, a local policy of the node and not a consensus rule
+    RefusedByPolicy,
+    /// the transaction of the fragment has more inputs or outputs than
+    /// allowed by the node operator, a local policy of the node and not
+    /// a consensus rule
+    TooManyInputsOutputs,
+    /// the fragment has been evicted from the pool over the memory limit
+    /// set by the node operator, to make room for fragments paying higher
+    /// fees or more recent ones, a local policy of the node and not a
+    /// consensus rule
+    PoolFull,
+    /// any other reason, see the description of the rejection
+    Other,
+}
+
+impl RejectionCode {
+    pub fn from_ledger_error(error: &ledger::Error) -> Self {
+        match error {
+            ledger::Error::UtxoInvalidSignature { .. }
+            | ledger::Error::AccountInvalidSignature { .. } => RejectionCode::InvalidSignature,
+            ledger::Error::NotBalanced { .. } | ledger::Error::Account { .. } => {
+                RejectionCode::InsufficientFunds
+            }
+            ledger::Error::UtxoError { .. } => RejectionCode::DoubleSpend,
+            _ => RejectionCode::Other,
+        }
+    }
+
+    /// the value of the `reason` label of the metrics for the code, see
+    /// `fragment::metrics`. The labels are stable as the codes are.
+    pub fn metric_label(&self) -> &'static str {
+        match self {
+            RejectionCode::InvalidSignature => "invalid_signature",
+            RejectionCode::InsufficientFunds => "insufficient_funds",
+            RejectionCode::DoubleSpend => "double_spend",
+            RejectionCode::Expired => "expired",
+            RejectionCode::BelowMinimumFee => "below_minimum_fee",
+            RejectionCode::RefusedByPolicy => "refused_by_policy",
+            RejectionCode::TooManyInputsOutputs => "too_many_inputs_outputs",
+            RejectionCode::PoolFull => "pool_full",
+            RejectionCode::Other => "other",
+        }
+    }
 }
 
 /// status of the fragment within the blockchain or the pool
@@ -25,10 +94,36 @@ pub enum Status {
     /// the fragment is yet to be processed
     Pending,
     /// the fragment has been rejected and won't be added in a block
-    Rejected { reason: String },
+    Rejected { code: RejectionCode, reason: String },
     /// The fragment has been added in a block
     #[serde(with = "serde::as_string")]
     InABlock { date: BlockDate },
+    /// the validity window of the fragment has passed before it was
+    /// added in a block, it has been dropped from the pool
+    #[serde(with = "serde::as_string")]
+    Expired { valid_until: BlockDate },
+}
+
+impl Status {
+    /// the fragment has been rejected by the ledger
+    pub fn rejected_by_ledger(error: &ledger::Error) -> Self {
+        Status::Rejected {
+            code: RejectionCode::from_ledger_error(error),
+            reason: error.to_string(),
+        }
+    }
+}
+
+/// a fragment rejected recently, see `Logs::recent_rejections`
+#[derive(Clone, Debug)]
+pub struct RejectionRecord {
+    pub fragment_id: FragmentId,
+    pub code: RejectionCode,
+    pub reason: String,
+    /// the date of the block being prepared when the fragment was
+    /// rejected, if it was rejected while preparing a block
+    pub block_date: Option<BlockDate>,
+    pub rejected_at: SystemTime,
 }
 
 /// the log associated to a given fragment
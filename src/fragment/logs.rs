@@ -1,4 +1,4 @@
-use crate::fragment::{FragmentId, Log, Status};
+use crate::fragment::{FragmentId, Log, RejectionRecord, Status};
 use std::time::Duration;
 use tokio::{
     prelude::*,
@@ -9,6 +9,35 @@ use tokio::{
 #[derive(Clone)]
 pub struct Logs(Lock<internal::Logs>);
 
+/// number of fragment logs in each of the status
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatusCount {
+    pub pending: usize,
+    pub in_a_block: usize,
+    pub rejected: usize,
+    pub expired: usize,
+}
+
+impl StatusCount {
+    fn get_mut(&mut self, status: &Status) -> &mut usize {
+        match status {
+            Status::Pending => &mut self.pending,
+            Status::InABlock { .. } => &mut self.in_a_block,
+            Status::Rejected { .. } => &mut self.rejected,
+            Status::Expired { .. } => &mut self.expired,
+        }
+    }
+
+    fn increment(&mut self, status: &Status) {
+        *self.get_mut(status) += 1;
+    }
+
+    fn decrement(&mut self, status: &Status) {
+        let count = self.get_mut(status);
+        *count = count.saturating_sub(1);
+    }
+}
+
 impl Logs {
     pub fn new(ttl: Duration) -> Self {
         Logs(Lock::new(internal::Logs::new(ttl)))
@@ -43,6 +72,15 @@ impl Logs {
         })
     }
 
+    /// set the fragment back to pending, see `Pool::requeue`
+    pub fn requeue(&mut self, fragment_id: FragmentId) -> impl Future<Item = (), Error = ()> {
+        let mut lock = self.0.clone();
+        future::poll_fn(move || Ok(lock.poll_lock())).and_then(move |mut guard| {
+            guard.requeue(&fragment_id);
+            future::ok(())
+        })
+    }
+
     pub fn remove(&mut self, fragment_id: FragmentId) -> impl Future<Item = (), Error = ()> {
         let mut lock = self.0.clone();
         future::poll_fn(move || Ok(lock.poll_lock())).and_then(move |mut guard| {
@@ -63,16 +101,42 @@ impl Logs {
             .and_then(|guard| future::ok(guard.logs().cloned().collect()))
     }
 
+    /// the last `n` fragments rejected, most recent first, along with the
+    /// reason. Only a bounded number of rejections is retained, see
+    /// `MAX_RECENT_REJECTIONS`.
+    pub fn recent_rejections(
+        &self,
+        n: usize,
+    ) -> impl Future<Item = Vec<RejectionRecord>, Error = ()> {
+        let mut lock = self.0.clone();
+        future::poll_fn(move || Ok(lock.poll_lock()))
+            .and_then(move |guard| future::ok(guard.recent_rejections(n)))
+    }
+
+    /// number of logs in each of the status
+    pub fn status_count(&self) -> impl Future<Item = StatusCount, Error = ()> {
+        let mut lock = self.0.clone();
+        future::poll_fn(move || Ok(lock.poll_lock()))
+            .and_then(|guard| future::ok(guard.status_count()))
+    }
+
     pub(super) fn inner(&self) -> impl Future<Item = LockGuard<internal::Logs>, Error = ()> {
         let mut lock = self.0.clone();
         future::poll_fn(move || Ok(lock.poll_lock()))
     }
 }
 
+/// the number of recent rejections retained by the logs
+pub const MAX_RECENT_REJECTIONS: usize = 1024;
+
 pub(super) mod internal {
-    use crate::fragment::{FragmentId, Log, Status};
+    use super::{StatusCount, MAX_RECENT_REJECTIONS};
+    use crate::{
+        blockcfg::BlockDate,
+        fragment::{FragmentId, Log, Origin, RejectionCode, RejectionRecord, Status},
+    };
     use std::{
-        collections::HashMap,
+        collections::{HashMap, VecDeque},
         time::{Duration, Instant, SystemTime},
     };
     use tokio::{
@@ -84,6 +148,11 @@ pub(super) mod internal {
         entries: HashMap<FragmentId, (Log, delay_queue::Key)>,
         expirations: DelayQueue<FragmentId>,
         ttl: Duration,
+        status_count: StatusCount,
+        // most recent last
+        rejections: VecDeque<RejectionRecord>,
+        // the date of the block being prepared, to date the rejections
+        block_date: Option<BlockDate>,
     }
 
     impl Logs {
@@ -92,6 +161,9 @@ pub(super) mod internal {
                 entries: HashMap::new(),
                 expirations: DelayQueue::new(),
                 ttl,
+                status_count: StatusCount::default(),
+                rejections: VecDeque::new(),
+                block_date: None,
             }
         }
 
@@ -106,11 +178,55 @@ pub(super) mod internal {
             let fragment_id = log.fragment_id.clone();
             let delay = self.expirations.insert(fragment_id.clone(), self.ttl);
 
-            self.entries.insert(fragment_id, (log, delay));
+            self.status_count.increment(&log.status);
+            if let Some((previous, cache_key)) = self.entries.insert(fragment_id, (log, delay)) {
+                self.status_count.decrement(&previous.status);
+                self.expirations.remove(&cache_key);
+            }
+        }
+
+        /// set the date of the block being prepared, the fragments rejected
+        /// from now on are recorded as rejected at that date
+        pub fn set_block_date(&mut self, date: BlockDate) {
+            self.block_date = Some(date);
+        }
+
+        /// the block has been prepared, the fragments rejected from now on
+        /// are not recorded with its date
+        pub fn clear_block_date(&mut self) {
+            self.block_date = None;
+        }
+
+        fn record_rejection(&mut self, fragment_id: &FragmentId, status: &Status) {
+            let (code, reason) = match status {
+                Status::Rejected { code, reason } => (*code, reason.clone()),
+                Status::Expired { valid_until } => (
+                    RejectionCode::Expired,
+                    format!("fragment expired at {}", valid_until),
+                ),
+                _ => return,
+            };
+            if self.rejections.len() >= MAX_RECENT_REJECTIONS {
+                self.rejections.pop_front();
+            }
+            self.rejections.push_back(RejectionRecord {
+                fragment_id: fragment_id.clone(),
+                code,
+                reason,
+                block_date: self.block_date.clone(),
+                rejected_at: SystemTime::now(),
+            });
+        }
+
+        pub fn recent_rejections(&self, n: usize) -> Vec<RejectionRecord> {
+            self.rejections.iter().rev().take(n).cloned().collect()
         }
 
         pub fn modify(&mut self, fragment_id: &FragmentId, status: Status) {
+            self.record_rejection(fragment_id, &status);
             if let Some((ref mut log, ref key)) = self.entries.get_mut(fragment_id) {
+                self.status_count.decrement(&log.status);
+                self.status_count.increment(&status);
                 log.status = status;
                 log.last_updated_at = SystemTime::now();
 
@@ -120,22 +236,63 @@ pub(super) mod internal {
             }
         }
 
+        /// set back to pending a fragment that was included in a block
+        /// that has been rolled back. If the log has been purged already,
+        /// or the fragment was only seen in the block, a new log is created.
+        pub fn requeue(&mut self, fragment_id: &FragmentId) {
+            if self.entries.contains_key(fragment_id) {
+                self.modify(fragment_id, Status::Pending);
+            } else {
+                self.insert(Log {
+                    fragment_id: fragment_id.clone(),
+                    last_updated_at: SystemTime::now(),
+                    received_at: SystemTime::now(),
+                    received_from: Origin::Network,
+                    status: Status::Pending,
+                });
+            }
+        }
+
         pub fn remove(&mut self, fragment_id: &FragmentId) {
-            if let Some((_, cache_key)) = self.entries.remove(fragment_id) {
+            if let Some((log, cache_key)) = self.entries.remove(fragment_id) {
+                self.status_count.decrement(&log.status);
                 self.expirations.remove(&cache_key);
             }
         }
 
         pub fn poll_purge(&mut self) -> Poll<(), timer::Error> {
             while let Some(entry) = try_ready!(self.expirations.poll()) {
-                self.entries.remove(entry.get_ref());
+                if let Some((log, _)) = self.entries.remove(entry.get_ref()) {
+                    self.status_count.decrement(&log.status);
+                }
             }
 
             Ok(Async::Ready(()))
         }
 
+        /// a copy of the logs of the given fragments, to try selections
+        /// on, discarded afterwards
+        pub fn trial_copy<'a, I>(&self, fragment_ids: I) -> Self
+        where
+            I: IntoIterator<Item = &'a FragmentId>,
+        {
+            let mut copy = Logs::new(self.ttl);
+            for log in fragment_ids.into_iter().filter_map(|id| self.get(id)) {
+                copy.insert(log.clone());
+            }
+            copy
+        }
+
+        pub fn get(&self, fragment_id: &FragmentId) -> Option<&Log> {
+            self.entries.get(fragment_id).map(|(log, _)| log)
+        }
+
         pub fn logs<'a>(&'a self) -> impl Iterator<Item = &'a Log> {
             self.entries.values().map(|(v, _)| v)
         }
+
+        pub fn status_count(&self) -> StatusCount {
+            self.status_count
+        }
     }
 }
@@ -0,0 +1,138 @@
+//! rendering of the fragment selection and pool metrics in the Prometheus
+//! text exposition format
+//!
+//! The metric names and labels are stable, for the operators to scrape
+//! them with the standard tools.
+
+use crate::fragment::{selection::SelectionTotals, PoolStats};
+use std::fmt::Write as _;
+
+/// render the totals of the selections and the usage of the pool
+pub fn render_prometheus(totals: &SelectionTotals, pool: &PoolStats) -> String {
+    let mut text = String::new();
+    let decisions = &totals.decisions;
+
+    metric(
+        &mut text,
+        "jormungandr_blocks_built_total",
+        "counter",
+        "blocks whose fragments have been selected by the node",
+        &[(None, totals.blocks_built)],
+    );
+    metric(
+        &mut text,
+        "jormungandr_fragments_committed_total",
+        "counter",
+        "fragments added to the blocks built by the node",
+        &[(None, decisions.committed)],
+    );
+
+    // sorted, so the lines stay in the same order from one scrape to the next
+    let mut rejected = decisions
+        .rejected
+        .iter()
+        .map(|(code, count)| (Some(("reason", code.metric_label())), *count))
+        .collect::<Vec<_>>();
+    rejected.sort();
+    metric(
+        &mut text,
+        "jormungandr_fragments_rejected_total",
+        "counter",
+        "fragments rejected by the ledger while building the blocks, by rejection code",
+        &rejected,
+    );
+
+    let skipped = [
+        ("block_size", decisions.skipped_size),
+        ("fee", decisions.skipped_fee),
+        ("account_limit", decisions.skipped_account_limit),
+        ("certificate_limit", decisions.skipped_certificate_limit),
+        ("deferred", decisions.deferred),
+        ("policy", decisions.refused_by_policy),
+        ("inputs_outputs", decisions.too_many_inputs_outputs),
+    ]
+    .iter()
+    .map(|(reason, count)| (Some(("reason", *reason)), *count))
+    .collect::<Vec<_>>();
+    metric(
+        &mut text,
+        "jormungandr_fragments_skipped_total",
+        "counter",
+        "fragments left out of the blocks built by the node by its local policies, by reason",
+        &skipped,
+    );
+
+    metric(
+        &mut text,
+        "jormungandr_fees_collected_total",
+        "counter",
+        "fees paid by the fragments of the blocks built by the node",
+        &[(None, totals.fees_collected)],
+    );
+    metric(
+        &mut text,
+        "jormungandr_fragment_pool_fragments",
+        "gauge",
+        "fragments pending in the pool",
+        &[(None, pool.fragment_count as u64)],
+    );
+    metric(
+        &mut text,
+        "jormungandr_fragment_pool_bytes",
+        "gauge",
+        "total size, in bytes, of the fragments pending in the pool",
+        &[(None, pool.total_size as u64)],
+    );
+    text
+}
+
+// a metric with its help and type lines, with a sample for each label
+fn metric(
+    text: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: &[(Option<(&str, &str)>, u64)],
+) {
+    writeln!(text, "# HELP {} {}", name, help).unwrap();
+    writeln!(text, "# TYPE {} {}", name, kind).unwrap();
+    for (label, value) in samples {
+        match label {
+            None => writeln!(text, "{} {}", name, value).unwrap(),
+            Some((label, label_value)) => {
+                writeln!(text, "{}{{{}=\"{}\"}} {}", name, label, label_value, value).unwrap()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fragment::RejectionCode;
+
+    #[test]
+    fn renders_stable_names_and_labels() {
+        let mut totals = SelectionTotals::default();
+        totals.blocks_built = 2;
+        totals.decisions.committed = 5;
+        totals
+            .decisions
+            .rejected
+            .insert(RejectionCode::DoubleSpend, 1);
+        let pool = PoolStats {
+            fragment_count: 3,
+            total_size: 600,
+            ..PoolStats::default()
+        };
+
+        let text = render_prometheus(&totals, &pool);
+        assert!(text.contains("\njormungandr_blocks_built_total 2\n"));
+        assert!(text.contains("\njormungandr_fragments_committed_total 5\n"));
+        assert!(
+            text.contains("\njormungandr_fragments_rejected_total{reason=\"double_spend\"} 1\n")
+        );
+        assert!(text.contains("\njormungandr_fragments_skipped_total{reason=\"fee\"} 0\n"));
+        assert!(text.contains("\njormungandr_fragment_pool_bytes 600\n"));
+    }
+}
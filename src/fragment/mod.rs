@@ -1,14 +1,18 @@
 mod entry;
 mod log;
 mod logs;
+pub mod metrics;
 mod pool;
 mod process;
+mod seen;
 pub mod selection;
 
-pub use self::entry::PoolEntry;
-pub use self::log::{Log, Origin, Status};
-pub use self::logs::Logs;
-pub use self::pool::Pool;
+pub use self::entry::{FragmentType, PoolEntry};
+pub use self::log::{Log, Origin, RejectionCode, RejectionRecord, Status};
+pub use self::logs::{Logs, StatusCount};
+pub use self::pool::{
+    Arrival, EvictionPolicy, FeeRange, ImportCount, MemoryLimit, Pool, PoolInsertError, PoolStats,
+};
 pub use self::process::Process;
 
 use crate::blockcfg::{Message, MessageId};
@@ -1,24 +1,166 @@
 use crate::{
-    blockcfg::{HeaderContentEvalContext, Ledger, LedgerParameters},
-    fragment::{selection::FragmentSelectionAlgorithm, Fragment, Log, Logs, Origin, Status},
+    blockcfg::{BlockDate, HeaderContentEvalContext, HeaderHash, Ledger, LedgerParameters, Value},
+    fragment::{
+        selection::{
+            self, EstimateError, FragmentSelectionAlgorithm, InclusionEstimate, Selection,
+            SelectionConfig,
+        },
+        Fragment, FragmentId, FragmentType, Log, Logs, Origin, RejectionCode, Status, StatusCount,
+    },
+};
+use chain_core::property::Deserialize as _;
+use futures::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
 };
-use std::time::{Duration, SystemTime};
 use tokio::{prelude::*, sync::lock::Lock, timer};
 
 #[derive(Clone)]
 pub struct Pool {
     logs: Logs,
     pool: Lock<internal::Pool>,
+    arrivals: Arc<Mutex<Vec<mpsc::UnboundedSender<Arrival>>>>,
+}
+
+/// the event of a new fragment entering the pool, see `subscribe_arrivals`
+#[derive(Clone, Debug)]
+pub struct Arrival {
+    pub fragment_id: FragmentId,
+    pub received_at: SystemTime,
+    pub received_from: Origin,
+}
+
+/// gauges of the fragment pool usage
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// number of fragments pending in the pool
+    pub fragment_count: usize,
+    /// total size, in bytes, of the fragments pending in the pool
+    pub total_size: usize,
+    /// number of fragment logs in each status
+    pub status_count: StatusCount,
+}
+
+custom_error! {pub PoolInsertError
+    Full = "the fragment pool is full, try again later",
+    Duplicate = "the fragment is already pending or included in a block",
+    Invalid { code: RejectionCode, reason: String } = "the fragment has been rejected: {reason}",
+}
+
+/// the outcome of `Pool::import`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportCount {
+    /// number of fragments added to the pool
+    pub imported: usize,
+    /// number of fragments skipped as they are already known
+    pub known: usize,
+    /// number of fragments evicted right away, the pool being full
+    pub full: usize,
+    /// number of fragments skipped as they cannot be decoded or
+    /// cannot be applied to the ledger
+    pub invalid: usize,
+}
+
+/// a range of fees of `Pool::fee_histogram`, from `min` included to `max`
+/// excluded, the last range having no `max`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeRange {
+    pub min: u64,
+    pub max: Option<u64>,
+}
+
+/// the fragments evicted first when the pool is over its memory limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// the fragments paying the lowest fee, the oldest first among the
+    /// ones paying the same fee
+    LowestFee,
+    /// the fragments received first
+    Oldest,
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        EvictionPolicy::LowestFee
+    }
+}
+
+/// a cap on the total size of the fragments held in the pool, so a flood
+/// of large fragments cannot exhaust the memory of the node
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryLimit {
+    /// the maximum total size, in bytes, of the fragments in the pool
+    pub max_bytes: usize,
+    pub eviction: EvictionPolicy,
+}
+
+// the length of each fragment in an export, in big endian
+const EXPORT_LEN_BYTES: usize = 4;
+
+// split an export into the serialized fragments, the bytes left at the
+// end not making a whole fragment are returned apart
+fn export_frames(mut bytes: &[u8]) -> (Vec<&[u8]>, &[u8]) {
+    let mut frames = Vec::new();
+    while bytes.len() >= EXPORT_LEN_BYTES {
+        let mut len = [0u8; EXPORT_LEN_BYTES];
+        len.copy_from_slice(&bytes[..EXPORT_LEN_BYTES]);
+        let len = u32::from_be_bytes(len) as usize;
+        if bytes.len() - EXPORT_LEN_BYTES < len {
+            break;
+        }
+        frames.push(&bytes[EXPORT_LEN_BYTES..EXPORT_LEN_BYTES + len]);
+        bytes = &bytes[EXPORT_LEN_BYTES + len..];
+    }
+    (frames, bytes)
+}
+
+// the reason to refuse a fragment from its log, if already known. A
+// fragment evicted from the full pool is inserted again, there may be room
+// for it by now.
+fn known_insert_error(log: Option<&Log>) -> Option<PoolInsertError> {
+    match log.map(|log| &log.status) {
+        None => None,
+        Some(Status::Rejected {
+            code: RejectionCode::PoolFull,
+            ..
+        }) => None,
+        Some(Status::Rejected { code, reason }) => Some(PoolInsertError::Invalid {
+            code: *code,
+            reason: reason.clone(),
+        }),
+        Some(Status::Expired { valid_until }) => Some(PoolInsertError::Invalid {
+            code: RejectionCode::Expired,
+            reason: format!("fragment expired at {}", valid_until),
+        }),
+        Some(_) => Some(PoolInsertError::Duplicate),
+    }
 }
 
 impl Pool {
-    pub fn new(ttl: Duration, logs: Logs) -> Self {
+    pub fn new(ttl: Duration, memory_limit: Option<MemoryLimit>, logs: Logs) -> Self {
         Pool {
             logs,
-            pool: Lock::new(internal::Pool::new(ttl)),
+            pool: Lock::new(internal::Pool::new(ttl, memory_limit)),
+            arrivals: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
+    /// a stream of the fragments entering the pool from now on, with
+    /// their pending logs recorded.
+    ///
+    /// The fragments already known are not notified, nor are the
+    /// fragments put back in the pool by `requeue`. The subscription
+    /// ends when the receiver is dropped.
+    pub fn subscribe_arrivals(&self) -> mpsc::UnboundedReceiver<Arrival> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.arrivals.lock().unwrap().push(sender);
+        receiver
+    }
+
     pub fn logs(&self) -> &Logs {
         &self.logs
     }
@@ -27,37 +169,174 @@ impl Pool {
         &mut self,
         origin: Origin,
         fragment: Fragment,
-    ) -> impl Future<Item = bool, Error = ()> {
+    ) -> impl Future<Item = Result<(), PoolInsertError>, Error = ()> {
+        self.insert_with_expiry(origin, fragment, None)
+    }
+
+    /// insert the fragment, with the last block date at which it can be
+    /// included in a block if requested by the submitter.
+    ///
+    /// Past that date, the fragment is removed from the pool and its log
+    /// is marked as rejected, see `select`.
+    ///
+    /// If the pool is then over its memory limit, the fragments are
+    /// evicted according to the eviction policy, possibly this fragment,
+    /// and their logs are marked as rejected.
+    ///
+    /// The fragment is refused with `PoolInsertError::Full` if evicted
+    /// right away, with `Duplicate` if it is already known and with
+    /// `Invalid` if it has been rejected before. A fragment evicted before
+    /// is inserted again, a client trying again later may get it in.
+    pub fn insert_with_expiry(
+        &mut self,
+        origin: Origin,
+        fragment: Fragment,
+        valid_until: Option<BlockDate>,
+    ) -> impl Future<Item = Result<(), PoolInsertError>, Error = ()> {
         use chain_core::property::Message as _;
 
         let id = fragment.id();
         let mut lock = self.pool.clone();
         let mut logs = self.logs.clone();
+        let arrivals = self.arrivals.clone();
 
         self.logs()
-            .exists(vec![id.clone()])
-            .and_then(move |exists| {
-                if exists[0] {
-                    future::Either::A(future::ok(false))
+            .inner()
+            .map(|logs| known_insert_error(logs.get(&id)))
+            .and_then(move |known| {
+                if let Some(error) = known {
+                    future::Either::A(future::ok(Err(error)))
                 } else {
                     future::Either::B(future::poll_fn(move || Ok(lock.poll_lock())).and_then(
                         move |mut guard| {
-                            guard.insert(fragment);
+                            guard.insert(fragment, valid_until);
+                            let evicted = guard.evict_over_limit();
+                            let entered = !evicted.contains(&id);
 
+                            let received_at = SystemTime::now();
                             let log = Log {
-                                fragment_id: id,
-                                last_updated_at: SystemTime::now(),
-                                received_at: SystemTime::now(),
+                                fragment_id: id.clone(),
+                                last_updated_at: received_at,
+                                received_at,
                                 received_from: origin,
                                 status: Status::Pending,
                             };
-                            logs.insert(log).map(|()| true)
+                            logs.inner().map(move |mut logs| {
+                                logs.insert(log);
+                                internal::mark_evicted(&mut logs, evicted);
+                                if entered {
+                                    let arrival = Arrival {
+                                        fragment_id: id,
+                                        received_at,
+                                        received_from: origin,
+                                    };
+                                    // the subscribers gone are dropped
+                                    arrivals.lock().unwrap().retain(|sender| {
+                                        sender.unbounded_send(arrival.clone()).is_ok()
+                                    });
+                                    Ok(())
+                                } else {
+                                    Err(PoolInsertError::Full)
+                                }
+                            })
                         },
                     ))
                 }
             })
     }
 
+    /// serialize the fragments pending in the pool, oldest first, to be
+    /// imported in the pool of another node with `import`.
+    ///
+    /// Each fragment is serialized as in a block, preceded by its length.
+    pub fn export(&self) -> impl Future<Item = Vec<u8>, Error = ()> {
+        let mut lock = self.pool.clone();
+        future::poll_fn(move || Ok(lock.poll_lock())).map(|pool| pool.export())
+    }
+
+    /// insert the fragments of an export of the pool of another node.
+    ///
+    /// The fragments are applied one after another, in the order of the
+    /// export, starting from the given ledger state, so a fragment may
+    /// depend on the fragments exported before it, e.g. chained spendings
+    /// from an account. They are inserted as received now. The fragments
+    /// that cannot be decoded or applied are skipped and counted as
+    /// invalid, a truncated fragment at the end of the export counts as
+    /// one invalid fragment.
+    pub fn import(
+        &mut self,
+        bytes: Vec<u8>,
+        ledger: Ledger,
+        metadata: HeaderContentEvalContext,
+        ledger_params: LedgerParameters,
+    ) -> impl Future<Item = ImportCount, Error = ()> {
+        let mut count = ImportCount::default();
+        let (frames, rest) = export_frames(&bytes);
+        if !rest.is_empty() {
+            count.invalid += 1;
+        }
+        let mut fragments = Vec::new();
+        let mut ledger = ledger;
+        for frame in frames {
+            let fragment = match Fragment::deserialize(frame) {
+                Ok(fragment) => fragment,
+                Err(_) => {
+                    count.invalid += 1;
+                    continue;
+                }
+            };
+            match ledger.apply_fragment(&ledger_params, &fragment, &metadata) {
+                Ok(applied) => {
+                    ledger = applied;
+                    fragments.push(fragment);
+                }
+                Err(_) => count.invalid += 1,
+            }
+        }
+
+        let mut pool = self.clone();
+        stream::iter_ok(fragments).fold(count, move |mut count, fragment| {
+            pool.insert(Origin::Import, fragment).map(move |result| {
+                match result {
+                    Ok(()) => count.imported += 1,
+                    Err(PoolInsertError::Full) => count.full += 1,
+                    Err(_) => count.known += 1,
+                }
+                count
+            })
+        })
+    }
+
+    /// put back the fragments of a block that has been rolled back
+    ///
+    /// The fragments are inserted at the front of the pool, in the order
+    /// they are given, so they are selected first for re-inclusion in the
+    /// new branch. They keep the time they were first received at, if
+    /// still logged. Their logs are set back to pending. Returns the number
+    /// of fragments put back, the ones still in the pool are skipped.
+    pub fn requeue(&mut self, fragments: Vec<Fragment>) -> impl Future<Item = usize, Error = ()> {
+        use chain_core::property::Message as _;
+
+        let mut lock = self.pool.clone();
+        let logs = self.logs().clone();
+
+        future::poll_fn(move || Ok(lock.poll_lock()))
+            .and_then(move |pool| logs.inner().map(|logs| (pool, logs)))
+            .and_then(move |(mut pool, mut logs)| {
+                let mut count = 0;
+                for fragment in fragments.into_iter().rev() {
+                    let id = fragment.id();
+                    let received_at = logs.get(&id).map(|log| log.received_at);
+                    if pool.requeue(fragment, received_at) {
+                        logs.requeue(&id);
+                        count += 1;
+                    }
+                }
+                internal::mark_evicted(&mut logs, pool.evict_over_limit());
+                future::ok(count)
+            })
+    }
+
     pub fn poll_purge(&mut self) -> impl Future<Item = (), Error = timer::Error> {
         let mut lock = self.pool.clone();
         let purge_logs = self.logs.poll_purge();
@@ -67,13 +346,119 @@ impl Pool {
             .and_then(move |()| purge_logs)
     }
 
+    /// current usage of the pool, computed from running counters
+    pub fn stats(&self) -> impl Future<Item = PoolStats, Error = ()> {
+        let mut lock = self.pool.clone();
+        let status_count = self.logs().status_count();
+
+        future::poll_fn(move || Ok(lock.poll_lock()))
+            .map(|pool| (pool.len(), pool.total_size()))
+            .join(status_count)
+            .map(|((fragment_count, total_size), status_count)| PoolStats {
+                fragment_count,
+                total_size,
+                status_count,
+            })
+    }
+
+    /// total size, in bytes, of the fragments held in the pool, which the
+    /// memory limit of the pool applies to
+    pub fn memory_usage(&self) -> impl Future<Item = usize, Error = ()> {
+        let mut lock = self.pool.clone();
+        future::poll_fn(move || Ok(lock.poll_lock())).map(|pool| pool.total_size())
+    }
+
+    /// number of fragments pending in the pool for each kind of fragment
+    pub fn type_histogram(&self) -> impl Future<Item = HashMap<FragmentType, usize>, Error = ()> {
+        let mut lock = self.pool.clone();
+        future::poll_fn(move || Ok(lock.poll_lock())).map(|pool| pool.type_histogram().clone())
+    }
+
+    /// number of fragments pending in the pool in each of the fee ranges
+    /// delimited by `bounds`, from a fee of 0 to the first bound, then
+    /// from each bound to the next one and from the last bound up.
+    ///
+    /// The pool keeps the number of fragments paying each fee, so this
+    /// does not go through the fragments.
+    pub fn fee_histogram(
+        &self,
+        bounds: Vec<u64>,
+    ) -> impl Future<Item = Vec<(FeeRange, usize)>, Error = ()> {
+        let mut lock = self.pool.clone();
+        future::poll_fn(move || Ok(lock.poll_lock())).map(move |pool| pool.fee_histogram(bounds))
+    }
+
+    /// estimate the number of blocks until a fragment paying `fee` would
+    /// be included with the given selection, see
+    /// `selection::estimate_inclusion`
+    pub fn estimate_inclusion(
+        &self,
+        config: SelectionConfig,
+        fee: Value,
+    ) -> impl Future<Item = InclusionEstimate, Error = EstimateError> {
+        let mut lock = self.pool.clone();
+        future::poll_fn(move || Ok(lock.poll_lock()))
+            .and_then(move |pool| selection::estimate_inclusion(&config, &pool, fee))
+    }
+
+    /// re-check every pending fragment against the given ledger state,
+    /// the state of the block `tip`
+    ///
+    /// The fragments that cannot be applied anymore (inputs already spent,
+    /// invalid counter...) are removed from the pool and their logs are
+    /// marked as rejected. Returns the number of fragments rejected.
+    ///
+    /// The fragments already found valid on the same tip, by a previous
+    /// call, are not checked again.
+    pub fn revalidate(
+        &mut self,
+        tip: HeaderHash,
+        ledger: Ledger,
+        metadata: HeaderContentEvalContext,
+        ledger_params: LedgerParameters,
+    ) -> impl Future<Item = usize, Error = ()> {
+        let mut lock = self.pool.clone();
+        let logs = self.logs().clone();
+
+        future::poll_fn(move || Ok(lock.poll_lock()))
+            .and_then(move |pool| logs.inner().map(|logs| (pool, logs)))
+            .and_then(move |(mut pool, mut logs)| {
+                logs.set_block_date(metadata.block_date.clone());
+                let removed = pool.revalidate(tip, &ledger, &ledger_params, &metadata, &mut logs);
+                logs.clear_block_date();
+                future::ok(removed)
+            })
+    }
+
+    /// remove the fragments expired at the date of a block just applied
+    /// to the blockchain and mark them as expired in the logs, so the
+    /// selection of the next blocks does not examine them. Returns the
+    /// number of fragments removed.
+    ///
+    /// The expiry follows the date of the chain rather than the clock,
+    /// nothing is done if the date has not advanced since the last call.
+    pub fn flush_expired(&mut self, date: BlockDate) -> impl Future<Item = usize, Error = ()> {
+        let mut lock = self.pool.clone();
+        let logs = self.logs().clone();
+
+        future::poll_fn(move || Ok(lock.poll_lock()))
+            .and_then(move |pool| logs.inner().map(|logs| (pool, logs)))
+            .and_then(move |(mut pool, mut logs)| future::ok(pool.flush_expired(&date, &mut logs)))
+    }
+
+    /// select the fragments of a block with the given algorithm
+    ///
+    /// The fragments expired at the date of the block are removed from
+    /// the pool first, so no algorithm can select them. With `audit`, the
+    /// decisions taken on the fragments are recorded in the selection.
     pub fn select<SelectAlg>(
         &mut self,
         ledger: Ledger,
         metadata: HeaderContentEvalContext,
         ledger_params: LedgerParameters,
         mut selection_alg: SelectAlg,
-    ) -> impl Future<Item = SelectAlg, Error = ()>
+        audit: bool,
+    ) -> impl Future<Item = Selection, Error = ()>
     where
         SelectAlg: FragmentSelectionAlgorithm,
     {
@@ -83,18 +468,39 @@ impl Pool {
         future::poll_fn(move || Ok(lock.poll_lock()))
             .and_then(move |pool| logs.inner().map(|logs| (pool, logs)))
             .and_then(move |(mut pool, mut logs)| {
-                selection_alg.select(&ledger, &ledger_params, &metadata, &mut logs, &mut pool);
-                future::ok(selection_alg)
+                logs.set_block_date(metadata.block_date.clone());
+                pool.purge_expired(&metadata.block_date, &mut logs);
+                let mut selection = Selection::new(ledger);
+                if audit {
+                    selection.enable_audit();
+                }
+                selection_alg.select(
+                    &ledger_params,
+                    &metadata,
+                    &mut logs,
+                    &mut pool,
+                    &mut selection,
+                );
+                selection.set_remaining(pool.len());
+                logs.clear_block_date();
+                future::ok(selection)
             })
     }
 }
 
 pub(super) mod internal {
-    use crate::fragment::{Fragment, FragmentId, PoolEntry};
+    use super::{EvictionPolicy, FeeRange, MemoryLimit};
+    use crate::{
+        blockcfg::{BlockDate, HeaderContentEvalContext, HeaderHash, Ledger, LedgerParameters},
+        fragment::{
+            logs::internal::Logs, selection, Fragment, FragmentId, FragmentType, PoolEntry,
+            RejectionCode, Status,
+        },
+    };
     use std::{
-        collections::{BTreeMap, HashMap, VecDeque},
+        collections::{BTreeMap, HashMap, HashSet, VecDeque},
         sync::Arc,
-        time::Duration,
+        time::{Duration, SystemTime},
     };
     use tokio::{
         prelude::*,
@@ -107,34 +513,271 @@ pub(super) mod internal {
         pub entries_by_time: VecDeque<FragmentId>,
         expirations: DelayQueue<FragmentId>,
         ttl: Duration,
+        memory_limit: Option<MemoryLimit>,
+        total_size: usize,
+        type_histogram: HashMap<FragmentType, usize>,
+        // the number of fragments paying each fee
+        fee_counts: BTreeMap<u64, usize>,
+        // the fragments ordered by fee then by age, the ones to evict first
+        // with `EvictionPolicy::LowestFee`, and the key of each fragment
+        // in this order
+        entries_by_fee: BTreeMap<(u64, i64), FragmentId>,
+        fee_keys: HashMap<FragmentId, (u64, i64)>,
+        // the ages given to the fragments inserted at the front and at the
+        // back of `entries_by_time`
+        front_age: i64,
+        back_age: i64,
+        // the fragments found valid on the state of the block
+        // `validated_tip` for a block at the date `validated_tip` by the
+        // last revalidations
+        validated_tip: Option<(HeaderHash, BlockDate)>,
+        validated: HashSet<FragmentId>,
+        // the date of the last block the expired fragments have been
+        // flushed at, see `flush_expired`
+        flushed_at: Option<BlockDate>,
+    }
+
+    // the outcome of checking a pending fragment on the state of the tip
+    enum Revalidation {
+        Valid,
+        // the fragment may become valid once the fragments it depends on,
+        // e.g. a previous spending from the same account, are applied
+        Undecided,
+        Rejected(Status),
+    }
+
+    /// mark the logs of the fragments evicted from the pool as rejected
+    pub fn mark_evicted(logs: &mut Logs, evicted: Vec<FragmentId>) {
+        for id in evicted {
+            if logs.get(&id).is_some() {
+                logs.modify(
+                    &id,
+                    Status::Rejected {
+                        code: RejectionCode::PoolFull,
+                        reason: "evicted from the fragment pool over its memory limit".to_owned(),
+                    },
+                );
+            }
+        }
     }
 
     impl Pool {
-        pub fn new(ttl: Duration) -> Self {
+        pub fn new(ttl: Duration, memory_limit: Option<MemoryLimit>) -> Self {
             Pool {
                 entries: HashMap::new(),
                 entries_by_id: BTreeMap::new(),
                 entries_by_time: VecDeque::new(),
                 expirations: DelayQueue::new(),
                 ttl,
+                memory_limit,
+                total_size: 0,
+                type_histogram: HashMap::new(),
+                fee_counts: BTreeMap::new(),
+                entries_by_fee: BTreeMap::new(),
+                fee_keys: HashMap::new(),
+                front_age: 0,
+                back_age: 0,
+                validated_tip: None,
+                validated: HashSet::new(),
+                flushed_at: None,
             }
         }
 
-        pub fn insert(&mut self, fragment: Fragment) {
-            let entry = Arc::new(PoolEntry::new(&fragment));
+        /// number of fragments in the pool
+        pub fn len(&self) -> usize {
+            self.entries.len()
+        }
+
+        /// total size, in bytes, of the fragments in the pool
+        pub fn total_size(&self) -> usize {
+            self.total_size
+        }
+
+        /// number of fragments in the pool for each kind of fragment
+        pub fn type_histogram(&self) -> &HashMap<FragmentType, usize> {
+            &self.type_histogram
+        }
+
+        /// number of fragments in the pool in each of the fee ranges
+        /// delimited by `bounds`, see `super::Pool::fee_histogram`
+        pub fn fee_histogram(&self, mut bounds: Vec<u64>) -> Vec<(FeeRange, usize)> {
+            bounds.sort();
+            bounds.dedup();
+            let mut histogram = Vec::with_capacity(bounds.len() + 1);
+            let mut min = 0;
+            // a bound of 0 would make an empty range
+            for max in bounds.into_iter().filter(|bound| *bound > 0) {
+                let count = self
+                    .fee_counts
+                    .range(min..max)
+                    .map(|(_, n)| n)
+                    .sum::<usize>();
+                histogram.push((
+                    FeeRange {
+                        min,
+                        max: Some(max),
+                    },
+                    count,
+                ));
+                min = max;
+            }
+            let count = self.fee_counts.range(min..).map(|(_, n)| n).sum::<usize>();
+            histogram.push((FeeRange { min, max: None }, count));
+            histogram
+        }
+
+        fn count_in(&mut self, entry: &PoolEntry) {
+            self.total_size += *entry.fragment_size();
+            *self
+                .type_histogram
+                .entry(entry.fragment_type())
+                .or_insert(0) += 1;
+            *self.fee_counts.entry(entry.fragment_fee().0).or_insert(0) += 1;
+        }
+
+        fn count_out(&mut self, entry: &PoolEntry) {
+            self.total_size = self.total_size.saturating_sub(*entry.fragment_size());
+            let fragment_type = entry.fragment_type();
+            if let Some(count) = self.type_histogram.get_mut(&fragment_type) {
+                *count -= 1;
+                if *count == 0 {
+                    self.type_histogram.remove(&fragment_type);
+                }
+            }
+            let fee = entry.fragment_fee().0;
+            if let Some(count) = self.fee_counts.get_mut(&fee) {
+                *count -= 1;
+                if *count == 0 {
+                    self.fee_counts.remove(&fee);
+                }
+            }
+        }
+
+        pub fn insert(&mut self, fragment: Fragment, valid_until: Option<BlockDate>) {
+            let mut entry = PoolEntry::new(&fragment);
+            if let Some(valid_until) = valid_until {
+                entry.set_valid_until(valid_until);
+            }
+            let fragment_id = self.insert_entry(entry, fragment);
+            self.back_age += 1;
+            self.index_fee(fragment_id.clone(), self.back_age);
+            self.entries_by_time.push_back(fragment_id);
+        }
+
+        /// insert the fragment at the front of the pool, unless it is
+        /// already in the pool. Returns whether the fragment was inserted.
+        ///
+        /// `received_at` is the time the fragment was first received, if
+        /// known, the entry is considered as just received otherwise.
+        pub fn requeue(&mut self, fragment: Fragment, received_at: Option<SystemTime>) -> bool {
+            use chain_core::property::Message as _;
+
+            if self.entries.contains_key(&fragment.id()) {
+                return false;
+            }
+            let entry = match received_at {
+                Some(received_at) => PoolEntry::with_received_at(&fragment, received_at),
+                None => PoolEntry::new(&fragment),
+            };
+            let fragment_id = self.insert_entry(entry, fragment);
+            self.front_age -= 1;
+            self.index_fee(fragment_id.clone(), self.front_age);
+            self.entries_by_time.push_front(fragment_id);
+            true
+        }
+
+        /// the fragments of the pool, oldest first, each preceded by its
+        /// length, see `export_frames`
+        pub fn export(&self) -> Vec<u8> {
+            use chain_core::property::Serialize as _;
+
+            let mut bytes = Vec::new();
+            for id in self.entries_by_time.iter() {
+                let (_, fragment, _) = &self.entries[id];
+                let serialized = fragment
+                    .serialize_as_vec()
+                    .expect("serializing a fragment in memory cannot fail");
+                bytes.extend_from_slice(&(serialized.len() as u32).to_be_bytes());
+                bytes.extend_from_slice(&serialized);
+            }
+            bytes
+        }
+
+        /// a copy of the pool to try selections on, discarded afterwards
+        ///
+        /// The entries are shared with the pool. The copy is not meant to
+        /// be purged, its expirations are unrelated to the ones of the pool.
+        /// Nothing is evicted from the copy, so it is not indexed by fee.
+        pub fn trial_copy(&self) -> Self {
+            let mut copy = Pool::new(self.ttl, None);
+            for id in self.entries_by_time.iter() {
+                let (entry, fragment, _) = &self.entries[id];
+                let delay = copy.expirations.insert(id.clone(), self.ttl);
+                copy.count_in(entry);
+                copy.entries
+                    .insert(id.clone(), (entry.clone(), fragment.clone(), delay));
+                copy.entries_by_id.insert(id.clone(), entry.clone());
+                copy.entries_by_time.push_back(id.clone());
+            }
+            copy
+        }
+
+        fn insert_entry(&mut self, entry: PoolEntry, fragment: Fragment) -> FragmentId {
+            let entry = Arc::new(entry);
             let fragment_id = entry.fragment_ref().clone();
             let delay = self.expirations.insert(fragment_id.clone(), self.ttl);
+            self.count_in(&entry);
 
             self.entries
                 .insert(fragment_id.clone(), (entry.clone(), fragment, delay));
-            self.entries_by_id
-                .insert(fragment_id.clone(), entry.clone());
-            self.entries_by_time.push_back(fragment_id);
+            self.entries_by_id.insert(fragment_id.clone(), entry);
+            fragment_id
+        }
+
+        fn index_fee(&mut self, fragment_id: FragmentId, age: i64) {
+            let key = (self.entries[&fragment_id].0.fragment_fee().0, age);
+            self.entries_by_fee.insert(key, fragment_id.clone());
+            self.fee_keys.insert(fragment_id, key);
+        }
+
+        fn unindex_fee(&mut self, fragment_id: &FragmentId) {
+            if let Some(key) = self.fee_keys.remove(fragment_id) {
+                self.entries_by_fee.remove(&key);
+            }
+        }
+
+        /// remove the fragments the pool holds over its memory limit, the
+        /// first ones to go with the eviction policy. Returns the ids of
+        /// the fragments removed.
+        pub fn evict_over_limit(&mut self) -> Vec<FragmentId> {
+            let limit = match self.memory_limit {
+                Some(limit) => limit,
+                None => return Vec::new(),
+            };
+            let mut evicted = Vec::new();
+            while self.total_size > limit.max_bytes {
+                let victim = match limit.eviction {
+                    EvictionPolicy::Oldest => self.entries_by_time.front().cloned(),
+                    // the first of the lowest fees is the oldest
+                    EvictionPolicy::LowestFee => self.entries_by_fee.values().next().cloned(),
+                };
+                match victim {
+                    Some(id) => {
+                        self.remove(&id);
+                        evicted.push(id);
+                    }
+                    None => break,
+                }
+            }
+            evicted
         }
 
         pub fn remove(&mut self, fragment_id: &FragmentId) -> Option<Fragment> {
-            if let Some((_, fragment, cache_key)) = self.entries.remove(fragment_id) {
+            if let Some((entry, fragment, cache_key)) = self.entries.remove(fragment_id) {
+                self.count_out(&entry);
+                self.unindex_fee(fragment_id);
                 self.entries_by_id.remove(fragment_id);
+                self.validated.remove(fragment_id);
                 self.entries_by_time
                     .iter()
                     .position(|id| id == fragment_id)
@@ -148,10 +791,124 @@ pub(super) mod internal {
             }
         }
 
+        /// remove the fragments that can never be applied on the given
+        /// ledger, the state of the block `tip`, and mark them as rejected
+        /// in the logs
+        ///
+        /// Each fragment is checked on its own, so a fragment failing as
+        /// it depends on other pending fragments, e.g. the second spending
+        /// from an account, stays pending: only the errors the fragments
+        /// applied before cannot change remove a fragment, see
+        /// `selection::is_definitive`. The selection applies the fragments
+        /// one after another and tells the others apart.
+        ///
+        /// The fragments found valid on the same tip for the same block
+        /// date by the previous calls are skipped, the cache is reset when
+        /// the tip or the date changes: the validity of a fragment may
+        /// depend on the date of the block it goes in.
+        pub fn revalidate(
+            &mut self,
+            tip: HeaderHash,
+            ledger: &Ledger,
+            ledger_params: &LedgerParameters,
+            metadata: &HeaderContentEvalContext,
+            logs: &mut Logs,
+        ) -> usize {
+            let validated_tip = (tip, metadata.block_date.clone());
+            if self.validated_tip.as_ref() != Some(&validated_tip) {
+                self.validated.clear();
+                self.validated_tip = Some(validated_tip);
+            }
+
+            self.revalidate_with(logs, |fragment| {
+                match ledger.apply_fragment(ledger_params, fragment, metadata) {
+                    Ok(_) => Revalidation::Valid,
+                    Err(ref error) if selection::is_definitive(error) => {
+                        Revalidation::Rejected(Status::rejected_by_ledger(error))
+                    }
+                    Err(_) => Revalidation::Undecided,
+                }
+            })
+        }
+
+        // `revalidate` with the check of each fragment not in the cache
+        fn revalidate_with<F>(&mut self, logs: &mut Logs, mut check: F) -> usize
+        where
+            F: FnMut(&Fragment) -> Revalidation,
+        {
+            let mut rejected = Vec::new();
+            for id in self.entries_by_time.iter() {
+                if self.validated.contains(id) {
+                    continue;
+                }
+                let (_, fragment, _) = &self.entries[id];
+                match check(fragment) {
+                    Revalidation::Valid => {
+                        self.validated.insert(id.clone());
+                    }
+                    Revalidation::Undecided => (),
+                    Revalidation::Rejected(status) => rejected.push((id.clone(), status)),
+                }
+            }
+
+            let count = rejected.len();
+            for (id, status) in rejected {
+                self.remove(&id);
+                logs.modify(&id, status);
+            }
+
+            count
+        }
+
+        /// remove the fragments that cannot be included in a block at the
+        /// given date anymore and mark them as expired in the logs
+        pub fn purge_expired(&mut self, date: &BlockDate, logs: &mut Logs) -> usize {
+            let expired: Vec<_> = self
+                .entries_by_time
+                .iter()
+                .filter_map(|id| {
+                    let (entry, _, _) = &self.entries[id];
+                    if entry.is_expired_at(date) {
+                        entry
+                            .valid_until()
+                            .map(|valid_until| (id.clone(), valid_until.clone()))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+
+            let count = expired.len();
+            for (id, valid_until) in expired {
+                self.remove(&id);
+                logs.modify(&id, Status::Expired { valid_until });
+            }
+
+            count
+        }
+
+        /// `purge_expired` at the date of a block applied to the
+        /// blockchain, nothing is done unless the date is past the date
+        /// of the last flush
+        pub fn flush_expired(&mut self, date: &BlockDate, logs: &mut Logs) -> usize {
+            let advanced = self.flushed_at.as_ref().map_or(true, |flushed_at| {
+                (flushed_at.epoch, flushed_at.slot_id) < (date.epoch, date.slot_id)
+            });
+            if !advanced {
+                return 0;
+            }
+            self.flushed_at = Some(date.clone());
+            self.purge_expired(date, logs)
+        }
+
         pub fn poll_purge(&mut self) -> Poll<(), timer::Error> {
             while let Some(entry) = try_ready!(self.expirations.poll()) {
-                self.entries.remove(entry.get_ref());
+                if let Some((pool_entry, _, _)) = self.entries.remove(entry.get_ref()) {
+                    self.count_out(&pool_entry);
+                }
+                self.unindex_fee(entry.get_ref());
                 self.entries_by_id.remove(entry.get_ref());
+                self.validated.remove(entry.get_ref());
                 self.entries_by_time
                     .iter()
                     .position(|id| id == entry.get_ref())
@@ -163,4 +920,98 @@ pub(super) mod internal {
             Ok(Async::Ready(()))
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::blockcfg::{ConfigParam, ConfigParams, Message};
+        use crate::fragment::{Log, Origin};
+        use chain_core::property::Message as _;
+
+        fn fragment(slots_per_epoch: u32) -> Fragment {
+            let mut params = ConfigParams::new();
+            params.push(ConfigParam::SlotsPerEpoch(slots_per_epoch));
+            Message::Initial(params)
+        }
+
+        fn pending(id: &FragmentId) -> Log {
+            let now = SystemTime::now();
+            Log {
+                fragment_id: id.clone(),
+                last_updated_at: now,
+                received_at: now,
+                received_from: Origin::Rest,
+                status: Status::Pending,
+            }
+        }
+
+        #[test]
+        fn chained_fragment_stays_pending() {
+            let ttl = Duration::from_secs(3600);
+            let mut pool = Pool::new(ttl, None);
+            let mut logs = Logs::new(ttl);
+            // two spendings from the same account: the second one signs
+            // the counter the first one leaves, it fails on the tip alone
+            let (first, second) = (fragment(1), fragment(2));
+            let (first_id, second_id) = (first.id(), second.id());
+            for fragment in vec![first, second] {
+                logs.insert(pending(&fragment.id()));
+                pool.insert(fragment, None);
+            }
+
+            let mut checked = Vec::new();
+            let removed = pool.revalidate_with(&mut logs, |fragment| {
+                checked.push(fragment.id());
+                if fragment.id() == first_id {
+                    Revalidation::Valid
+                } else {
+                    Revalidation::Undecided
+                }
+            });
+            assert_eq!(removed, 0);
+            assert_eq!(pool.len(), 2);
+            match logs.get(&second_id).map(|log| &log.status) {
+                Some(Status::Pending) => (),
+                _ => panic!("the chained fragment is no longer pending"),
+            }
+
+            // the undecided fragment is checked again, the valid one is not
+            checked.clear();
+            pool.revalidate_with(&mut logs, |fragment| {
+                checked.push(fragment.id());
+                Revalidation::Valid
+            });
+            assert_eq!(checked, vec![second_id]);
+        }
+
+        #[test]
+        fn expired_fragments_are_marked_expired() {
+            let ttl = Duration::from_secs(3600);
+            let mut pool = Pool::new(ttl, None);
+            let mut logs = Logs::new(ttl);
+            let valid_until = BlockDate {
+                epoch: 0,
+                slot_id: 1,
+            };
+            let (expiring, lasting) = (fragment(1), fragment(2));
+            let (expiring_id, lasting_id) = (expiring.id(), lasting.id());
+            logs.insert(pending(&expiring_id));
+            pool.insert(expiring, Some(valid_until.clone()));
+            logs.insert(pending(&lasting_id));
+            pool.insert(lasting, None);
+
+            let date = BlockDate {
+                epoch: 0,
+                slot_id: 2,
+            };
+            assert_eq!(pool.flush_expired(&date, &mut logs), 1);
+            assert_eq!(pool.len(), 1);
+            match logs.get(&expiring_id).map(|log| &log.status) {
+                Some(Status::Expired { valid_until: at }) if *at == valid_until => (),
+                _ => panic!("the fragment is not marked expired"),
+            }
+            let count = logs.status_count();
+            assert_eq!((count.pending, count.expired, count.rejected), (1, 1, 0));
+        }
+    }
 }
@@ -1,9 +1,10 @@
 use crate::{
-    fragment::{Logs, Pool},
+    fragment::{seen::SeenFragments, Logs, MemoryLimit, Pool},
     intercom::TransactionMsg,
     rest::v0::node::stats::StatsCounter,
     utils::{async_msg::MessageQueue, task::TokioServiceInfo},
 };
+use chain_core::property::Message as _;
 use slog::Logger;
 use std::time::Duration;
 use tokio::{
@@ -18,19 +19,23 @@ pub struct Process {
     pool: Pool,
     logs: Logs,
     garbage_collection_interval: Duration,
+    seen_capacity: usize,
 }
 
 impl Process {
     pub fn new(
         pool_ttl: Duration,
+        pool_memory_limit: Option<MemoryLimit>,
         logs_ttl: Duration,
         garbage_collection_interval: Duration,
+        seen_capacity: usize,
     ) -> Self {
         let logs = Logs::new(logs_ttl);
         Process {
-            pool: Pool::new(pool_ttl, logs.clone()),
+            pool: Pool::new(pool_ttl, pool_memory_limit, logs.clone()),
             logs,
             garbage_collection_interval,
+            seen_capacity,
         }
     }
 
@@ -51,6 +56,7 @@ impl Process {
 
         let pool = self.pool.clone();
         let pool_copy = self.pool;
+        let mut seen = SeenFragments::new(self.seen_capacity);
 
         input.for_each(move |input| {
             match input {
@@ -78,19 +84,39 @@ impl Process {
 
                     let mut pool_copy = pool_copy.clone();
                     let stats_counter = stats_counter.clone();
+                    let txs = txs
+                        .into_iter()
+                        .filter(|tx| !seen.check_and_insert(tx.id()))
+                        .collect::<Vec<_>>();
 
                     A(B(stream::iter_ok(txs).for_each(move |tx| {
                         let stats_counter = stats_counter.clone();
-                        pool_copy.insert(origin, tx).map(move |inserted| {
-                            if inserted {
+                        pool_copy.insert(origin, tx).map(move |result| {
+                            if result.is_ok() {
                                 stats_counter.add_tx_recv_cnt(1)
                             }
                         })
                     })))
                 }
+                TransactionMsg::SubmitTransaction(origin, tx, valid_until, reply) => {
+                    // the client is told why a fragment already received
+                    // is refused, from its log in the pool
+                    seen.check_and_insert(tx.id());
+                    let stats_counter = stats_counter.clone();
+
+                    B(A(pool_copy
+                        .clone()
+                        .insert_with_expiry(origin, tx, valid_until)
+                        .map(move |result| {
+                            if result.is_ok() {
+                                stats_counter.add_tx_recv_cnt(1)
+                            }
+                            reply.reply_ok(result);
+                        })))
+                }
                 TransactionMsg::GetTransactions(_txids, _handler) => {
                     // this function is no yet implemented, this is not handled in the
-                    B(future::ok(unimplemented!()))
+                    B(B(future::ok(unimplemented!())))
                 }
             }
         })
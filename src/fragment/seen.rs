@@ -0,0 +1,54 @@
+use crate::fragment::FragmentId;
+use std::collections::{BTreeMap, HashMap};
+
+/// Bounded record of the ids of the fragments recently received, the
+/// least recently received forgotten first.
+///
+/// A fragment gossiped around the network arrives from several peers, the
+/// copies received after the first one are dropped before reaching the
+/// pool. This also covers the fragments rejected since, so the spam just
+/// rejected is not examined again.
+pub struct SeenFragments {
+    // the last time each id was received, as a sequence number
+    last_seen: HashMap<FragmentId, u64>,
+    by_last_seen: BTreeMap<u64, FragmentId>,
+    next_seq: u64,
+    capacity: usize,
+}
+
+impl SeenFragments {
+    pub fn new(capacity: usize) -> Self {
+        SeenFragments {
+            last_seen: HashMap::with_capacity(capacity),
+            by_last_seen: BTreeMap::new(),
+            next_seq: 0,
+            capacity,
+        }
+    }
+
+    /// record the fragment as received now, returns whether it was
+    /// already received
+    pub fn check_and_insert(&mut self, id: FragmentId) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        match self.last_seen.insert(id.clone(), seq) {
+            Some(previous) => {
+                self.by_last_seen.remove(&previous);
+                self.by_last_seen.insert(seq, id);
+                true
+            }
+            None => {
+                self.by_last_seen.insert(seq, id);
+                if self.last_seen.len() > self.capacity {
+                    let oldest = *self.by_last_seen.keys().next().unwrap();
+                    let id = self.by_last_seen.remove(&oldest).unwrap();
+                    self.last_seen.remove(&id);
+                }
+                false
+            }
+        }
+    }
+}
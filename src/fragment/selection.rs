@@ -1,11 +1,21 @@
 use crate::{
-    blockcfg::{BlockBuilder, HeaderContentEvalContext, Ledger, LedgerParameters},
-    fragment::{FragmentId, Status},
+    blockcfg::{
+        BlockBuilder, BlockDate, HeaderContentEvalContext, Ledger, LedgerParameters, Value,
+    },
+    fragment::{Fragment, FragmentId, FragmentType, RejectionCode, Status},
 };
+use chain_impl_mockchain::{ledger, transaction::InputEnum};
+use serde::{Deserialize, Serialize};
+use std::cmp::{self, Ordering};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
 
+use super::entry::fragment_fee;
 use super::logs::internal::Logs;
 use super::pool::internal::Pool;
 
+/// the outcome of the examination of a fragment by a selection algorithm
 pub enum SelectionOutput {
     Commit { fragment_id: FragmentId },
     RequestSmallerFee,
@@ -13,75 +23,2348 @@ pub enum SelectionOutput {
     Reject { reason: String },
 }
 
+/// The content of the block being built by the selection algorithms.
+///
+/// Along with the block builder, it keeps the ledger state resulting from
+/// the application of the fragments selected so far, so several selection
+/// algorithms can be run one after the other on the same block.
+pub struct Selection {
+    builder: BlockBuilder,
+    ledger: Ledger,
+    count: usize,
+    remaining: usize,
+    limit: Option<usize>,
+    max_per_account: Option<usize>,
+    per_account: HashMap<AccountKey, usize>,
+    max_certificates: Option<usize>,
+    certificates: usize,
+    constraints: OrderingConstraints,
+    included: HashSet<FragmentId>,
+    // the ids of the included fragments, in the order of the block
+    selected: Vec<FragmentId>,
+    size: usize,
+    max_size: Option<usize>,
+    overflow: Vec<FragmentId>,
+    fees: u64,
+    mandatory_errors: Vec<MandatoryFragmentError>,
+    policies: Vec<AcceptancePolicy>,
+    // the fragments refused by a policy rejecting them
+    refused: Vec<FragmentId>,
+    // the decisions taken on the fragments, in audit mode
+    audit: Option<Vec<AuditEntry>>,
+    decisions: DecisionCounts,
+}
+
+/// The decision taken by the selection on a fragment it has examined.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuditDecision {
+    /// added to the block
+    Committed,
+    /// rejected by the ledger
+    Rejected { code: RejectionCode, reason: String },
+    /// left pending, it would exceed the size budget of the block
+    SkippedSize,
+    /// below the minimum fee of the node
+    SkippedFee,
+    /// left pending, its accounts have reached their limit in the block
+    SkippedAccountLimit,
+    /// left pending, the block has reached its limit of certificates
+    SkippedCertificateLimit,
+    /// left pending, it must follow fragments not selected yet
+    Deferred,
+    /// refused by an acceptance policy
+    RefusedByPolicy,
+    /// more inputs or outputs than the limits of the node
+    TooManyInputsOutputs,
+}
+
+/// A decision of the selection on a fragment, along with the state of the
+/// block budget once taken, see `Selection::enable_audit`.
+#[derive(Clone, Debug)]
+pub struct AuditEntry {
+    pub fragment_id: FragmentId,
+    /// the order in which the fragment was examined, a fragment examined
+    /// several times has an entry each time
+    pub position: usize,
+    pub decision: AuditDecision,
+    /// the number of fragments in the block
+    pub count: usize,
+    /// the size in bytes of the fragments in the block
+    pub size: usize,
+    /// the fees paid by the fragments in the block
+    pub fees: u64,
+}
+
+/// The number of times each decision has been taken on the fragments by a
+/// selection, whether audited or not. A fragment examined several times
+/// is counted each time.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecisionCounts {
+    pub committed: u64,
+    /// the fragments rejected by the ledger, by rejection code
+    pub rejected: HashMap<RejectionCode, u64>,
+    pub skipped_size: u64,
+    pub skipped_fee: u64,
+    pub skipped_account_limit: u64,
+    pub skipped_certificate_limit: u64,
+    pub deferred: u64,
+    pub refused_by_policy: u64,
+    pub too_many_inputs_outputs: u64,
+}
+
+impl DecisionCounts {
+    fn count(&mut self, decision: &AuditDecision) {
+        match decision {
+            AuditDecision::Committed => self.committed += 1,
+            AuditDecision::Rejected { code, .. } => *self.rejected.entry(*code).or_insert(0) += 1,
+            AuditDecision::SkippedSize => self.skipped_size += 1,
+            AuditDecision::SkippedFee => self.skipped_fee += 1,
+            AuditDecision::SkippedAccountLimit => self.skipped_account_limit += 1,
+            AuditDecision::SkippedCertificateLimit => self.skipped_certificate_limit += 1,
+            AuditDecision::Deferred => self.deferred += 1,
+            AuditDecision::RefusedByPolicy => self.refused_by_policy += 1,
+            AuditDecision::TooManyInputsOutputs => self.too_many_inputs_outputs += 1,
+        }
+    }
+
+    /// add the counts of another selection
+    pub fn add(&mut self, other: &DecisionCounts) {
+        self.committed += other.committed;
+        for (code, count) in other.rejected.iter() {
+            *self.rejected.entry(*code).or_insert(0) += count;
+        }
+        self.skipped_size += other.skipped_size;
+        self.skipped_fee += other.skipped_fee;
+        self.skipped_account_limit += other.skipped_account_limit;
+        self.skipped_certificate_limit += other.skipped_certificate_limit;
+        self.deferred += other.deferred;
+        self.refused_by_policy += other.refused_by_policy;
+        self.too_many_inputs_outputs += other.too_many_inputs_outputs;
+    }
+}
+
+/// the bytes of the public key of an account contributing to the block
+type AccountKey = Vec<u8>;
+
+/// the size of the fragment in the block, counted as in the pool
+fn fragment_size(fragment: &Fragment) -> usize {
+    fragment.to_raw().size_bytes_plus_size()
+}
+
+/// the accounts spending from the inputs of the fragment
+fn source_accounts(fragment: &Fragment) -> Vec<AccountKey> {
+    match fragment {
+        Fragment::Transaction(auth) => auth
+            .transaction
+            .inputs
+            .iter()
+            .filter_map(|input| match input.to_enum() {
+                InputEnum::AccountInput(account, _) => account.to_single_account(),
+                InputEnum::UtxoInput(_) => None,
+            })
+            .map(|account| {
+                let public_key: chain_crypto::PublicKey<_> = account.into();
+                public_key.as_ref().to_vec()
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// the fragments carrying a transaction, the only ones paying a fee
+fn pays_fee(fragment: &Fragment) -> bool {
+    match fragment {
+        Fragment::Transaction(_) | Fragment::Certificate(_) => true,
+        _ => false,
+    }
+}
+
+fn is_certificate(fragment: &Fragment) -> bool {
+    match fragment {
+        Fragment::Certificate(_) => true,
+        _ => false,
+    }
+}
+
+/// the fragments whose outputs are spent by the inputs of the fragment
+fn spent_fragments(fragment: &Fragment) -> Vec<FragmentId> {
+    let inputs = match fragment {
+        Fragment::Transaction(auth) => &auth.transaction.inputs,
+        Fragment::Certificate(auth) => &auth.transaction.inputs,
+        _ => return Vec::new(),
+    };
+    inputs
+        .iter()
+        .filter_map(|input| match input.to_enum() {
+            InputEnum::UtxoInput(pointer) => Some(pointer.transaction_id),
+            InputEnum::AccountInput(_, _) => None,
+        })
+        .collect()
+}
+
+/// the number of inputs and outputs of the transaction of the fragment
+fn input_output_counts(fragment: &Fragment) -> (usize, usize) {
+    match fragment {
+        Fragment::Transaction(auth) => (
+            auth.transaction.inputs.len(),
+            auth.transaction.outputs.len(),
+        ),
+        Fragment::Certificate(auth) => (
+            auth.transaction.inputs.len(),
+            auth.transaction.outputs.len(),
+        ),
+        _ => (0, 0),
+    }
+}
+
+impl Selection {
+    pub fn new(ledger: Ledger) -> Self {
+        Selection {
+            builder: BlockBuilder::new(),
+            ledger,
+            count: 0,
+            remaining: 0,
+            limit: None,
+            max_per_account: None,
+            per_account: HashMap::new(),
+            max_certificates: None,
+            certificates: 0,
+            constraints: OrderingConstraints::new(),
+            included: HashSet::new(),
+            selected: Vec::new(),
+            size: 0,
+            max_size: None,
+            overflow: Vec::new(),
+            fees: 0,
+            mandatory_errors: Vec::new(),
+            policies: Vec::new(),
+            refused: Vec::new(),
+            audit: None,
+            decisions: DecisionCounts::default(),
+        }
+    }
+
+    /// record every decision taken on the fragments, with the state of
+    /// the block budget, to be returned by `finalize`.
+    ///
+    /// This is heavier than the logs of the fragments, it is meant to find
+    /// out why a block contains what it does.
+    pub fn enable_audit(&mut self) {
+        if self.audit.is_none() {
+            self.audit = Some(Vec::new());
+        }
+    }
+
+    fn audit(&mut self, id: &FragmentId, decision: AuditDecision) {
+        self.decisions.count(&decision);
+        if let Some(audit) = &mut self.audit {
+            audit.push(AuditEntry {
+                fragment_id: id.clone(),
+                position: audit.len(),
+                decision,
+                count: self.count,
+                size: self.size,
+                fees: self.fees,
+            });
+        }
+    }
+
+    // a fork of the state of the selection to try fragments on, the
+    // fragments already selected are not copied in the builder, see
+    // `adopt` and `join`
+    fn fork(&self) -> Self {
+        Selection {
+            builder: BlockBuilder::new(),
+            ledger: self.ledger.clone(),
+            count: self.count,
+            remaining: self.remaining,
+            limit: self.limit,
+            max_per_account: self.max_per_account,
+            per_account: self.per_account.clone(),
+            max_certificates: self.max_certificates,
+            certificates: self.certificates,
+            constraints: self.constraints.clone(),
+            included: self.included.clone(),
+            selected: self.selected.clone(),
+            size: self.size,
+            max_size: self.max_size,
+            overflow: self.overflow.clone(),
+            fees: self.fees,
+            // only the errors of the trial are of interest
+            mandatory_errors: Vec::new(),
+            policies: self.policies.clone(),
+            refused: self.refused.clone(),
+            // only the decisions of the trial, see `adopt`
+            audit: self.audit.as_ref().map(|_| Vec::new()),
+            decisions: DecisionCounts::default(),
+        }
+    }
+
+    // take over the outcome of an algorithm run on the `fork` of the
+    // selection and on the `trial_copy` of the pool and of the logs: the
+    // fragments taken out of the trial pool are taken out of the pool,
+    // see `join`.
+    fn adopt(
+        &mut self,
+        trial: Selection,
+        trial_pool: &Pool,
+        trial_logs: &Logs,
+        pool: &mut Pool,
+        logs: &mut Logs,
+    ) {
+        let taken = pool
+            .entries
+            .keys()
+            .filter(|id| !trial_pool.entries.contains_key(id))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.join(trial, &taken, trial_logs, pool, logs);
+    }
+
+    // take over the outcome of the fragments tried on the `fork` of the
+    // selection: the `taken` fragments are taken out of the pool with the
+    // status they have in the trial logs, the ones selected by the fork
+    // being added to the block in the same order.
+    fn join(
+        &mut self,
+        trial: Selection,
+        taken: &[FragmentId],
+        trial_logs: &Logs,
+        pool: &mut Pool,
+        logs: &mut Logs,
+    ) {
+        let mut fragments = HashMap::new();
+        for id in taken {
+            if let Some(log) = trial_logs.get(id) {
+                logs.modify(id, log.status.clone());
+            }
+            if let Some(fragment) = pool.remove(id) {
+                fragments.insert(id.clone(), fragment);
+            }
+        }
+
+        let mut builder = std::mem::replace(&mut self.builder, BlockBuilder::new());
+        for id in &trial.selected[self.selected.len()..] {
+            if let Some(fragment) = fragments.remove(id) {
+                builder.message(fragment);
+            }
+        }
+        let mut audit = self.audit.take();
+        if let (Some(audit), Some(trial_audit)) = (&mut audit, trial.audit) {
+            let offset = audit.len();
+            audit.extend(trial_audit.into_iter().map(|mut entry| {
+                entry.position += offset;
+                entry
+            }));
+        }
+        let mut decisions = std::mem::replace(&mut self.decisions, DecisionCounts::default());
+        decisions.add(&trial.decisions);
+        let mut mandatory_errors = std::mem::replace(&mut self.mandatory_errors, Vec::new());
+        mandatory_errors.extend(trial.mandatory_errors);
+
+        *self = Selection {
+            builder,
+            audit,
+            decisions,
+            mandatory_errors,
+            ..trial
+        };
+    }
+
+    /// the ledger state after the application of the selected fragments
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// the number of fragments selected so far
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// the number of fragments left pending in the pool once the
+    /// selection is done
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    pub(super) fn set_remaining(&mut self, remaining: usize) {
+        self.remaining = remaining;
+    }
+
+    /// the ids of the fragments selected so far, in the order they are
+    /// in the block being built
+    pub fn selected_ids(&self) -> &[FragmentId] {
+        &self.selected
+    }
+
+    /// the total size, in bytes, of the fragments selected so far
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// the total fees paid by the fragments selected so far
+    pub fn fees(&self) -> Value {
+        Value(self.fees)
+    }
+
+    /// the number of times each decision has been taken so far
+    pub fn decisions(&self) -> &DecisionCounts {
+        &self.decisions
+    }
+
+    /// the fragments examined but left in the pool because they would
+    /// have exceeded the size budget of the block, in the order they
+    /// were examined.
+    ///
+    /// This is the `SelectionOutput::RequestSmallerSize` outcome: these
+    /// fragments were not rejected, they may fit in a larger block.
+    pub fn overflow(&self) -> &[FragmentId] {
+        &self.overflow
+    }
+
+    /// the fragments required by `MustInclude` that could not be added
+    /// to the block
+    pub fn mandatory_errors(&self) -> &[MandatoryFragmentError] {
+        &self.mandatory_errors
+    }
+
+    /// take the `mandatory_errors` out of the selection, to report them
+    /// once the selection is finalized
+    pub fn take_mandatory_errors(&mut self) -> Vec<MandatoryFragmentError> {
+        std::mem::replace(&mut self.mandatory_errors, Vec::new())
+    }
+
+    /// the block budget is exhausted, no more fragments can be selected
+    pub fn is_full(&self) -> bool {
+        self.limit.map_or(false, |limit| self.count >= limit)
+    }
+
+    // the block budget leaves room for `count` more fragments
+    fn has_room_for(&self, count: usize) -> bool {
+        self.limit.map_or(true, |limit| self.count + count <= limit)
+    }
+
+    /// check the fragment is allowed in the block by the constraints of
+    /// the selection, not whether it is valid on the ledger.
+    ///
+    /// The algorithms are expected to leave the fragments that are not
+    /// allowed in the pool, so they can be selected in a later block.
+    /// The fragments not allowed because of the size budget only are
+    /// recorded in the `overflow` report.
+    pub fn can_add(&mut self, fragment: &Fragment) -> bool {
+        use chain_core::property::Message as _;
+
+        let within_account_limit = match self.max_per_account {
+            None => true,
+            Some(max) => source_accounts(fragment)
+                .iter()
+                .all(|account| self.per_account.get(account).map_or(true, |n| *n < max)),
+        };
+        let id = fragment.id();
+        if !within_account_limit {
+            self.audit(&id, AuditDecision::SkippedAccountLimit);
+            return false;
+        }
+        let within_certificate_limit = !is_certificate(fragment)
+            || self
+                .max_certificates
+                .map_or(true, |max| self.certificates < max);
+        if !within_certificate_limit {
+            self.audit(&id, AuditDecision::SkippedCertificateLimit);
+            return false;
+        }
+        if self.is_deferred(&id) {
+            self.audit(&id, AuditDecision::Deferred);
+            return false;
+        }
+
+        let refusal = self
+            .policies
+            .iter()
+            .find(|policy| !(policy.predicate)(fragment, &self.ledger))
+            .map(|policy| policy.refusal);
+        match refusal {
+            None => {}
+            Some(Refusal::LeavePending) => {
+                self.audit(&id, AuditDecision::RefusedByPolicy);
+                return false;
+            }
+            Some(Refusal::Reject) => {
+                self.audit(&id, AuditDecision::RefusedByPolicy);
+                if !self.refused.contains(&id) {
+                    self.refused.push(id);
+                }
+                return false;
+            }
+        }
+
+        let within_size_limit = self
+            .max_size
+            .map_or(true, |max| self.size + fragment_size(fragment) <= max);
+        if !within_size_limit {
+            self.audit(&id, AuditDecision::SkippedSize);
+            if !self.overflow.contains(&id) {
+                self.overflow.push(id);
+            }
+        }
+        within_size_limit
+    }
+
+    // the fragment must come after fragments not selected yet
+    fn is_deferred(&self, id: &FragmentId) -> bool {
+        self.constraints
+            .prerequisites_of(id)
+            .any(|prerequisite| !self.included.contains(prerequisite))
+    }
+
+    /// try to add the fragment to the block
+    ///
+    /// The fragment is applied on top of the current ledger state and the
+    /// logs are updated with the outcome. Returns `true` if the fragment
+    /// has been added to the block.
+    ///
+    /// The witnesses are verified as part of applying the fragment and
+    /// cannot be checked ahead, concurrently: verifying a witness needs
+    /// the owner of the spent UTxO or the spending counter of the account,
+    /// which both depend on the fragments added to the block before.
+    pub fn try_add(
+        &mut self,
+        id: &FragmentId,
+        fragment: Fragment,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+    ) -> bool {
+        match self
+            .ledger
+            .apply_fragment(ledger_params, &fragment, metadata)
+        {
+            Ok(ledger) => {
+                for account in source_accounts(&fragment) {
+                    *self.per_account.entry(account).or_insert(0) += 1;
+                }
+                if is_certificate(&fragment) {
+                    self.certificates += 1;
+                }
+                self.ledger = ledger;
+                self.size += fragment_size(&fragment);
+                self.fees = self.fees.saturating_add(fragment_fee(&fragment).0);
+                self.builder.message(fragment);
+                self.count += 1;
+                self.included.insert(id.clone());
+                self.selected.push(id.clone());
+                self.audit(id, AuditDecision::Committed);
+
+                logs.modify(
+                    id,
+                    Status::InABlock {
+                        date: metadata.block_date,
+                    },
+                );
+                true
+            }
+            Err(error) => {
+                self.reject(id, &error, logs);
+                false
+            }
+        }
+    }
+
+    // record the fragment as rejected by the ledger
+    fn reject(&mut self, id: &FragmentId, error: &ledger::Error, logs: &mut Logs) {
+        self.audit(
+            id,
+            AuditDecision::Rejected {
+                code: RejectionCode::from_ledger_error(error),
+                reason: error.to_string(),
+            },
+        );
+        logs.modify(id, Status::rejected_by_ledger(error));
+    }
+
+    /// the block builder with the selected fragments, along with the
+    /// decisions taken if the audit was enabled
+    pub fn finalize(self) -> (BlockBuilder, Option<Vec<AuditEntry>>) {
+        (self.builder, self.audit)
+    }
+}
+
 pub trait FragmentSelectionAlgorithm {
     fn select(
         &mut self,
-        ledger: &Ledger,
         ledger_params: &LedgerParameters,
         metadata: &HeaderContentEvalContext,
         logs: &mut Logs,
         pool: &mut Pool,
+        selection: &mut Selection,
     );
+}
 
-    fn finalize(self) -> BlockBuilder;
+impl<A: FragmentSelectionAlgorithm + ?Sized> FragmentSelectionAlgorithm for Box<A> {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        (**self).select(ledger_params, metadata, logs, pool, selection)
+    }
 }
 
+/// Selection algorithm giving priority to the fragments first received
+/// the earliest.
 pub struct OldestFirst {
-    builder: BlockBuilder,
     max_per_block: usize,
 }
 
 impl OldestFirst {
     pub fn new(max_per_block: usize) -> Self {
-        OldestFirst {
-            builder: BlockBuilder::new(),
-            max_per_block,
-        }
+        OldestFirst { max_per_block }
+    }
+}
+
+// true if the ledger rejects the fragment with this error whatever the
+// fragments applied before it: the owner of an UTxO of the ledger does
+// not change and the balance of a transaction only depends on the values
+// it states. The errors of the account witnesses are not, the spending
+// counter they sign is incremented by the fragments spending from the
+// same account.
+pub(super) fn is_definitive(error: &ledger::Error) -> bool {
+    match error {
+        ledger::Error::UtxoInvalidSignature { .. } | ledger::Error::NotBalanced { .. } => true,
+        _ => false,
     }
 }
 
 impl FragmentSelectionAlgorithm for OldestFirst {
-    fn finalize(self) -> BlockBuilder {
-        self.builder
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let mut candidates = pool
+            .entries_by_time
+            .iter()
+            .map(|id| {
+                let (entry, _, _) = &pool.entries[id];
+                (*entry.received_at(), id.clone())
+            })
+            .collect::<Vec<_>>();
+        // the fragments requeued after a rollback keep the time they were
+        // first received at, the queue position does not reflect it
+        candidates.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut total = 0usize;
+
+        for (_, id) in candidates {
+            if total >= self.max_per_block || selection.is_full() {
+                break;
+            }
+
+            if !selection.can_add(&pool.entries[&id].1) {
+                continue;
+            }
+
+            let fragment = pool.remove(&id).unwrap();
+
+            if selection.try_add(&id, fragment, ledger_params, metadata, logs) {
+                total += 1;
+            }
+        }
+    }
+}
+
+/// Selection algorithm giving priority to the fragments paying the
+/// highest fee. Fragments paying the same fee are selected oldest first.
+pub struct HighestFee {
+    max_per_block: usize,
+}
+
+impl HighestFee {
+    pub fn new(max_per_block: usize) -> Self {
+        HighestFee { max_per_block }
     }
+}
 
+impl FragmentSelectionAlgorithm for HighestFee {
     fn select(
         &mut self,
-        ledger: &Ledger,
         ledger_params: &LedgerParameters,
         metadata: &HeaderContentEvalContext,
         logs: &mut Logs,
         pool: &mut Pool,
+        selection: &mut Selection,
     ) {
+        let mut candidates = pool
+            .entries_by_time
+            .iter()
+            .map(|id| {
+                let (entry, _, _) = &pool.entries[id];
+                (entry.fragment_fee().0, *entry.received_at(), id.clone())
+            })
+            .collect::<Vec<_>>();
+        // fragments paying the same fee are ordered by the time they
+        // were first received at
+        candidates.sort_by(|(fee_a, time_a, _), (fee_b, time_b, _)| {
+            fee_b.cmp(fee_a).then(time_a.cmp(time_b))
+        });
+
         let mut total = 0usize;
 
-        while let Some(id) = pool.entries_by_time.pop_front() {
-            if total >= self.max_per_block {
+        for (_, _, id) in candidates {
+            if total >= self.max_per_block || selection.is_full() {
                 break;
             }
 
+            if !selection.can_add(&pool.entries[&id].1) {
+                continue;
+            }
+
             let fragment = pool.remove(&id).unwrap();
 
-            match ledger.apply_fragment(ledger_params, &fragment, metadata) {
-                Ok(_) => {
-                    self.builder.message(fragment);
+            if selection.try_add(&id, fragment, ledger_params, metadata, logs) {
+                total += 1;
+            }
+        }
+    }
+}
+
+/// Selection algorithm giving priority to the largest fragments, to use
+/// as much as possible of the size budget of the block rather than to
+/// maximize the fees.
+///
+/// Each fragment is the largest one still fitting in the remaining
+/// budget, the fragments too large are left pending in the pool and
+/// the smaller ones are tried next. Fragments of the same size are
+/// selected oldest first.
+pub struct LargestFirst {
+    max_per_block: usize,
+}
 
-                    logs.modify(
-                        &id,
-                        Status::InABlock {
-                            date: metadata.block_date,
-                        },
-                    );
+impl LargestFirst {
+    pub fn new(max_per_block: usize) -> Self {
+        LargestFirst { max_per_block }
+    }
+}
 
-                    total += 1;
-                }
-                Err(error) => logs.modify(
-                    &id,
-                    Status::Rejected {
-                        reason: error.to_string(),
-                    },
-                ),
+impl FragmentSelectionAlgorithm for LargestFirst {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let mut candidates = pool
+            .entries_by_time
+            .iter()
+            .map(|id| {
+                let (entry, _, _) = &pool.entries[id];
+                (*entry.fragment_size(), *entry.received_at(), id.clone())
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|(size_a, time_a, _), (size_b, time_b, _)| {
+            size_b.cmp(size_a).then(time_a.cmp(time_b))
+        });
+
+        let mut total = 0usize;
+
+        for (_, _, id) in candidates {
+            if total >= self.max_per_block || selection.is_full() {
+                break;
+            }
+
+            if !selection.can_add(&pool.entries[&id].1) {
+                continue;
+            }
+
+            let fragment = pool.remove(&id).unwrap();
+
+            if selection.try_add(&id, fragment, ledger_params, metadata, logs) {
+                total += 1;
+            }
+        }
+    }
+}
+
+/// Selection algorithm ordering the fragments by a score combining a
+/// weight given to each kind of fragment with the age and the fee of the
+/// fragment:
+///
+/// `type weight + age_coefficient * age in seconds + fee_coefficient * fee`
+///
+/// e.g. with a weight of the delegations slightly above the one of the
+/// transactions and a small age coefficient, the delegations come first
+/// but a transaction pending for long enough wins over them. The kinds of
+/// fragments without weight have a weight of 0. Fragments with the same
+/// score are selected oldest first.
+pub struct WeightedSelection {
+    max_per_block: usize,
+    type_weights: HashMap<FragmentType, f64>,
+    age_coefficient: f64,
+    fee_coefficient: f64,
+}
+
+impl WeightedSelection {
+    pub fn new(
+        max_per_block: usize,
+        type_weights: HashMap<FragmentType, f64>,
+        age_coefficient: f64,
+        fee_coefficient: f64,
+    ) -> Self {
+        WeightedSelection {
+            max_per_block,
+            type_weights,
+            age_coefficient,
+            fee_coefficient,
+        }
+    }
+
+    fn score(&self, fragment_type: FragmentType, age_secs: f64, fee: u64) -> f64 {
+        self.type_weights
+            .get(&fragment_type)
+            .cloned()
+            .unwrap_or(0.0)
+            + self.age_coefficient * age_secs
+            + self.fee_coefficient * fee as f64
+    }
+}
+
+/// The weights of the `weighted` strategy, see `WeightedSelection`.
+///
+/// The weight of the initial and old UTxO declaration fragments, which
+/// are never pending in the pool, is 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SelectionWeights {
+    pub transaction: f64,
+    pub stake_delegation: f64,
+    pub stake_key_registration: f64,
+    pub stake_key_deregistration: f64,
+    pub stake_pool_registration: f64,
+    pub stake_pool_retirement: f64,
+    /// update proposals and votes, and any other kind of fragment
+    pub other: f64,
+    /// the score added per second the fragment has been pending
+    pub age: f64,
+    /// the score added per unit of fee paid by the fragment
+    pub fee: f64,
+}
+
+impl SelectionWeights {
+    pub fn type_weights(&self) -> HashMap<FragmentType, f64> {
+        vec![
+            (FragmentType::Transaction, self.transaction),
+            (FragmentType::StakeDelegation, self.stake_delegation),
+            (
+                FragmentType::StakeKeyRegistration,
+                self.stake_key_registration,
+            ),
+            (
+                FragmentType::StakeKeyDeregistration,
+                self.stake_key_deregistration,
+            ),
+            (
+                FragmentType::StakePoolRegistration,
+                self.stake_pool_registration,
+            ),
+            (
+                FragmentType::StakePoolRetirement,
+                self.stake_pool_retirement,
+            ),
+            (FragmentType::Other, self.other),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    fn is_finite(&self) -> bool {
+        self.type_weights()
+            .values()
+            .chain(&[self.age, self.fee])
+            .all(|weight| weight.is_finite())
+    }
+}
+
+impl FragmentSelectionAlgorithm for WeightedSelection {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let now = SystemTime::now();
+        let mut candidates = pool
+            .entries_by_time
+            .iter()
+            .map(|id| {
+                let (entry, _, _) = &pool.entries[id];
+                let received_at = *entry.received_at();
+                let age = now.duration_since(received_at).unwrap_or_default();
+                let age_secs = age.as_secs() as f64 + f64::from(age.subsec_millis()) / 1000.0;
+                let score = self.score(entry.fragment_type(), age_secs, entry.fragment_fee().0);
+                (score, received_at, id.clone())
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|(score_a, time_a, _), (score_b, time_b, _)| {
+            score_b
+                .partial_cmp(score_a)
+                .unwrap_or(Ordering::Equal)
+                .then(time_a.cmp(time_b))
+        });
+
+        let mut total = 0usize;
+
+        for (_, _, id) in candidates {
+            if total >= self.max_per_block || selection.is_full() {
+                break;
+            }
+
+            if !selection.can_add(&pool.entries[&id].1) {
+                continue;
+            }
+
+            let fragment = pool.remove(&id).unwrap();
+
+            if selection.try_add(&id, fragment, ledger_params, metadata, logs) {
+                total += 1;
             }
         }
     }
 }
+
+/// Selection algorithm giving priority to the fragments the closest
+/// to the end of their validity window.
+///
+/// Fragments already expired at the date of the block being built are
+/// rejected. Fragments without validity window come last, oldest first.
+pub struct EarliestExpiryFirst {
+    max_per_block: usize,
+}
+
+impl EarliestExpiryFirst {
+    pub fn new(max_per_block: usize) -> Self {
+        EarliestExpiryFirst { max_per_block }
+    }
+}
+
+fn cmp_valid_until(a: &Option<BlockDate>, b: &Option<BlockDate>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => (a.epoch, a.slot_id).cmp(&(b.epoch, b.slot_id)),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+fn is_expired(valid_until: &BlockDate, date: &BlockDate) -> bool {
+    (valid_until.epoch, valid_until.slot_id) < (date.epoch, date.slot_id)
+}
+
+impl FragmentSelectionAlgorithm for EarliestExpiryFirst {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let mut candidates = pool
+            .entries_by_time
+            .iter()
+            .map(|id| {
+                let (entry, _, _) = &pool.entries[id];
+                (entry.valid_until().cloned(), id.clone())
+            })
+            .collect::<Vec<_>>();
+        // the sort is stable: fragments expiring at the same date
+        // keep their time order
+        candidates.sort_by(|(a, _), (b, _)| cmp_valid_until(a, b));
+
+        let mut total = 0usize;
+
+        for (valid_until, id) in candidates {
+            if total >= self.max_per_block || selection.is_full() {
+                break;
+            }
+
+            if let Some(valid_until) = valid_until {
+                if is_expired(&valid_until, &metadata.block_date) {
+                    pool.remove(&id);
+                    logs.modify(&id, Status::Expired { valid_until });
+                    continue;
+                }
+            }
+
+            if !selection.can_add(&pool.entries[&id].1) {
+                continue;
+            }
+
+            let fragment = pool.remove(&id).unwrap();
+
+            if selection.try_add(&id, fragment, ledger_params, metadata, logs) {
+                total += 1;
+            }
+        }
+    }
+}
+
+/// The fee tiers of `FeeTiered`, the fragments paying at least `high_fee`
+/// are in the high tier, the ones paying at least `medium_fee` in the
+/// medium tier and the other ones in the low tier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeTiers {
+    pub high_fee: u64,
+    pub medium_fee: u64,
+    /// the number of fragments selected from the high, medium and low
+    /// tiers, in that order, in each round
+    pub weights: [usize; 3],
+}
+
+impl FeeTiers {
+    fn tier_of(&self, fee: u64) -> usize {
+        if fee >= self.high_fee {
+            0
+        } else if fee >= self.medium_fee {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+/// Selection algorithm interleaving the fee tiers, so the fragments paying
+/// low fees are not starved by the ones paying higher fees.
+///
+/// The selection goes in rounds, each round selecting up to the weight of
+/// each tier from the high tier, then the medium and low tiers. Within a
+/// tier, the fragments are selected oldest first. The fragments of a tier
+/// of weight 0 are left pending in the pool.
+pub struct FeeTiered {
+    max_per_block: usize,
+    tiers: FeeTiers,
+}
+
+impl FeeTiered {
+    pub fn new(max_per_block: usize, tiers: FeeTiers) -> Self {
+        FeeTiered {
+            max_per_block,
+            tiers,
+        }
+    }
+}
+
+impl FragmentSelectionAlgorithm for FeeTiered {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let mut tiers: [VecDeque<FragmentId>; 3] = Default::default();
+        for id in pool.entries_by_time.iter() {
+            let fee = pool.entries[id].0.fragment_fee().0;
+            tiers[self.tiers.tier_of(fee)].push_back(id.clone());
+        }
+        let weights = self.tiers.weights;
+
+        let mut total = 0usize;
+
+        'rounds: while tiers
+            .iter()
+            .zip(weights.iter())
+            .any(|(tier, weight)| *weight > 0 && !tier.is_empty())
+        {
+            for (tier, weight) in tiers.iter_mut().zip(weights.iter()) {
+                let mut taken = 0;
+                while taken < *weight {
+                    if total >= self.max_per_block || selection.is_full() {
+                        break 'rounds;
+                    }
+
+                    let id = match tier.pop_front() {
+                        Some(id) => id,
+                        None => break,
+                    };
+
+                    if !selection.can_add(&pool.entries[&id].1) {
+                        continue;
+                    }
+
+                    let fragment = pool.remove(&id).unwrap();
+
+                    if selection.try_add(&id, fragment, ledger_params, metadata, logs) {
+                        total += 1;
+                        taken += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Selection algorithm including the chains of pending transactions as
+/// packages, the parents before their children.
+///
+/// A fragment spending an output of another pending fragment depends on
+/// it, the fragments linked by such dependencies form a package. The
+/// packages are selected by their oldest fragment, and a package is only
+/// included in whole, the fragments ordered parents first, oldest first.
+/// The packages not fitting in the rest of the block are left pending.
+///
+/// The minimum fee applies to the packages rather than to each fragment:
+/// a package is left pending unless its fragments pay the minimum fee on
+/// average, so a child paying a high fee can pay for its parents. A
+/// package is tried on a fork of the selection, it is added in whole or
+/// not at all: if one of its fragments cannot be added to the block, the
+/// package is left pending, without the fragment if it is rejected by the
+/// ledger.
+pub struct DependencyPackages {
+    max_per_block: usize,
+    min_fee: Value,
+}
+
+impl DependencyPackages {
+    pub fn new(max_per_block: usize, min_fee: Value) -> Self {
+        DependencyPackages {
+            max_per_block,
+            min_fee,
+        }
+    }
+}
+
+// the pending fragments spent from by each pending fragment, for the
+// fragments depending on others only
+fn pending_parents(pool: &Pool) -> HashMap<FragmentId, Vec<FragmentId>> {
+    pool.entries_by_time
+        .iter()
+        .filter_map(|id| {
+            let parents = spent_fragments(&pool.entries[id].1)
+                .into_iter()
+                .filter(|parent| parent != id && pool.entries.contains_key(parent))
+                .collect::<Vec<_>>();
+            if parents.is_empty() {
+                None
+            } else {
+                Some((id.clone(), parents))
+            }
+        })
+        .collect()
+}
+
+// the pending fragments grouped in packages, each one ordered parents
+// first, oldest first
+fn dependency_packages(pool: &Pool) -> Vec<Vec<FragmentId>> {
+    let parents = pending_parents(pool);
+    let mut linked: HashMap<&FragmentId, Vec<&FragmentId>> = HashMap::new();
+    for (child, child_parents) in parents.iter() {
+        for parent in child_parents {
+            linked.entry(child).or_insert_with(Vec::new).push(parent);
+            linked.entry(parent).or_insert_with(Vec::new).push(child);
+        }
+    }
+    let position = pool
+        .entries_by_time
+        .iter()
+        .enumerate()
+        .map(|(position, id)| (id, position))
+        .collect::<HashMap<_, _>>();
+
+    let mut packaged = HashSet::new();
+    let mut packages = Vec::new();
+    for id in pool.entries_by_time.iter() {
+        if !packaged.insert(id) {
+            continue;
+        }
+        let mut members = vec![id];
+        let mut next = 0;
+        while next < members.len() {
+            for other in linked.get(members[next]).into_iter().flatten() {
+                if packaged.insert(*other) {
+                    members.push(*other);
+                }
+            }
+            next += 1;
+        }
+        members.sort_by_key(|id| position[id]);
+
+        let mut ordered = Vec::with_capacity(members.len());
+        let mut placed = HashSet::new();
+        // a member is placed once all its parents are, a cycle of
+        // dependencies cannot be valid and is left out
+        while let Some(index) = members.iter().position(|id| {
+            parents
+                .get(*id)
+                .into_iter()
+                .flatten()
+                .all(|parent| placed.contains(parent))
+        }) {
+            let id = members.remove(index);
+            placed.insert(id);
+            ordered.push(id.clone());
+        }
+        packages.push(ordered);
+    }
+    packages
+}
+
+impl FragmentSelectionAlgorithm for DependencyPackages {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let mut total = 0usize;
+
+        for package in dependency_packages(pool) {
+            if total >= self.max_per_block || selection.is_full() {
+                break;
+            }
+
+            let len = package.len();
+            if total + len > self.max_per_block || !selection.has_room_for(len) {
+                continue;
+            }
+
+            let fee = package
+                .iter()
+                .map(|id| pool.entries[id].0.fragment_fee().0)
+                .fold(0u64, u64::saturating_add);
+            if fee < self.min_fee.0.saturating_mul(len as u64) {
+                for id in package.iter() {
+                    selection.audit(id, AuditDecision::SkippedFee);
+                }
+                continue;
+            }
+
+            let mut fork = selection.fork();
+            let mut fork_logs = logs.trial_copy(package.iter());
+            let mut rejected = None;
+            let mut added = 0;
+            for id in package.iter() {
+                let fragment = &pool.entries[id].1;
+                if !fork.can_add(fragment) {
+                    break;
+                }
+                if !fork.try_add(
+                    id,
+                    fragment.clone(),
+                    ledger_params,
+                    metadata,
+                    &mut fork_logs,
+                ) {
+                    rejected = Some(id);
+                    break;
+                }
+                added += 1;
+            }
+
+            if added == len {
+                selection.join(fork, &package, &fork_logs, pool, logs);
+                total += len;
+            } else if let Some(id) = rejected {
+                // the fragment cannot be applied even once its parents
+                // are, the ledger state of the fork gives the reason
+                let fragment = pool.remove(id).unwrap();
+                if let Err(error) = fork
+                    .ledger
+                    .apply_fragment(ledger_params, &fragment, metadata)
+                {
+                    selection.reject(id, &error, logs);
+                }
+            }
+        }
+    }
+}
+
+/// Selection algorithm picking the fragments by increasing fragment id.
+///
+/// The selected fragments and their order only depend on the content of
+/// the pool, not on the time the fragments were received, so the blocks
+/// built in the tests are reproducible. This is not meant for production:
+/// the fragment ids give no meaningful priority.
+#[cfg(feature = "testing")]
+pub struct ByFragmentId {
+    max_per_block: usize,
+}
+
+#[cfg(feature = "testing")]
+impl ByFragmentId {
+    pub fn new(max_per_block: usize) -> Self {
+        ByFragmentId { max_per_block }
+    }
+}
+
+#[cfg(feature = "testing")]
+impl FragmentSelectionAlgorithm for ByFragmentId {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let candidates = pool.entries_by_id.keys().cloned().collect::<Vec<_>>();
+        let mut total = 0usize;
+
+        for id in candidates {
+            if total >= self.max_per_block || selection.is_full() {
+                break;
+            }
+
+            if !selection.can_add(&pool.entries[&id].1) {
+                continue;
+            }
+
+            let fragment = pool.remove(&id).unwrap();
+
+            if selection.try_add(&id, fragment, ledger_params, metadata, logs) {
+                total += 1;
+            }
+        }
+    }
+}
+
+/// Runs a sequence of selection algorithms over the same block.
+///
+/// Every algorithm picks from the fragments left in the pool by the
+/// previous ones, on top of the ledger state resulting from the fragments
+/// already selected. The block budget of the chain is shared by all the
+/// algorithms, each of them being further limited by its own budget.
+pub struct Chained {
+    algorithms: Vec<Box<dyn FragmentSelectionAlgorithm + Send>>,
+    max_per_block: usize,
+}
+
+impl Chained {
+    pub fn new(max_per_block: usize) -> Self {
+        Chained {
+            algorithms: Vec::new(),
+            max_per_block,
+        }
+    }
+
+    /// append an algorithm to run once the previous ones are done
+    pub fn then<A>(mut self, algorithm: A) -> Self
+    where
+        A: FragmentSelectionAlgorithm + Send + 'static,
+    {
+        self.algorithms.push(Box::new(algorithm));
+        self
+    }
+}
+
+impl FragmentSelectionAlgorithm for Chained {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let outer_limit = selection.limit;
+        let limit = selection.count() + self.max_per_block;
+        selection.limit = Some(outer_limit.map_or(limit, |outer| cmp::min(outer, limit)));
+
+        for algorithm in self.algorithms.iter_mut() {
+            if selection.is_full() {
+                break;
+            }
+            algorithm.select(ledger_params, metadata, logs, pool, selection);
+        }
+
+        selection.limit = outer_limit;
+    }
+}
+
+/// Scores the outcome of a selection, the higher the better.
+///
+/// See `BestOf`, any `Fn(&Selection) -> u64` can be used as a score.
+pub trait SelectionScore {
+    fn score(&self, selection: &Selection) -> u64;
+}
+
+impl<F: Fn(&Selection) -> u64> SelectionScore for F {
+    fn score(&self, selection: &Selection) -> u64 {
+        self(selection)
+    }
+}
+
+/// The usual objectives to compare selections on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionObjective {
+    /// the total fees paid by the fragments of the block
+    Fees,
+    /// the number of fragments in the block
+    Count,
+    /// the total size of the fragments in the block
+    Size,
+}
+
+impl SelectionScore for SelectionObjective {
+    fn score(&self, selection: &Selection) -> u64 {
+        match self {
+            SelectionObjective::Fees => selection.fees().0,
+            SelectionObjective::Count => selection.count() as u64,
+            SelectionObjective::Size => selection.size() as u64,
+        }
+    }
+}
+
+/// Experimental: runs several selection algorithms and keeps the
+/// selection of the one scoring best.
+///
+/// Each algorithm is tried on copies of the pool, of the logs and of the
+/// block being built, so the attempts leave no trace. The outcome of the
+/// best scoring algorithm, the first one in case of a tie, is then kept:
+/// the fragments it has selected are added to the block and the ones it
+/// has rejected are taken out of the pool, without running it again.
+pub struct BestOf<S> {
+    algorithms: Vec<Box<dyn FragmentSelectionAlgorithm + Send>>,
+    score: S,
+}
+
+impl<S> BestOf<S> {
+    pub fn new(score: S) -> Self {
+        BestOf {
+            algorithms: Vec::new(),
+            score,
+        }
+    }
+
+    /// add an algorithm to compare with the other ones
+    pub fn candidate<A>(mut self, algorithm: A) -> Self
+    where
+        A: FragmentSelectionAlgorithm + Send + 'static,
+    {
+        self.algorithms.push(Box::new(algorithm));
+        self
+    }
+}
+
+impl<S: SelectionScore> FragmentSelectionAlgorithm for BestOf<S> {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let mut best: Option<(u64, Pool, Logs, Selection)> = None;
+
+        for algorithm in self.algorithms.iter_mut() {
+            let mut trial_pool = pool.trial_copy();
+            let mut trial_logs = logs.trial_copy(pool.entries.keys());
+            let mut trial = selection.fork();
+            algorithm.select(
+                ledger_params,
+                metadata,
+                &mut trial_logs,
+                &mut trial_pool,
+                &mut trial,
+            );
+            let score = self.score.score(&trial);
+            if best
+                .as_ref()
+                .map_or(true, |(best_score, _, _, _)| score > *best_score)
+            {
+                best = Some((score, trial_pool, trial_logs, trial));
+            }
+        }
+
+        if let Some((_, trial_pool, trial_logs, trial)) = best {
+            selection.adopt(trial, &trial_pool, &trial_logs, pool, logs);
+        }
+    }
+}
+
+/// Limits the number of fragments each account can contribute to the
+/// block selected by the wrapped algorithm.
+///
+/// Once an account has spent from that many fragments of the block, its
+/// other fragments are left pending in the pool.
+pub struct MaxPerAccount<A> {
+    algorithm: A,
+    max_per_account: usize,
+}
+
+impl<A> MaxPerAccount<A> {
+    pub fn new(algorithm: A, max_per_account: usize) -> Self {
+        MaxPerAccount {
+            algorithm,
+            max_per_account,
+        }
+    }
+}
+
+impl<A: FragmentSelectionAlgorithm> FragmentSelectionAlgorithm for MaxPerAccount<A> {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let outer_max = selection.max_per_account;
+        selection.max_per_account = Some(outer_max.map_or(self.max_per_account, |outer| {
+            cmp::min(outer, self.max_per_account)
+        }));
+
+        self.algorithm
+            .select(ledger_params, metadata, logs, pool, selection);
+
+        selection.max_per_account = outer_max;
+    }
+}
+
+/// Limits the number of certificate fragments in the block selected by
+/// the wrapped algorithm.
+///
+/// The certificates are heavier to validate than the transfers, the limit
+/// bounds the time to build the block. Once it is reached, the other
+/// certificates are left pending in the pool while the block is still
+/// filled with the other fragments.
+pub struct MaxCertificates<A> {
+    algorithm: A,
+    max_certificates: usize,
+}
+
+impl<A> MaxCertificates<A> {
+    pub fn new(algorithm: A, max_certificates: usize) -> Self {
+        MaxCertificates {
+            algorithm,
+            max_certificates,
+        }
+    }
+}
+
+impl<A: FragmentSelectionAlgorithm> FragmentSelectionAlgorithm for MaxCertificates<A> {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let outer_max = selection.max_certificates;
+        selection.max_certificates = Some(outer_max.map_or(self.max_certificates, |outer| {
+            cmp::min(outer, self.max_certificates)
+        }));
+
+        self.algorithm
+            .select(ledger_params, metadata, logs, pool, selection);
+
+        selection.max_certificates = outer_max;
+    }
+}
+
+/// Rejects the fragments paying less than a minimum fee before running
+/// the wrapped algorithm, even if there is room left in the block.
+///
+/// This is a local policy of the node operator, to discourage spam, and
+/// may be stricter than the minimum fee enforced by the ledger: the
+/// fragments rejected here may still be included in a block created by
+/// another node. Only the transactions and the certificates are checked,
+/// the other fragments pay no fee.
+pub struct MinFee<A> {
+    algorithm: A,
+    min_fee: Value,
+}
+
+impl<A> MinFee<A> {
+    pub fn new(algorithm: A, min_fee: Value) -> Self {
+        MinFee { algorithm, min_fee }
+    }
+}
+
+impl<A: FragmentSelectionAlgorithm> FragmentSelectionAlgorithm for MinFee<A> {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let below_min_fee = pool
+            .entries_by_time
+            .iter()
+            .filter(|id| {
+                let (entry, fragment, _) = &pool.entries[id];
+                pays_fee(fragment) && entry.fragment_fee().0 < self.min_fee.0
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in below_min_fee {
+            pool.remove(&id);
+            selection.audit(&id, AuditDecision::SkippedFee);
+            logs.modify(
+                &id,
+                Status::Rejected {
+                    code: RejectionCode::BelowMinimumFee,
+                    reason: "below minimum fee".to_owned(),
+                },
+            );
+        }
+
+        self.algorithm
+            .select(ledger_params, metadata, logs, pool, selection);
+    }
+}
+
+/// Rejects the fragments with more inputs or outputs than the limits
+/// before running the wrapped algorithm, without applying them to the
+/// ledger.
+///
+/// This is a local policy of the node operator, to keep a single large
+/// transaction from slowing down the creation of the block.
+pub struct MaxInputsOutputs<A> {
+    algorithm: A,
+    max_inputs: Option<usize>,
+    max_outputs: Option<usize>,
+}
+
+impl<A> MaxInputsOutputs<A> {
+    pub fn new(algorithm: A, max_inputs: Option<usize>, max_outputs: Option<usize>) -> Self {
+        MaxInputsOutputs {
+            algorithm,
+            max_inputs,
+            max_outputs,
+        }
+    }
+}
+
+impl<A: FragmentSelectionAlgorithm> FragmentSelectionAlgorithm for MaxInputsOutputs<A> {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let exceeds = |count: usize, max: Option<usize>| max.map_or(false, |max| count > max);
+        let too_large = pool
+            .entries_by_time
+            .iter()
+            .filter(|id| {
+                let (inputs, outputs) = input_output_counts(&pool.entries[id].1);
+                exceeds(inputs, self.max_inputs) || exceeds(outputs, self.max_outputs)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in too_large {
+            pool.remove(&id);
+            selection.audit(&id, AuditDecision::TooManyInputsOutputs);
+            logs.modify(
+                &id,
+                Status::Rejected {
+                    code: RejectionCode::TooManyInputsOutputs,
+                    reason: "too many inputs or outputs".to_owned(),
+                },
+            );
+        }
+
+        self.algorithm
+            .select(ledger_params, metadata, logs, pool, selection);
+    }
+}
+
+/// Limits the total size, in bytes, of the fragments of the block
+/// selected by the wrapped algorithm.
+///
+/// The fragments that would exceed the budget are left pending in the
+/// pool and reported in the `overflow` of the selection.
+pub struct MaxBlockSize<A> {
+    algorithm: A,
+    max_size: usize,
+}
+
+impl<A> MaxBlockSize<A> {
+    pub fn new(algorithm: A, max_size: usize) -> Self {
+        MaxBlockSize {
+            algorithm,
+            max_size,
+        }
+    }
+}
+
+impl<A: FragmentSelectionAlgorithm> FragmentSelectionAlgorithm for MaxBlockSize<A> {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let outer_max = selection.max_size;
+        let max_size = selection.size + self.max_size;
+        selection.max_size = Some(outer_max.map_or(max_size, |outer| cmp::min(outer, max_size)));
+
+        self.algorithm
+            .select(ledger_params, metadata, logs, pool, selection);
+
+        selection.max_size = outer_max;
+    }
+}
+
+/// A predicate of the node operator accepting a fragment in the block,
+/// given the ledger state resulting from the fragments selected so far.
+pub type AcceptancePredicate = Arc<dyn Fn(&Fragment, &Ledger) -> bool + Send + Sync>;
+
+/// What becomes of the fragments refused by an acceptance predicate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Refusal {
+    /// the fragment stays pending in the pool, it may be accepted in
+    /// a later block
+    LeavePending,
+    /// the fragment is removed from the pool and its status becomes
+    /// rejected
+    Reject,
+}
+
+#[derive(Clone)]
+struct AcceptancePolicy {
+    predicate: AcceptancePredicate,
+    refusal: Refusal,
+}
+
+/// Checks the fragments selected by the wrapped algorithm against
+/// a predicate of the node operator, before they are applied to the
+/// ledger.
+///
+/// This lets a node implement bespoke policies without changing the
+/// selection algorithms. The fragments refused by the predicate are
+/// skipped and, depending on the `Refusal`, left pending in the pool or
+/// rejected once the wrapped algorithm is done.
+pub struct WithAcceptance<A> {
+    algorithm: A,
+    policy: AcceptancePolicy,
+}
+
+impl<A> WithAcceptance<A> {
+    pub fn new(algorithm: A, predicate: AcceptancePredicate, refusal: Refusal) -> Self {
+        WithAcceptance {
+            algorithm,
+            policy: AcceptancePolicy { predicate, refusal },
+        }
+    }
+}
+
+impl<A: FragmentSelectionAlgorithm> FragmentSelectionAlgorithm for WithAcceptance<A> {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        selection.policies.push(self.policy.clone());
+
+        self.algorithm
+            .select(ledger_params, metadata, logs, pool, selection);
+
+        selection.policies.pop();
+
+        let refused = std::mem::replace(&mut selection.refused, Vec::new());
+        for id in refused {
+            if pool.remove(&id).is_none() {
+                continue;
+            }
+            logs.modify(
+                &id,
+                Status::Rejected {
+                    code: RejectionCode::RefusedByPolicy,
+                    reason: "refused by the acceptance policy of the node".to_owned(),
+                },
+            );
+        }
+    }
+}
+
+custom_error! {pub MandatoryFragmentError
+    NotInPool { fragment_id: FragmentId } = "mandatory fragment {fragment_id} is not pending in the pool",
+    Rejected { fragment_id: FragmentId }
+        = "mandatory fragment {fragment_id} cannot be applied to the ledger, see its status",
+}
+
+/// Adds the given fragments to the block first, in the given order, before
+/// the wrapped algorithm fills the rest of the block.
+///
+/// The fragments are required by the node operator, so they bypass the
+/// constraints and budgets of the selection. A fragment that is not in the
+/// pool or that fails to apply is not skipped silently, it is reported in
+/// the `mandatory_errors` of the selection.
+pub struct MustInclude<A> {
+    algorithm: A,
+    must_include: Vec<FragmentId>,
+}
+
+impl<A> MustInclude<A> {
+    pub fn new(algorithm: A, must_include: Vec<FragmentId>) -> Self {
+        MustInclude {
+            algorithm,
+            must_include,
+        }
+    }
+}
+
+impl<A: FragmentSelectionAlgorithm> FragmentSelectionAlgorithm for MustInclude<A> {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        for id in &self.must_include {
+            let error = match pool.remove(id) {
+                None => MandatoryFragmentError::NotInPool {
+                    fragment_id: id.clone(),
+                },
+                Some(fragment) => {
+                    if selection.try_add(id, fragment, ledger_params, metadata, logs) {
+                        continue;
+                    }
+                    MandatoryFragmentError::Rejected {
+                        fragment_id: id.clone(),
+                    }
+                }
+            };
+            selection.mandatory_errors.push(error);
+        }
+
+        self.algorithm
+            .select(ledger_params, metadata, logs, pool, selection);
+    }
+}
+
+/// Pairs of fragments where one fragment must not be included in a block
+/// before the other, e.g. fragments depending on a certificate being
+/// revoked by another fragment.
+#[derive(Clone, Debug, Default)]
+pub struct OrderingConstraints {
+    // for each dependent fragment, the fragments it must follow
+    prerequisites: HashMap<FragmentId, HashSet<FragmentId>>,
+}
+
+impl OrderingConstraints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `dependent` must not be included before `prerequisite`
+    pub fn add(&mut self, dependent: FragmentId, prerequisite: FragmentId) {
+        self.prerequisites
+            .entry(dependent)
+            .or_insert_with(HashSet::new)
+            .insert(prerequisite);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prerequisites.is_empty()
+    }
+
+    fn prerequisites_of<'a>(&'a self, id: &FragmentId) -> impl Iterator<Item = &'a FragmentId> {
+        self.prerequisites.get(id).into_iter().flatten()
+    }
+
+    // only the prerequisites pending in the pool can hold back a
+    // fragment, the other ones are either in the chain already or
+    // will never be included
+    fn pending_in(&self, pool: &Pool) -> Self {
+        let prerequisites = self
+            .prerequisites
+            .iter()
+            .filter_map(|(dependent, prerequisites)| {
+                let pending = prerequisites
+                    .iter()
+                    .filter(|id| pool.entries.contains_key(id))
+                    .cloned()
+                    .collect::<HashSet<_>>();
+                if pending.is_empty() {
+                    None
+                } else {
+                    Some((dependent.clone(), pending))
+                }
+            })
+            .collect();
+        OrderingConstraints { prerequisites }
+    }
+}
+
+/// Defers the fragments selected by the wrapped algorithm that must
+/// follow fragments not selected yet.
+///
+/// Once the wrapped algorithm is done, the deferred fragments whose
+/// prerequisites have all been selected are tried again, oldest first.
+/// The other ones are left pending in the pool.
+pub struct WithOrderingConstraints<A> {
+    algorithm: A,
+    constraints: OrderingConstraints,
+}
+
+impl<A> WithOrderingConstraints<A> {
+    pub fn new(algorithm: A, constraints: OrderingConstraints) -> Self {
+        WithOrderingConstraints {
+            algorithm,
+            constraints,
+        }
+    }
+}
+
+impl<A: FragmentSelectionAlgorithm> FragmentSelectionAlgorithm for WithOrderingConstraints<A> {
+    fn select(
+        &mut self,
+        ledger_params: &LedgerParameters,
+        metadata: &HeaderContentEvalContext,
+        logs: &mut Logs,
+        pool: &mut Pool,
+        selection: &mut Selection,
+    ) {
+        let constraints = self.constraints.pending_in(pool);
+        if constraints.is_empty() {
+            self.algorithm
+                .select(ledger_params, metadata, logs, pool, selection);
+            return;
+        }
+
+        let outer_constraints = std::mem::replace(&mut selection.constraints, constraints);
+
+        self.algorithm
+            .select(ledger_params, metadata, logs, pool, selection);
+
+        // the prerequisites selected since may release deferred fragments,
+        // which may in turn release other ones
+        let mut added_any = true;
+        while added_any && !selection.is_full() {
+            added_any = false;
+            let deferred = pool
+                .entries_by_time
+                .iter()
+                .filter(|id| selection.constraints.prerequisites.contains_key(id))
+                .cloned()
+                .collect::<Vec<_>>();
+            for id in deferred {
+                if selection.is_full() {
+                    break;
+                }
+                if !selection.can_add(&pool.entries[&id].1) {
+                    continue;
+                }
+                let fragment = pool.remove(&id).unwrap();
+                if selection.try_add(&id, fragment, ledger_params, metadata, logs) {
+                    added_any = true;
+                }
+            }
+        }
+
+        selection.constraints = outer_constraints;
+    }
+}
+
+/// The fragment selection algorithms that can be chosen in the settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionStrategy {
+    OldestFirst,
+    HighestFee,
+    EarliestExpiryFirst,
+    LargestFirst,
+    /// requires the `fee_tiers` of the parameters, see `FeeTiered`
+    FeeTiered,
+    /// the `min_fee` of the parameters applies to the packages, see
+    /// `DependencyPackages`
+    DependencyPackages,
+    /// requires the `weights` of the parameters, see `WeightedSelection`
+    Weighted,
+    /// requires the `best_of` of the parameters, see `BestOf`
+    BestOf,
+    /// only available with the `testing` feature, see `ByFragmentId`
+    #[cfg(feature = "testing")]
+    ByFragmentId,
+}
+
+impl Default for SelectionStrategy {
+    fn default() -> Self {
+        SelectionStrategy::OldestFirst
+    }
+}
+
+/// The parameters of the fragment selection algorithms
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SelectionParams {
+    /// the maximum number of fragments in a block
+    pub max_per_block: usize,
+    /// the maximum number of fragments of a block spending from
+    /// the same account, if limited
+    pub max_per_account: Option<usize>,
+    /// the maximum total size, in bytes, of the fragments of a block,
+    /// if limited
+    pub max_block_size: Option<usize>,
+    /// the minimum fee of the fragments included in a block, if set by
+    /// the node operator. This is not a consensus rule.
+    pub min_fee: Option<Value>,
+    /// the maximum number of inputs of the fragments included in a block,
+    /// if set by the node operator
+    pub max_inputs: Option<usize>,
+    /// the maximum number of outputs of the fragments included in a block,
+    /// if set by the node operator
+    pub max_outputs: Option<usize>,
+    /// the maximum number of certificate fragments in a block, if limited
+    pub max_certificates_per_block: Option<usize>,
+    /// the fee tiers of the `fee_tiered` strategy
+    pub fee_tiers: Option<FeeTiers>,
+    /// the weights of the `weighted` strategy
+    pub weights: Option<SelectionWeights>,
+    /// the strategies compared by the `best_of` strategy
+    pub best_of: Option<BestOfStrategies>,
+}
+
+/// The strategies compared by the `best_of` strategy, see `BestOf`. The
+/// strategies take their parameters from the same `SelectionParams`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BestOfStrategies {
+    pub candidates: [SelectionStrategy; 2],
+    pub objective: SelectionObjective,
+}
+
+pub const DEFAULT_MAX_PER_BLOCK: usize = 250;
+
+impl Default for SelectionParams {
+    fn default() -> Self {
+        SelectionParams {
+            max_per_block: DEFAULT_MAX_PER_BLOCK,
+            max_per_account: None,
+            max_block_size: None,
+            min_fee: None,
+            max_inputs: None,
+            max_outputs: None,
+            max_certificates_per_block: None,
+            fee_tiers: None,
+            weights: None,
+            best_of: None,
+        }
+    }
+}
+
+/// The fragment selection algorithm chosen in the settings,
+/// along with its parameters
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SelectionConfig {
+    pub strategy: SelectionStrategy,
+    pub params: SelectionParams,
+    /// record the decisions taken on the fragments of each block, see
+    /// `Selection::enable_audit`
+    pub audit: bool,
+}
+
+impl SelectionConfig {
+    pub fn build(&self) -> Result<Box<dyn FragmentSelectionAlgorithm + Send>, BuildSelectorError> {
+        build_selector(self.strategy, self.params)
+    }
+}
+
+/// A shared handle on the fragment selection configuration, to change
+/// the selection of the blocks while the node is running.
+///
+/// The configuration is read once at the beginning of the preparation of
+/// each block, so a change never affects the block being prepared and
+/// applies from the next one.
+///
+/// The handle also keeps the running totals of the selections, see
+/// `record`.
+#[derive(Clone)]
+pub struct SelectionHandle {
+    config: Arc<RwLock<SelectionConfig>>,
+    totals: Arc<Mutex<SelectionTotals>>,
+}
+
+/// The totals of the selections of the blocks created by the node since
+/// it started.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SelectionTotals {
+    pub blocks_built: u64,
+    pub decisions: DecisionCounts,
+    /// the fees paid by the fragments of the blocks
+    pub fees_collected: u64,
+}
+
+impl SelectionHandle {
+    pub fn new(config: SelectionConfig) -> Self {
+        SelectionHandle {
+            config: Arc::new(RwLock::new(config)),
+            totals: Arc::new(Mutex::new(SelectionTotals::default())),
+        }
+    }
+
+    /// the configuration to select the fragments of the next block
+    pub fn current(&self) -> SelectionConfig {
+        *self.config.read().unwrap()
+    }
+
+    /// add the selection of a block to the totals
+    pub fn record(&self, selection: &Selection) {
+        let mut totals = self.totals.lock().unwrap();
+        totals.blocks_built += 1;
+        totals.decisions.add(selection.decisions());
+        totals.fees_collected = totals.fees_collected.saturating_add(selection.fees().0);
+    }
+
+    pub fn totals(&self) -> SelectionTotals {
+        self.totals.lock().unwrap().clone()
+    }
+
+    /// replace the configuration, returning the previous one. The new
+    /// configuration is checked first and left out if the selection
+    /// cannot be built.
+    pub fn replace(&self, config: SelectionConfig) -> Result<SelectionConfig, BuildSelectorError> {
+        config.build()?;
+        let mut current = self.config.write().unwrap();
+        Ok(std::mem::replace(&mut *current, config))
+    }
+}
+
+/// An estimate of the inclusion of a fragment paying a given fee, if it
+/// was submitted now, see `estimate_inclusion`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct InclusionEstimate {
+    /// the number of pending fragments to be selected before it
+    pub fragments_ahead: usize,
+    /// the total size, in bytes, of these fragments
+    pub size_ahead: usize,
+    /// the number of blocks until it is included, the next block
+    /// counting as 1
+    pub blocks: usize,
+}
+
+custom_error! {pub EstimateError
+    NotFeeAware = "the fragment selection strategy does not depend on the fee",
+    NeverSelected = "a fragment paying this fee is not selected",
+}
+
+/// estimate the number of blocks until a fragment paying `fee` would be
+/// included, given the fragments pending in the pool.
+///
+/// The fragments paying as much or more are selected before it with the
+/// `highest_fee` strategy, the fragments of the same tier with the
+/// `fee_tiered` strategy, which gets a share of each block in proportion
+/// to its weight. The blocks are assumed to be filled up to `max_per_block`
+/// and `max_block_size`; the fragments received later and the other limits
+/// of the selection are not accounted for, so this is only a guidance.
+pub fn estimate_inclusion(
+    config: &SelectionConfig,
+    pool: &Pool,
+    fee: Value,
+) -> Result<InclusionEstimate, EstimateError> {
+    let params = &config.params;
+    let min_fee = params.min_fee.map_or(0, |min_fee| min_fee.0);
+    if fee.0 < min_fee {
+        return Err(EstimateError::NeverSelected);
+    }
+    let pending = pool.entries_by_time.iter().filter_map(|id| {
+        let entry = &pool.entries[id].0;
+        let pending_fee = entry.fragment_fee().0;
+        if pending_fee < min_fee {
+            None
+        } else {
+            Some((pending_fee, *entry.fragment_size()))
+        }
+    });
+
+    // the pending fragments ahead, and the share of each block
+    // they are selected in
+    let (ahead, weight, total_weight) = match config.strategy {
+        SelectionStrategy::HighestFee => (
+            pending
+                .filter(|(pending_fee, _)| *pending_fee >= fee.0)
+                .collect::<Vec<_>>(),
+            1,
+            1,
+        ),
+        SelectionStrategy::FeeTiered => {
+            let tiers = params.fee_tiers.ok_or(EstimateError::NotFeeAware)?;
+            let tier = tiers.tier_of(fee.0);
+            if tiers.weights[tier] == 0 {
+                return Err(EstimateError::NeverSelected);
+            }
+            let mut pending_tiers = [false; 3];
+            pending_tiers[tier] = true;
+            let mut ahead = Vec::new();
+            for (pending_fee, size) in pending {
+                let pending_tier = tiers.tier_of(pending_fee);
+                pending_tiers[pending_tier] = true;
+                if pending_tier == tier {
+                    ahead.push((pending_fee, size));
+                }
+            }
+            // the tiers with no pending fragment leave their share
+            // to the other ones
+            let total_weight = pending_tiers
+                .iter()
+                .zip(tiers.weights.iter())
+                .filter(|(pending, _)| **pending)
+                .map(|(_, weight)| *weight)
+                .sum::<usize>();
+            (ahead, tiers.weights[tier], total_weight)
+        }
+        _ => return Err(EstimateError::NotFeeAware),
+    };
+
+    let fragments_ahead = ahead.len();
+    let size_ahead = ahead.iter().map(|(_, size)| size).sum::<usize>();
+
+    let per_block = cmp::max(1, params.max_per_block * weight / total_weight);
+    let mut blocks = fragments_ahead / per_block + 1;
+    if let Some(max_size) = params.max_block_size {
+        let size_per_block = cmp::max(1, max_size * weight / total_weight);
+        blocks = cmp::max(blocks, size_ahead / size_per_block + 1);
+    }
+
+    Ok(InclusionEstimate {
+        fragments_ahead,
+        size_ahead,
+        blocks,
+    })
+}
+
+custom_error! {pub BuildSelectorError
+    NoFragmentPerBlock = "the maximum number of fragments per block cannot be 0",
+    NoFragmentPerAccount = "the maximum number of fragments per account cannot be 0",
+    NoBlockSize = "the maximum size of the fragments of a block cannot be 0",
+    NoInputPerFragment = "the maximum number of inputs of a fragment cannot be 0",
+    NoOutputPerFragment = "the maximum number of outputs of a fragment cannot be 0",
+    NoFeeTiers = "the fee_tiered strategy requires the fee tiers",
+    InvalidFeeTiers = "the medium fee tier cannot be above the high one",
+    NoFeeTierWeight = "at least one fee tier must have a weight",
+    NoSelectionWeights = "the weighted strategy requires the selection weights",
+    InvalidSelectionWeights = "the selection weights must be finite numbers",
+    NoBestOfStrategies = "the best_of strategy requires the strategies to compare",
+    NestedBestOf = "the best_of strategy cannot compare itself",
+}
+
+// the algorithm of the strategy, without the limits of the parameters
+fn build_strategy(
+    strategy: SelectionStrategy,
+    params: &SelectionParams,
+) -> Result<Box<dyn FragmentSelectionAlgorithm + Send>, BuildSelectorError> {
+    let max_per_block = params.max_per_block;
+    let algorithm: Box<dyn FragmentSelectionAlgorithm + Send> = match strategy {
+        SelectionStrategy::OldestFirst => Box::new(OldestFirst::new(max_per_block)),
+        SelectionStrategy::HighestFee => Box::new(HighestFee::new(max_per_block)),
+        SelectionStrategy::EarliestExpiryFirst => Box::new(EarliestExpiryFirst::new(max_per_block)),
+        SelectionStrategy::LargestFirst => Box::new(LargestFirst::new(max_per_block)),
+        SelectionStrategy::FeeTiered => match params.fee_tiers {
+            None => return Err(BuildSelectorError::NoFeeTiers),
+            Some(tiers) if tiers.medium_fee > tiers.high_fee => {
+                return Err(BuildSelectorError::InvalidFeeTiers)
+            }
+            Some(tiers) if tiers.weights.iter().all(|weight| *weight == 0) => {
+                return Err(BuildSelectorError::NoFeeTierWeight)
+            }
+            Some(tiers) => Box::new(FeeTiered::new(max_per_block, tiers)),
+        },
+        SelectionStrategy::DependencyPackages => Box::new(DependencyPackages::new(
+            max_per_block,
+            params.min_fee.unwrap_or_else(Value::zero),
+        )),
+        SelectionStrategy::Weighted => match params.weights {
+            None => return Err(BuildSelectorError::NoSelectionWeights),
+            Some(weights) if !weights.is_finite() => {
+                return Err(BuildSelectorError::InvalidSelectionWeights)
+            }
+            Some(weights) => Box::new(WeightedSelection::new(
+                max_per_block,
+                weights.type_weights(),
+                weights.age,
+                weights.fee,
+            )),
+        },
+        SelectionStrategy::BestOf => match params.best_of {
+            None => return Err(BuildSelectorError::NoBestOfStrategies),
+            Some(best_of) => {
+                let mut algorithm = BestOf::new(best_of.objective);
+                for candidate in best_of.candidates.iter() {
+                    if *candidate == SelectionStrategy::BestOf {
+                        return Err(BuildSelectorError::NestedBestOf);
+                    }
+                    algorithm = algorithm.candidate(build_strategy(*candidate, params)?);
+                }
+                Box::new(algorithm)
+            }
+        },
+        #[cfg(feature = "testing")]
+        SelectionStrategy::ByFragmentId => Box::new(ByFragmentId::new(max_per_block)),
+    };
+    Ok(algorithm)
+}
+
+/// build the fragment selection algorithm for the given strategy
+pub fn build_selector(
+    strategy: SelectionStrategy,
+    params: SelectionParams,
+) -> Result<Box<dyn FragmentSelectionAlgorithm + Send>, BuildSelectorError> {
+    if params.max_per_block == 0 {
+        return Err(BuildSelectorError::NoFragmentPerBlock);
+    }
+
+    let algorithm = build_strategy(strategy, &params)?;
+
+    let algorithm: Box<dyn FragmentSelectionAlgorithm + Send> = match params.max_per_account {
+        None => algorithm,
+        Some(0) => return Err(BuildSelectorError::NoFragmentPerAccount),
+        Some(max_per_account) => Box::new(MaxPerAccount::new(algorithm, max_per_account)),
+    };
+
+    let algorithm: Box<dyn FragmentSelectionAlgorithm + Send> = match params.max_block_size {
+        None => algorithm,
+        Some(0) => return Err(BuildSelectorError::NoBlockSize),
+        Some(max_size) => Box::new(MaxBlockSize::new(algorithm, max_size)),
+    };
+
+    // a limit of 0 keeps the certificates out of the blocks
+    let algorithm: Box<dyn FragmentSelectionAlgorithm + Send> =
+        match params.max_certificates_per_block {
+            None => algorithm,
+            Some(max_certificates) => Box::new(MaxCertificates::new(algorithm, max_certificates)),
+        };
+
+    let algorithm: Box<dyn FragmentSelectionAlgorithm + Send> =
+        match (params.max_inputs, params.max_outputs) {
+            (None, None) => algorithm,
+            (Some(0), _) => return Err(BuildSelectorError::NoInputPerFragment),
+            (_, Some(0)) => return Err(BuildSelectorError::NoOutputPerFragment),
+            (max_inputs, max_outputs) => {
+                Box::new(MaxInputsOutputs::new(algorithm, max_inputs, max_outputs))
+            }
+        };
+
+    match params.min_fee {
+        // the packages are checked against the minimum fee as a whole
+        _ if strategy == SelectionStrategy::DependencyPackages => Ok(algorithm),
+        None => Ok(algorithm),
+        Some(min_fee) => Ok(Box::new(MinFee::new(algorithm, min_fee))),
+    }
+}
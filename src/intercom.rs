@@ -1,6 +1,9 @@
-use crate::blockcfg::{Block, Header, HeaderHash, Message, MessageId};
+use crate::blockcfg::{Block, BlockDate, Header, HeaderHash, Message, MessageId};
 use crate::fragment;
-use crate::network::p2p::topology::NodeId;
+use crate::network::{
+    p2p::{comm::SubscriptionRejected, topology::NodeId},
+    NetworkStats,
+};
 use futures::prelude::*;
 use futures::sync::{mpsc, oneshot};
 use network_core::error as core_error;
@@ -65,6 +68,19 @@ impl From<chain_storage::error::Error> for Error {
     }
 }
 
+impl From<SubscriptionRejected> for Error {
+    fn from(err: SubscriptionRejected) -> Self {
+        let code = match err {
+            // the peer may subscribe again once a slot is free
+            SubscriptionRejected::CapacityReached { .. } => core_error::Code::ResourceExhausted,
+        };
+        Error {
+            code,
+            cause: err.into(),
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         Display::fmt(&self.cause, f)
@@ -218,6 +234,15 @@ where
 pub enum TransactionMsg {
     ProposeTransaction(Vec<MessageId>, ReplyHandle<Vec<bool>>),
     SendTransaction(fragment::Origin, Vec<Message>),
+    /// a fragment submitted by a client, to remove from the pool if not
+    /// included in a block by the block date if given. The reply tells
+    /// whether the fragment has entered the pool.
+    SubmitTransaction(
+        fragment::Origin,
+        Message,
+        Option<BlockDate>,
+        ReplyHandle<Result<(), fragment::PoolInsertError>>,
+    ),
     GetTransactions(Vec<MessageId>, ReplyStreamHandle<Message>),
 }
 
@@ -279,8 +304,18 @@ pub enum BlockMsg {
     LeadershipExpectEndOfEpoch,
     /// An untrusted Block has been received from the network task
     NetworkBlock(Block),
+    /// A Block from the network task has been validated by the
+    /// validation pool: the block submitted with `seq` along with
+    /// whether its header has been verified, `None` if rejected
+    ValidatedNetworkBlock {
+        seq: u64,
+        validated: Option<(Block, bool)>,
+    },
     /// A untrusted block Header has been received from the network task
     AnnouncedBlock(Header, NodeId),
+    /// Stop accepting new blocks, replied once the messages received
+    /// before are processed
+    Shutdown(ReplyHandle<()>),
 }
 
 /// Propagation requests for the network task.
@@ -291,10 +326,15 @@ pub enum PropagateMsg {
 }
 
 /// Messages to the network task.
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum NetworkMsg {
     Propagate(PropagateMsg),
     GetBlocks(NodeId, Vec<HeaderHash>),
+    /// Flush the propagations received before to the peers and close
+    /// their subscriptions, replied once done
+    Shutdown(ReplyHandle<()>),
+    /// The statistics of the peers of the node
+    GetStats(ReplyHandle<NetworkStats>),
 }
 
 #[cfg(test)]
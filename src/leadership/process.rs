@@ -1,7 +1,7 @@
 use crate::{
     blockcfg::{BlockDate, Epoch},
     blockchain::Tip,
-    fragment::Pool,
+    fragment::{selection::SelectionHandle, Pool},
     intercom::BlockMsg,
     leadership::{EpochParameters, Leadership, Task, TaskParameters},
     secure::enclave::{Enclave, LeaderId},
@@ -31,6 +31,7 @@ pub struct Process {
     service_info: TokioServiceInfo,
 
     fragment_pool: Pool,
+    fragment_selection: SelectionHandle,
     blockchain_tip: Tip,
 
     block_message_box: MessageBox<BlockMsg>,
@@ -46,6 +47,7 @@ impl Process {
     pub fn new(
         service_info: TokioServiceInfo,
         fragment_pool: Pool,
+        fragment_selection: SelectionHandle,
         blockchain_tip: Tip,
         block_message_box: MessageBox<BlockMsg>,
     ) -> Self {
@@ -56,6 +58,7 @@ impl Process {
         Process {
             service_info,
             fragment_pool,
+            fragment_selection,
             blockchain_tip,
             block_message_box,
             epoch_broadcaster,
@@ -112,6 +115,7 @@ impl Process {
             enclave,
             blockchain_tip,
             fragment_pool,
+            self.fragment_selection.clone(),
             epoch_receiver,
             block_message,
         );
@@ -4,7 +4,10 @@ use crate::{
         LeaderOutput, Ledger,
     },
     blockchain::Tip,
-    fragment::Pool,
+    fragment::{
+        selection::{AuditEntry, MandatoryFragmentError, SelectionHandle},
+        FragmentId, Pool,
+    },
     intercom::BlockMsg,
     leadership::{LeaderSchedule, Leadership},
     secure::enclave::{Enclave, LeaderId},
@@ -38,6 +41,7 @@ pub struct Task {
     blockchain_tip: Tip,
     epoch_receiver: watch::Receiver<Option<TaskParameters>>,
     fragment_pool: Pool,
+    fragment_selection: SelectionHandle,
     block_message: MessageBox<BlockMsg>,
 }
 
@@ -49,6 +53,7 @@ impl Task {
         enclave: Enclave,
         blockchain_tip: Tip,
         fragment_pool: Pool,
+        fragment_selection: SelectionHandle,
         epoch_receiver: watch::Receiver<Option<TaskParameters>>,
         block_message: MessageBox<BlockMsg>,
     ) -> Self {
@@ -66,6 +71,7 @@ impl Task {
             enclave: enclave,
             blockchain_tip,
             fragment_pool,
+            fragment_selection,
             epoch_receiver,
             block_message,
         }
@@ -78,6 +84,7 @@ impl Task {
         let enclave = self.enclave;
         let blockchain_tip = self.blockchain_tip;
         let fragment_pool = self.fragment_pool;
+        let fragment_selection = self.fragment_selection;
         let block_message = self.block_message;
 
         self.epoch_receiver
@@ -94,6 +101,7 @@ impl Task {
                     handle_logger.clone(),
                     blockchain_tip.clone(),
                     fragment_pool.clone(),
+                    fragment_selection.clone(),
                     task_parameters,
                 )
                 .map_err(|error| {
@@ -116,6 +124,7 @@ fn handle_leadership(
     logger: Logger,
     blockchain_tip: Tip,
     mut fragment_pool: Pool,
+    fragment_selection: SelectionHandle,
     task_parameters: TaskParameters,
 ) -> impl Future<Item = (), Error = HandleLeadershipError> {
     let schedule = LeaderSchedule::new(logger.clone(), &leader_id, &enclave, &task_parameters);
@@ -130,8 +139,9 @@ fn handle_leadership(
                 "scheduled_at_date" => format!("{}", scheduled_event.leader_output.date),
             );
 
-            let block = prepare_block(
+            let (block, remaining, overflow, audit, mandatory_errors) = prepare_block(
                 &mut fragment_pool,
+                &fragment_selection,
                 blockchain_tip.ledger().unwrap().clone(),
                 &task_parameters.leadership,
                 scheduled_event.leader_output.date,
@@ -139,6 +149,31 @@ fn handle_leadership(
                 blockchain_tip.hash().unwrap(),
             );
 
+            debug!(logger, "block prepared";
+                "pending_fragments" => remaining,
+                "overflow_fragments" => overflow.len(),
+            );
+            if !overflow.is_empty() {
+                // these fragments may be included by raising the
+                // block size limit
+                info!(logger, "fragments left pending, exceeding the block size limit";
+                    "fragments" => overflow
+                        .iter()
+                        .map(|id| id.to_string())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                );
+            }
+            // the operator required these fragments in this very block
+            for error in &mandatory_errors {
+                error!(logger, "mandatory fragment not included in the block";
+                    "reason" => error.to_string(),
+                );
+            }
+            if let Some(audit) = audit {
+                log_selection_audit(&logger, &audit);
+            }
+
             let block = enclave.create_block(block, scheduled_event.leader_output);
 
             block_message
@@ -151,29 +186,75 @@ fn handle_leadership(
 
 fn prepare_block(
     fragment_pool: &mut Pool,
+    fragment_selection: &SelectionHandle,
     ledger: Ledger,
     leadership: &Leadership,
     date: BlockDate,
     chain_length: ChainLength,
     parent_id: HeaderHash,
-) -> BlockBuilder {
-    use crate::fragment::selection::{FragmentSelectionAlgorithm as _, OldestFirst};
-
-    let selection_algorithm = OldestFirst::new(250 /* TODO!! */);
-    let metadata = HeaderContentEvalContext {
+) -> (
+    BlockBuilder,
+    usize,
+    Vec<FragmentId>,
+    Option<Vec<AuditEntry>>,
+    Vec<MandatoryFragmentError>,
+) {
+    // the configuration has been checked when loading the settings or
+    // when replaced, a replacement from now on applies to the next block
+    let selection_config = fragment_selection.current();
+    let selection_algorithm = selection_config
+        .build()
+        .expect("invalid fragment selection configuration");
+    let metadata = || HeaderContentEvalContext {
         block_date: date,
         chain_length,
         nonce: None,
     };
     let ledger_params = leadership.ledger_parameters().clone();
 
-    let mut bb = fragment_pool
-        .select(ledger, metadata, ledger_params, selection_algorithm)
+    // drop the fragments that have been made invalid by the latest blocks
+    // so the selection does not try them again
+    fragment_pool
+        .revalidate(
+            parent_id.clone(),
+            ledger.clone(),
+            metadata(),
+            ledger_params.clone(),
+        )
+        .wait()
+        .unwrap();
+
+    let metadata = metadata();
+    let mut selection = fragment_pool
+        .select(
+            ledger,
+            metadata,
+            ledger_params,
+            selection_algorithm,
+            selection_config.audit,
+        )
         .wait()
-        .unwrap()
-        .finalize();
+        .unwrap();
+    fragment_selection.record(&selection);
+    let remaining = selection.remaining();
+    let overflow = selection.overflow().to_vec();
+    let mandatory_errors = selection.take_mandatory_errors();
+    let (mut bb, audit) = selection.finalize();
 
     bb.date(date).parent(parent_id).chain_length(chain_length);
 
-    bb
+    (bb, remaining, overflow, audit, mandatory_errors)
+}
+
+fn log_selection_audit(logger: &Logger, audit: &[AuditEntry]) {
+    for entry in audit {
+        info!(logger, "fragment selection audit";
+            "position" => entry.position,
+            "fragment_id" => entry.fragment_id.to_string(),
+            "decision" => format!("{:?}", entry.decision),
+            "block_fragments" => entry.count,
+            "block_size" => entry.size,
+            "block_fees" => entry.fees,
+        );
+    }
 }
@@ -17,6 +17,7 @@ extern crate chain_storage_sqlite;
 extern crate chain_time;
 extern crate clap;
 extern crate cryptoxide;
+extern crate flate2;
 #[macro_use(try_ready)]
 extern crate futures;
 extern crate generic_array;
@@ -28,6 +29,7 @@ extern crate network_grpc;
 extern crate poldercast;
 extern crate rand_chacha;
 extern crate tokio;
+extern crate tokio_signal;
 #[macro_use]
 extern crate custom_error;
 
@@ -41,7 +43,7 @@ extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 extern crate serde_yaml;
-#[macro_use(o, debug, info, warn, error, crit)]
+#[macro_use(o, trace, debug, info, warn, error, crit)]
 extern crate slog;
 extern crate slog_async;
 extern crate slog_json;
@@ -54,6 +56,7 @@ extern crate test;
 use crate::{
     blockcfg::Leader,
     blockchain::BlockchainR,
+    fragment::selection::SelectionHandle,
     rest::v0::node::stats::StatsCounter,
     secure::enclave::Enclave,
     settings::start::Settings,
@@ -75,6 +78,7 @@ pub mod network;
 pub mod rest;
 pub mod secure;
 pub mod settings;
+pub mod shutdown;
 pub mod start_up;
 pub mod state;
 pub mod utils;
@@ -96,12 +100,16 @@ pub struct BootstrappedNode {
 
 const FRAGMENT_TASK_QUEUE_LEN: usize = 1024;
 const NETWORK_TASK_QUEUE_LEN: usize = 32;
+const ORPHAN_BLOCK_QUEUE_LEN: usize = 16;
+const ORPHAN_BLOCK_MAX_RETRIES: usize = 3;
+const BLOCK_VALIDATION_QUEUE_LEN: usize = 64;
+const PRODUCED_SLOTS_LEN: usize = 64;
 
 fn start_services(bootstrapped_node: BootstrappedNode) -> Result<(), start_up::Error> {
     let mut services = Services::new(bootstrapped_node.logger.clone());
 
     // initialize the network propagation channel
-    let (mut network_msgbox, network_queue) = async_msg::channel(NETWORK_TASK_QUEUE_LEN);
+    let (network_msgbox, network_queue) = async_msg::channel(NETWORK_TASK_QUEUE_LEN);
     let (fragment_msgbox, fragment_queue) = async_msg::channel(FRAGMENT_TASK_QUEUE_LEN);
     let new_epoch_notifier = bootstrapped_node.new_epoch_notifier;
 
@@ -114,10 +122,13 @@ fn start_services(bootstrapped_node: BootstrappedNode) -> Result<(), start_up::E
         let process = fragment::Process::new(
             // TTL of a fragment in the MemPool: 1h
             Duration::from_secs(3600),
+            bootstrapped_node.settings.fragment_pool_memory_limit,
             // TTL of a MemPool log: 2h
             Duration::from_secs(3600 * 2),
             // Interval between GC pauses: 15min
             Duration::from_secs(3600 / 4),
+            // ids of the fragments recently received, not examined again
+            4096,
         );
 
         let pool = process.pool().clone();
@@ -129,26 +140,76 @@ fn start_services(bootstrapped_node: BootstrappedNode) -> Result<(), start_up::E
         (pool, logs)
     };
 
+    // the subscribers are notified when the blockchain switches branch
+    let reorgs = blockchain::ReorgNotifier::new();
+
+    // the headers of the network blocks are verified by these workers,
+    // started once the block task is running
+    let (validation, validation_workers) = blockchain::validation_pool(
+        bootstrapped_node.settings.block_validation_threads,
+        BLOCK_VALIDATION_QUEUE_LEN,
+    );
+
     let block_task = {
         let blockchain = bootstrapped_node.blockchain.clone();
-        let stats_counter = stats_counter.clone();
+        let mut state = blockchain::BlockTaskState {
+            stats_counter: stats_counter.clone(),
+            network_msg_box: network_msgbox.clone(),
+            fragment_pool: fragment_pool.clone(),
+            reorgs: reorgs.clone(),
+            orphans: blockchain::OrphanQueue::new(ORPHAN_BLOCK_QUEUE_LEN, ORPHAN_BLOCK_MAX_RETRIES),
+            validation,
+            produced: blockchain::ProducedSlots::new(PRODUCED_SLOTS_LEN),
+            confirm_readable: bootstrapped_node.settings.confirm_block_readable,
+            shutdown: blockchain::ShutdownState::Running,
+        };
+        // the extension point to act on the applied blocks, e.g. to build
+        // an index of the blocks: the hooks are registered here, there is
+        // no other place to add them
+        let block_applied_hooks = blockchain::BlockAppliedHooks::default();
         services.spawn_future_with_inputs("block", move |info, input| {
+            let on_block_applied =
+                |block: &blockcfg::Block, source| block_applied_hooks.call(block, source);
             blockchain::handle_input(
                 info,
                 &blockchain,
-                &stats_counter,
-                &mut network_msgbox,
+                &mut state,
+                // the blocks are only copied for the hooks if any
+                if block_applied_hooks.is_empty() {
+                    None
+                } else {
+                    Some(&on_block_applied)
+                },
                 input,
             );
             futures::future::ok(())
         })
     };
 
+    validation_workers.start(
+        bootstrapped_node.blockchain.clone(),
+        stats_counter.clone(),
+        block_task.clone(),
+        bootstrapped_node
+            .logger
+            .new(o!(::log::KEY_TASK => "block-validation")),
+    );
+
+    {
+        let stats_counter = stats_counter.clone();
+        let threshold = bootstrapped_node.settings.stale_tip_threshold;
+        services.spawn_future("tip-watchdog", move |info| {
+            blockchain::watch_tip(stats_counter, threshold, info.logger().clone())
+        });
+    }
+
     let client_task = {
         let blockchain = bootstrapped_node.blockchain.clone();
-        services.spawn_with_inputs("client-query", move |info, input| {
-            client::handle_input(info, &blockchain, input)
-        })
+        services.spawn_with_prioritized_inputs(
+            "client-query",
+            client::is_urgent,
+            move |info, input| client::handle_input(info, &blockchain, input),
+        )
     };
 
     {
@@ -182,17 +243,43 @@ fn start_services(bootstrapped_node: BootstrappedNode) -> Result<(), start_up::E
     let leader_secrets = leader_secrets?;
     let enclave = Enclave::from_vec(leader_secrets);
 
+    let fragment_selection = SelectionHandle::new(bootstrapped_node.settings.fragment_selection);
+
+    let shutdown = {
+        let (sequence, handle) =
+            shutdown::Shutdown::new(block_task.clone(), network_msgbox.clone());
+        services.spawn("shutdown", move |info| {
+            match sequence.run(info.logger()) {
+                // the network task runs until the process exits
+                Ok(true) => std::process::exit(0),
+                Ok(false) => {}
+                Err(e) => {
+                    error!(info.logger(), "shutdown sequence failed: {}", e);
+                    std::process::exit(1)
+                }
+            }
+        });
+        let signal_handle = handle.clone();
+        services.spawn_future("signals", move |info| {
+            shutdown::trigger_on_signals(signal_handle, info.logger().clone())
+        });
+        handle
+    };
+
     {
         let fragment_pool = fragment_pool.clone();
         let block_task = block_task.clone();
         let blockchain = bootstrapped_node.blockchain.clone();
 
+        let fragment_selection = fragment_selection.clone();
+
         let enclave = enclave.clone();
 
         services.spawn_future("leadership", move |info| {
             let process = self::leadership::Process::new(
                 info,
                 fragment_pool,
+                fragment_selection,
                 blockchain.lock_read().tip.clone(),
                 block_task,
             );
@@ -207,6 +294,11 @@ fn start_services(bootstrapped_node: BootstrappedNode) -> Result<(), start_up::E
                 stats_counter,
                 blockchain: bootstrapped_node.blockchain.clone(),
                 transaction_task: Arc::new(Mutex::new(fragment_msgbox)),
+                network_task: Arc::new(Mutex::new(network_msgbox)),
+                fragment_selection,
+                fragment_pool,
+                shutdown,
+                logger: bootstrapped_node.logger.new(o!(log::KEY_TASK => "rest")),
             };
             Some(rest::start_rest_server(&rest, context)?)
         }
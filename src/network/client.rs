@@ -1,16 +1,18 @@
 use super::{
     grpc,
     p2p::{
-        comm::{PeerComms, Subscription},
+        comm::{PeerComms, Solicitation, Subscription},
         topology,
     },
-    subscription, Channels, ConnectionState,
+    subscription, Channels, ConnectionState, GlobalStateR,
 };
 use crate::{
-    blockcfg::{Block, HeaderHash},
+    blockcfg::Block,
     intercom::{self, BlockMsg, ClientMsg},
 };
+use chain_core::property::{Block as _, Header as _};
 use futures::prelude::*;
+use futures::sync::mpsc;
 use network_core::{
     client::{block::BlockService, gossip::GossipService, P2pService},
     subscription::BlockEvent,
@@ -25,7 +27,12 @@ where
     channels: Channels,
     remote_node_id: topology::NodeId,
     block_events: S::BlockSubscription,
-    block_solicitations: Subscription<Vec<HeaderHash>>,
+    block_solicitations: Subscription<Solicitation>,
+    // The peer may return only part of the solicited blocks, the
+    // remaining ones are solicited again through this channel.
+    remainder_sender: mpsc::UnboundedSender<Solicitation>,
+    remainder_receiver: mpsc::UnboundedReceiver<Solicitation>,
+    global_state: GlobalStateR,
     logger: Logger,
 }
 
@@ -73,11 +80,17 @@ where
 
                 // Spin off processing tasks for subscriptions that can be
                 // managed with just the global state.
-                subscription::process_gossip(gossip_sub, state.global, client_logger.clone());
+                subscription::process_gossip(
+                    gossip_sub,
+                    node_id,
+                    state.global.clone(),
+                    client_logger.clone(),
+                );
 
                 // Plug the block solicitations to be handled
                 // via client requests.
                 let block_solicitations = peer_comms.subscribe_to_block_solicitations();
+                let (remainder_sender, remainder_receiver) = mpsc::unbounded();
 
                 // Resolve with the client instance and communication handles.
                 let client = Client {
@@ -86,6 +99,9 @@ where
                     remote_node_id: node_id,
                     block_events,
                     block_solicitations,
+                    remainder_sender,
+                    remainder_receiver,
+                    global_state: state.global,
                     logger: client_logger,
                 };
                 Ok((client, peer_comms))
@@ -101,12 +117,18 @@ where
     fn process_block_event(&mut self, event: BlockEvent<S::Block>) {
         match event {
             BlockEvent::Announce(header) => {
+                self.global_state
+                    .peers
+                    .record_tip(self.remote_node_id, header.chain_length());
                 self.channels
                     .block_box
                     .try_send(BlockMsg::AnnouncedBlock(header, self.remote_node_id))
                     .unwrap();
             }
-            BlockEvent::Solicit(block_ids) => {
+            BlockEvent::Solicit(mut block_ids) => {
+                self.global_state
+                    .config
+                    .limit_block_solicitation(&mut block_ids);
                 let (reply_handle, stream) = intercom::stream_reply::<
                     Block,
                     network_core::error::Error,
@@ -138,28 +160,70 @@ where
     S::GetBlocksFuture: Send + 'static,
     S::GetBlocksStream: Send + 'static,
 {
-    fn solicit_blocks(&mut self, block_ids: &[HeaderHash]) {
+    fn process_solicitation(&mut self, solicitation: Solicitation) {
+        if solicitation.is_cancelled() {
+            debug!(self.logger, "skipping cancelled block solicitation");
+        } else {
+            self.solicit_blocks(solicitation);
+        }
+    }
+
+    fn solicit_blocks(&mut self, solicitation: Solicitation) {
         let mut block_box = self.channels.block_box.clone();
         let err_logger = self.logger.clone();
         let and_then_logger = self.logger.clone();
+        let cancel_logger = self.logger.clone();
+        let handle = solicitation.handle();
+        let remainder_handle = handle.clone();
+        let remainder_sender = self.remainder_sender.clone();
+        let requested = solicitation.hashes().to_vec();
         tokio::spawn(
             self.service
-                .get_blocks(block_ids)
+                .get_blocks(solicitation.hashes())
                 .map_err(move |e| {
                     warn!(err_logger, "solicitation request GetBlocks failed: {:?}", e);
                 })
                 .and_then(move |blocks| {
                     blocks
-                        .for_each(move |block| {
+                        .take_while(move |_| {
+                            let cancelled = handle.is_cancelled();
+                            if cancelled {
+                                debug!(
+                                    cancel_logger,
+                                    "solicitation cancelled, ignoring remaining blocks"
+                                );
+                            }
+                            Ok(!cancelled)
+                        })
+                        .map(move |block| {
+                            let id = block.id();
                             block_box.try_send(BlockMsg::NetworkBlock(block)).unwrap();
-                            Ok(())
+                            id
                         })
+                        .collect()
                         .map_err(move |e| {
                             warn!(
                                 and_then_logger,
                                 "solicitation stream response to GetBlocks failed: {:?}", e
                             );
                         })
+                        .map(move |received| {
+                            // The peer may limit the number of blocks
+                            // sent per solicitation, solicit the rest again
+                            // as long as progress is made.
+                            if received.is_empty() || remainder_handle.is_cancelled() {
+                                return;
+                            }
+                            let remaining = requested
+                                .into_iter()
+                                .filter(|id| !received.contains(id))
+                                .collect::<Vec<_>>();
+                            if !remaining.is_empty() {
+                                let solicitation =
+                                    Solicitation::with_handle(remaining, &remainder_handle);
+                                let _ = remainder_sender.unbounded_send(solicitation);
+                            }
+                        })
                 }),
         );
     }
@@ -198,11 +262,18 @@ where
                     debug!(self.logger, "outbound block solicitation stream closed");
                     return Ok(().into());
                 }
-                Async::Ready(Some(block_ids)) => {
+                Async::Ready(Some(solicitation)) => {
                     streams_ready = true;
-                    self.solicit_blocks(&block_ids);
+                    self.process_solicitation(solicitation);
                 }
             }
+            // The receiver cannot terminate while the client holds
+            // the sender half.
+            if let Async::Ready(Some(solicitation)) = self.remainder_receiver.poll().unwrap() {
+                streams_ready = true;
+                debug!(self.logger, "soliciting the remaining blocks");
+                self.process_solicitation(solicitation);
+            }
             if !streams_ready {
                 return Ok(Async::NotReady);
             }
@@ -14,7 +14,12 @@ mod service;
 mod subscription;
 
 use self::p2p::{
-    comm::{PeerComms, PeerMap},
+    comm::{
+        PeerComms, PeerCommsOccupancy, PeerLimits, PeerMap, PeerMapStats, PropagationError,
+        SolicitBlocksError, Solicitation,
+    },
+    gossip_budget::{GossipBudget, OverBudget, OverBudgetCounts},
+    snapshot::PeerSnapshot,
     topology::{self, P2pTopology},
 };
 use crate::blockcfg::{Block, HeaderHash};
@@ -26,7 +31,7 @@ use crate::utils::{
     task::TaskMessageBox,
 };
 use futures::prelude::*;
-use futures::stream;
+use futures::{future, stream};
 use network_core::{
     error as core_error,
     gossip::{Gossip, Node},
@@ -37,6 +42,21 @@ use tokio::timer::Interval;
 
 type Connection = SocketAddr;
 
+// Interval between two saves of the connected peers, if configured.
+const PEER_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+// Interval between two checks of the subscription streams not consumed
+// by the peers, if an idle timeout is configured.
+const IDLE_SUBSCRIPTION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Interval between two attempts to send the gossip deferred by the
+// gossip budget, if configured so.
+const DEFERRED_GOSSIP_INTERVAL: Duration = Duration::from_secs(1);
+
+// Time given to each peer to take the pending items of its subscription
+// streams when the node shuts down.
+const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub enum BlockConfig {}
 
 /// all the different channels the network may need to talk to
@@ -59,7 +79,7 @@ impl Clone for Channels {
 /// Global state shared between all network tasks.
 pub struct GlobalState {
     pub config: Configuration,
-    pub topology: P2pTopology,
+    pub topology: Arc<P2pTopology>,
     pub node: topology::Node,
     pub peers: PeerMap,
     pub logger: Logger,
@@ -67,6 +87,19 @@ pub struct GlobalState {
 
 type GlobalStateR = Arc<GlobalState>;
 
+/// The statistics of the peers of the node, see `NetworkMsg::GetStats`
+#[derive(Debug)]
+pub struct NetworkStats {
+    pub peers: PeerMapStats,
+    /// the occupancy of the subscription streams of each peer
+    pub occupancy: Vec<(topology::NodeId, PeerCommsOccupancy)>,
+    /// the gossip not sent when due because of the gossip budget
+    pub gossip_over_budget: OverBudgetCounts,
+    /// the strikes counted against the peers having some, see
+    /// `PeerMap::add_strikes`
+    pub strikes: Vec<(topology::NodeId, u64)>,
+}
+
 impl GlobalState {
     /// the network global state
     pub fn new(config: Configuration, logger: Logger) -> Self {
@@ -84,7 +117,7 @@ impl GlobalState {
         node.add_message_subscription(topology::InterestLevel::High);
         node.add_block_subscription(topology::InterestLevel::High);
 
-        let mut topology = P2pTopology::new(node.clone(), logger.clone());
+        let mut topology = P2pTopology::new(node.clone(), config.max_gossip_age, logger.clone());
         topology.set_poldercast_modules();
         topology.add_module(topology::modules::TrustedPeers::new_with(
             config.trusted_peers.iter().cloned().map(|trusted_peer| {
@@ -92,11 +125,39 @@ impl GlobalState {
             }),
         ));
 
+        let topology = Arc::new(topology);
+        let evict_logger = logger.clone();
+        let evict_topology = topology.clone();
+        let peers = PeerMap::new(
+            config.propagation_log_policy,
+            config.occupancy_watermark,
+            PeerLimits {
+                max_peers: config.max_subscribed_peers,
+                evict_least_active: config.evict_least_active_peer,
+                eviction_warmup: config.eviction_warmup,
+                propagation_breaker_percent: config.propagation_breaker_percent,
+            },
+            config.gossip_budget,
+            move |id| {
+                debug!(evict_logger, "peer {} evicted", id);
+                evict_topology.evict_node(id);
+            },
+            logger.clone(),
+        )
+        .with_pinned_peers(
+            config
+                .trusted_peers
+                .iter()
+                .filter(|_| config.pin_trusted_peers)
+                .map(|trusted_peer| trusted_peer.id),
+        )
+        .with_gossip_compression(config.gossip_compression);
+
         GlobalState {
             config,
             topology,
             node,
-            peers: PeerMap::new(logger.clone()),
+            peers,
             logger,
         }
     }
@@ -158,11 +219,18 @@ pub fn run(
         unimplemented!()
     };
 
-    let addrs = global_state
+    // reconnect first to the peers we were connected to before
+    // the node was restarted
+    let mut addrs = load_peer_snapshot(&global_state.config, &logger);
+    for addr in global_state
         .topology
         .view()
         .filter_map(|paddr| paddr.address())
-        .collect::<Vec<_>>();
+    {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
     let state = global_state.clone();
     let conn_channels = channels.clone();
     let connections = stream::iter_ok(addrs).for_each(move |addr| {
@@ -174,7 +242,12 @@ pub fn run(
             // TODO
             let node_id = client.remote_node_id();
             let gossip = Gossip::from_nodes(iter::once(state.node.clone()));
-            match comms.try_send_gossip(gossip) {
+            let res = match state.peers.admit_gossip(node_id, gossip) {
+                Some(gossip) => comms.try_send_gossip(gossip),
+                // dropped or deferred by the gossip budget
+                None => Ok(()),
+            };
+            match res {
                 Ok(()) => state.peers.insert_peer(node_id, comms),
                 Err(e) => {
                     warn!(
@@ -188,6 +261,54 @@ pub fn run(
 
     let handle_cmds = handle_network_input(input, global_state.clone(), channels.clone());
 
+    let snapshot_state = global_state.clone();
+    let snapshot_err_logger = logger.clone();
+    let snapshot = Interval::new_interval(PEER_SNAPSHOT_INTERVAL)
+        .map_err(move |e| {
+            error!(snapshot_err_logger, "interval timer error: {:?}", e);
+        })
+        .for_each(move |_| {
+            save_peer_snapshot(&snapshot_state);
+            Ok(())
+        });
+
+    let idle_check = match global_state.config.subscription_idle_timeout {
+        Some(timeout) => {
+            let idle_state = global_state.clone();
+            let idle_err_logger = logger.clone();
+            let check = Interval::new_interval(IDLE_SUBSCRIPTION_CHECK_INTERVAL)
+                .map_err(move |e| {
+                    error!(idle_err_logger, "interval timer error: {:?}", e);
+                })
+                .for_each(move |_| {
+                    idle_state.peers.close_idle_subscriptions(timeout);
+                    Ok(())
+                });
+            future::Either::A(check)
+        }
+        None => future::Either::B(future::ok(())),
+    };
+
+    let deferred_gossip = match global_state.config.gossip_budget {
+        Some(GossipBudget {
+            over_budget: OverBudget::Defer,
+            ..
+        }) => {
+            let deferred_state = global_state.clone();
+            let deferred_err_logger = logger.clone();
+            let retry = Interval::new_interval(DEFERRED_GOSSIP_INTERVAL)
+                .map_err(move |e| {
+                    error!(deferred_err_logger, "interval timer error: {:?}", e);
+                })
+                .for_each(move |_| {
+                    deferred_state.peers.send_deferred_gossip();
+                    Ok(())
+                });
+            future::Either::A(retry)
+        }
+        _ => future::Either::B(future::ok(())),
+    };
+
     let gossip_err_logger = logger.clone();
     // TODO: get gossip propagation interval from configuration
     let gossip = Interval::new_interval(Duration::from_secs(10))
@@ -199,7 +320,49 @@ pub fn run(
             Ok(())
         });
 
-    tokio::run(listener.join4(connections, handle_cmds, gossip).map(|_| ()));
+    tokio::run(
+        listener
+            .join5(connections, handle_cmds, gossip, snapshot)
+            .join3(idle_check, deferred_gossip)
+            .map(|_| ()),
+    );
+}
+
+fn load_peer_snapshot(config: &Configuration, logger: &Logger) -> Vec<SocketAddr> {
+    let path = match &config.peer_snapshot {
+        Some(path) if path.exists() => path,
+        _ => return Vec::new(),
+    };
+    match PeerSnapshot::load(path) {
+        Ok(snapshot) => {
+            let addrs = snapshot.addresses().collect::<Vec<_>>();
+            info!(
+                logger,
+                "reconnecting to {} peers from the last run",
+                addrs.len()
+            );
+            addrs
+        }
+        Err(e) => {
+            warn!(logger, "cannot load the peer snapshot: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn save_peer_snapshot(state: &GlobalState) {
+    let path = match &state.config.peer_snapshot {
+        Some(path) => path,
+        None => return,
+    };
+    let snapshot = PeerSnapshot::capture(&state.peers, &state.topology);
+    // keep the previous snapshot while we are out of peers
+    if snapshot.is_empty() {
+        return;
+    }
+    if let Err(e) = snapshot.save(path) {
+        warn!(state.logger(), "cannot save the peer snapshot: {}", e);
+    }
 }
 
 fn handle_network_input(
@@ -210,11 +373,44 @@ fn handle_network_input(
     input.for_each(move |msg| match msg {
         NetworkMsg::Propagate(msg) => {
             handle_propagation_msg(msg, state.clone(), channels.clone());
-            Ok(())
+            future::Either::A(future::ok(()))
         }
         NetworkMsg::GetBlocks(node_id, block_ids) => {
-            state.peers.solicit_blocks(node_id, block_ids);
-            Ok(())
+            match state.peers.solicit_blocks(node_id, block_ids) {
+                Ok(_) => {}
+                Err(SolicitBlocksError::NotConnected(solicitation)) => {
+                    if state.config.connect_to_solicit_blocks {
+                        connect_and_solicit(node_id, solicitation, state.clone(), channels.clone());
+                    } else {
+                        warn!(state.logger(), "peer not available to solicit blocks from";
+                            "peer" => node_id.to_string(),
+                        );
+                    }
+                }
+                // logged by the peer map
+                Err(SolicitBlocksError::Failed(_)) => {}
+            }
+            future::Either::A(future::ok(()))
+        }
+        NetworkMsg::Shutdown(reply) => {
+            // the propagations received before this message are
+            // already in the subscription streams of the peers
+            info!(state.logger(), "closing the subscriptions of the peers");
+            future::Either::B(
+                state
+                    .peers
+                    .shutdown(SHUTDOWN_FLUSH_TIMEOUT)
+                    .map(move |()| reply.reply_ok(())),
+            )
+        }
+        NetworkMsg::GetStats(reply) => {
+            reply.reply_ok(NetworkStats {
+                peers: state.peers.stats(),
+                occupancy: state.peers.occupancy(),
+                gossip_over_budget: state.peers.gossip_over_budget(),
+                strikes: state.peers.strikes(),
+            });
+            future::Either::A(future::ok(()))
         }
     })
 }
@@ -228,45 +424,100 @@ fn handle_propagation_msg(msg: PropagateMsg, state: GlobalStateR, channels: Chan
         nodes.iter().map(|node| node.id()).collect::<Vec<_>>()
     );
     let res = match msg {
+        PropagateMsg::Block(ref header) if state.config.prefer_peers_behind => state
+            .peers
+            .propagate_block_to_peers_behind(nodes, header.clone()),
         PropagateMsg::Block(ref header) => state.peers.propagate_block(nodes, header.clone()),
         PropagateMsg::Message(ref message) => state.peers.propagate_message(nodes, message.clone()),
     };
     // If any nodes selected for propagation are not in the
     // active subscriptions map, connect to them and deliver
     // the item.
-    if let Err(unreached_nodes) = res {
-        for node in unreached_nodes {
-            let msg = msg.clone();
-            connect_and_propagate_with(
-                node,
-                state.clone(),
-                channels.clone(),
-                |handles| match msg {
-                    PropagateMsg::Block(header) => handles
-                        .try_send_block_announcement(header)
-                        .map_err(|e| e.kind()),
-                    PropagateMsg::Message(message) => {
-                        handles.try_send_message(message).map_err(|e| e.kind())
-                    }
-                },
+    match res {
+        Ok(()) => {}
+        Err(PropagationError::NoTargets) => {
+            warn!(
+                state.logger(),
+                "no peers to propagate to, the node may be isolated from the network"
             );
         }
+        Err(PropagationError::Unreached(unreached_nodes)) => {
+            for node in unreached_nodes {
+                let msg = msg.clone();
+                connect_and_propagate_with(node, state.clone(), channels.clone(), |handles| {
+                    match msg {
+                        PropagateMsg::Block(header) => handles
+                            .try_send_block_announcement(header)
+                            .map_err(|e| e.kind()),
+                        PropagateMsg::Message(message) => {
+                            handles.try_send_message(message).map_err(|e| e.kind())
+                        }
+                    }
+                });
+            }
+        }
     }
 }
 
 fn send_gossip(state: GlobalStateR, channels: Channels) {
     for node in state.topology.view() {
-        let gossip = Gossip::from_nodes(state.topology.select_gossips(&node));
+        let gossips = state.topology.select_gossips(&node);
+        let gossip = match state.config.max_gossip_age {
+            Some(_) => {
+                let gossips = state.topology.retain_fresh_gossips(gossips);
+                if gossips.is_empty() {
+                    debug!(
+                        state.logger(),
+                        "no fresh gossip to send to node {}",
+                        node.id()
+                    );
+                    continue;
+                }
+                Gossip::from_nodes(gossips)
+            }
+            None => Gossip::from_nodes(gossips),
+        };
         debug!(state.logger(), "sending gossip to node {}", node.id());
         let res = state.peers.propagate_gossip_to(node.id(), gossip);
-        if let Err(gossip) = res {
-            connect_and_propagate_with(node, state.clone(), channels.clone(), |handles| {
-                handles.try_send_gossip(gossip).map_err(|e| e.kind())
-            });
+        if let Err(e) = res {
+            // the peer is still connected if its failure has been spared,
+            // the gossip is dropped
+            if state.peers.contains_peer(node.id()) {
+                continue;
+            }
+            if let Some(gossip) = state.peers.admit_gossip(node.id(), e.into_item()) {
+                connect_and_propagate_with(node, state.clone(), channels.clone(), |handles| {
+                    handles.try_send_gossip(gossip).map_err(|e| e.kind())
+                });
+            }
         }
     }
 }
 
+// connects to the peer known to the topology with the given id, to send
+// it the solicitation once connected
+fn connect_and_solicit(
+    node_id: topology::NodeId,
+    solicitation: Solicitation,
+    state: GlobalStateR,
+    channels: Channels,
+) {
+    let node = match state.topology.view().find(|node| node.id() == node_id) {
+        Some(node) => node,
+        None => {
+            warn!(state.logger(), "peer to solicit blocks from is not known to the topology";
+                "peer" => node_id.to_string(),
+            );
+            return;
+        }
+    };
+    connect_and_propagate_with(node, state, channels, move |handles| {
+        handles
+            .try_send_block_solicitation(solicitation)
+            .map_err(|e| e.kind())
+    });
+}
+
 fn connect_and_propagate_with<F>(
     node: topology::Node,
     state: GlobalStateR,
@@ -1,23 +1,52 @@
+use super::gossip_budget::{GossipBudget, GossipLimiter, OverBudgetCounts};
+use super::gossip_compression::{self, GossipCompression};
 use super::topology;
-use crate::blockcfg::{Block, Header, HeaderHash, Message};
+use crate::blockcfg::{Block, ChainLength, Header, HeaderHash, Message};
+use chain_core::property::Header as _;
 use futures::prelude::*;
-use futures::{stream, sync::mpsc};
+use futures::{future, stream, sync::mpsc};
 use network_core::{
     error as core_error,
     gossip::{Gossip, Node},
     subscription::BlockEvent,
 };
-use slog::Logger;
+use rand::Rng;
+use slog::{Level, Logger};
 use std::{
-    collections::{hash_map, HashMap},
-    sync::Mutex,
+    cmp,
+    collections::{hash_map, HashMap, HashSet},
+    fmt,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
+use tokio::timer::{self, Interval, Timeout};
 
 // Buffer size determines the number of stream items pending processing that
 // can be buffered before back pressure is applied to the inbound half of
 // a gRPC subscription stream.
 const BUFFER_LEN: usize = 8;
 
+// The number of items a subscription channel accepts before it is full:
+// on top of its buffer, the channel guarantees a slot to each sender,
+// and the channels of `CommHandle::subscribe` have a single sender.
+const CHANNEL_CAPACITY: usize = BUFFER_LEN + 1;
+
+// Interval at which `PeerComms::flush` checks whether the buffered items
+// have been consumed.
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Uptime beyond which a peer is not favored any further as a gossip target,
+// so the peers connected more recently are still sampled now and then.
+const GOSSIP_WEIGHT_MAX_UPTIME: Duration = Duration::from_secs(600);
+
+// Number of peers a propagation is to be sent to below which the overflows
+// are not blamed on the node, see `PeerLimits::propagation_breaker_percent`.
+const BREAKER_MIN_TARGETS: usize = 4;
+
 #[derive(Debug)]
 pub struct PropagateError<T> {
     kind: ErrorKind,
@@ -34,17 +63,81 @@ impl<T> PropagateError<T> {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// Error returned by the propagation methods of `PeerMap`.
+#[derive(Debug)]
+pub enum PropagationError {
+    /// The list of nodes to propagate to was empty, the item was
+    /// not sent to anybody.
+    NoTargets,
+    /// The item could not be sent to these nodes, either because they
+    /// are not connected or sending to them has failed.
+    Unreached(Vec<topology::Node>),
+}
+
+/// The reason an item could not be propagated to a peer.
+///
+/// It serializes to a stable snake case tag, e.g. `stream_overflow`,
+/// to be reported in structured logs and API responses.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ErrorKind {
     NotSubscribed,
     SubscriptionClosed,
     StreamOverflow,
-    Unexpected,
+}
+
+impl ErrorKind {
+    /// the snake case tag of the error kind, as it is serialized
+    pub fn tag(&self) -> &'static str {
+        match self {
+            ErrorKind::NotSubscribed => "not_subscribed",
+            ErrorKind::SubscriptionClosed => "subscription_closed",
+            ErrorKind::StreamOverflow => "stream_overflow",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            ErrorKind::NotSubscribed => "not subscribed",
+            ErrorKind::SubscriptionClosed => "subscription closed",
+            ErrorKind::StreamOverflow => "stream overflow",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// Error returned by `PeerMap::solicit_blocks`.
+#[derive(Debug)]
+pub enum SolicitBlocksError {
+    /// The peer is not in the map. The solicitation is given back, to be
+    /// sent once connected to the peer.
+    NotConnected(Solicitation),
+    /// The solicitation could not be queued to the peer.
+    Failed(ErrorKind),
+}
+
+custom_error! {pub SubscriptionRejected
+    CapacityReached { limit: usize }
+        = "the node has reached its limit of {limit} subscribed peers, retry later",
+}
+
+/// Error returned by `PeerComms::flush`.
+#[derive(Debug)]
+pub enum FlushError {
+    /// Some items were still buffered when the timeout expired.
+    Timeout,
+    /// The timer used to wait for the items to be consumed has failed.
+    Timer(timer::Error),
 }
 
 /// Stream used as the outbound half of a subscription stream.
 pub struct Subscription<T> {
     inner: mpsc::Receiver<T>,
+    buffered: Arc<Buffered>,
+    // invoked the first time the stream is polled
+    on_first_poll: Option<Box<dyn FnMut() + Send>>,
 }
 
 impl<T> Stream for Subscription<T> {
@@ -52,27 +145,246 @@ impl<T> Stream for Subscription<T> {
     type Error = core_error::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        Ok(self.inner.poll().unwrap())
+        if let Some(mut on_first_poll) = self.on_first_poll.take() {
+            on_first_poll();
+        }
+        self.buffered.touch();
+        let item = self.inner.poll().unwrap();
+        if let Async::Ready(Some(_)) = item {
+            self.buffered.items.fetch_sub(1, Ordering::SeqCst);
+        }
+        Ok(item)
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        self.buffered.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+// Count of the items sent to a subscription and not yet polled out
+// of its stream.
+struct Buffered {
+    items: AtomicUsize,
+    closed: AtomicBool,
+    // when the stream was last polled, or when an item was sent to the
+    // stream with nothing buffered, whichever is the latest
+    last_activity: Mutex<Instant>,
+}
+
+impl Buffered {
+    fn new() -> Self {
+        Buffered {
+            items: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            last_activity: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    // The stream has items buffered, but has not been polled for
+    // at least `timeout`.
+    fn is_idle(&self, timeout: Duration) -> bool {
+        !self.closed.load(Ordering::SeqCst)
+            && self.items.load(Ordering::SeqCst) > 0
+            && self.last_activity.lock().unwrap().elapsed() >= timeout
+    }
+
+    // Once the stream is dropped, the buffered items are lost
+    // and there is nothing left to wait for.
+    fn is_flushed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst) || self.items.load(Ordering::SeqCst) == 0
     }
 }
 
 type BlockEventAnnounceStream = stream::Map<Subscription<Header>, fn(Header) -> BlockEvent<Block>>;
 
 type BlockEventSolicitStream =
-    stream::Map<Subscription<Vec<HeaderHash>>, fn(Vec<HeaderHash>) -> BlockEvent<Block>>;
+    stream::FilterMap<Subscription<Solicitation>, fn(Solicitation) -> Option<BlockEvent<Block>>>;
 
 pub type BlockEventSubscription = stream::Select<BlockEventAnnounceStream, BlockEventSolicitStream>;
 
+/// A request to solicit blocks from a peer, queued for the client
+/// connection to that peer.
+///
+/// The solicitation can be cancelled through the `SolicitationHandle`
+/// obtained when it was submitted; a cancelled solicitation is not sent out,
+/// and blocks received in response to it after cancellation are ignored.
+#[derive(Debug)]
+pub struct Solicitation {
+    hashes: Vec<HeaderHash>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Solicitation {
+    fn new(hashes: Vec<HeaderHash>) -> (Self, SolicitationHandle) {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let handle = SolicitationHandle {
+            cancelled: cancelled.clone(),
+        };
+        (Solicitation { hashes, cancelled }, handle)
+    }
+
+    /// Creates a solicitation that is cancelled together with the
+    /// solicitation of the given handle.
+    pub fn with_handle(hashes: Vec<HeaderHash>, handle: &SolicitationHandle) -> Self {
+        Solicitation {
+            hashes,
+            cancelled: handle.cancelled.clone(),
+        }
+    }
+
+    pub fn hashes(&self) -> &[HeaderHash] {
+        &self.hashes
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns a handle that observes or cancels this solicitation.
+    pub fn handle(&self) -> SolicitationHandle {
+        SolicitationHandle {
+            cancelled: self.cancelled.clone(),
+        }
+    }
+
+    fn into_event(self) -> Option<BlockEvent<Block>> {
+        if self.is_cancelled() {
+            None
+        } else {
+            Some(BlockEvent::Solicit(self.hashes))
+        }
+    }
+}
+
+/// Handle to a block solicitation submitted with `PeerMap::solicit_blocks`.
+#[derive(Clone, Debug)]
+pub struct SolicitationHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl SolicitationHandle {
+    /// Cancels the solicitation. If it is still queued, it will not be
+    /// sent to the peer; if it is in flight, the blocks received in
+    /// response are discarded.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Error returned by `SubscriptionSender::try_send`.
+///
+/// The item that could not be sent is returned with the error.
+#[derive(Debug)]
+pub enum TrySendError<T> {
+    /// The channel buffer is full.
+    Full(T),
+    /// The receiving half of the channel has been dropped.
+    Disconnected(T),
+}
+
+impl<T> TrySendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(item) => item,
+            TrySendError::Disconnected(item) => item,
+        }
+    }
+}
+
+/// The sending half of a subscription channel.
+///
+/// `CommHandle` uses `futures::sync::mpsc` channels by default;
+/// this trait is a seam to plug in other channel implementations,
+/// such as mock channels in tests.
+pub trait SubscriptionSender<T> {
+    fn try_send(&mut self, item: T) -> Result<(), TrySendError<T>>;
+}
+
+impl<T> SubscriptionSender<T> for mpsc::Sender<T> {
+    fn try_send(&mut self, item: T) -> Result<(), TrySendError<T>> {
+        mpsc::Sender::try_send(self, item).map_err(|e| {
+            if e.is_disconnected() {
+                TrySendError::Disconnected(e.into_inner())
+            } else {
+                TrySendError::Full(e.into_inner())
+            }
+        })
+    }
+}
+
+/// Thresholds of the occupancy of the subscription streams, in percent
+/// of their capacity, to warn of a stream backing up before it overflows.
+///
+/// A stream is reported once when its occupancy reaches `high_percent`,
+/// and again once it drops back to `low_percent` or below, so a stream
+/// hovering around a single threshold does not flood the logs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OccupancyWatermark {
+    pub high_percent: u8,
+    pub low_percent: u8,
+}
+
+impl Default for OccupancyWatermark {
+    fn default() -> Self {
+        OccupancyWatermark {
+            high_percent: 75,
+            low_percent: 25,
+        }
+    }
+}
+
+impl OccupancyWatermark {
+    fn is_high(&self, occupancy: &StreamOccupancy) -> bool {
+        occupancy.len * 100 >= occupancy.capacity * self.high_percent as usize
+    }
+
+    fn is_low(&self, occupancy: &StreamOccupancy) -> bool {
+        occupancy.len * 100 <= occupancy.capacity * self.low_percent as usize
+    }
+}
+
+/// The occupancy of a subscription stream crossing the thresholds of
+/// the `OccupancyWatermark`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatermarkCrossing {
+    /// the stream has reached the high threshold
+    Raised(StreamOccupancy),
+    /// the stream has dropped back to the low threshold
+    Cleared(StreamOccupancy),
+}
+
 /// Handle used by the per-peer communication tasks to produce an outbound
 /// subscription stream towards the peer.
-pub struct CommHandle<T> {
-    state: SubscriptionState<T>,
+pub struct CommHandle<T, S = mpsc::Sender<T>> {
+    state: SubscriptionState<S>,
+    // number of items buffered in the channel, if it is tracked
+    buffered: Option<Arc<Buffered>>,
+    // the occupancy has reached the high watermark and has not dropped
+    // back to the low one since
+    above_watermark: bool,
+    // the number of times the subscription was replaced by a new one
+    replacements: u64,
+    _phantom_item: PhantomData<T>,
 }
 
-impl<T> Default for CommHandle<T> {
+impl<T, S> Default for CommHandle<T, S> {
     fn default() -> Self {
         CommHandle {
             state: SubscriptionState::NotSubscribed,
+            buffered: None,
+            above_watermark: false,
+            replacements: 0,
+            _phantom_item: PhantomData,
         }
     }
 }
@@ -84,9 +396,138 @@ impl<T> CommHandle<T> {
     /// If this method is called again on the same handle,
     /// the previous subscription is closed and its stream is terminated.
     pub fn subscribe(&mut self) -> Subscription<T> {
+        self.subscribe_inner(None)
+    }
+
+    /// Same as `subscribe`, `on_first_poll` being invoked the first time
+    /// the returned stream is polled, which tells that the connection
+    /// task is actually consuming the stream.
+    pub fn subscribe_notifying<F>(&mut self, on_first_poll: F) -> Subscription<T>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.subscribe_inner(Some(Box::new(on_first_poll)))
+    }
+
+    fn subscribe_inner(
+        &mut self,
+        on_first_poll: Option<Box<dyn FnMut() + Send>>,
+    ) -> Subscription<T> {
         let (tx, rx) = mpsc::channel(BUFFER_LEN);
-        self.state = SubscriptionState::Subscribed(tx);
-        Subscription { inner: rx }
+        let buffered = Arc::new(Buffered::new());
+        self.subscribe_with(tx);
+        self.buffered = Some(buffered.clone());
+        Subscription {
+            inner: rx,
+            buffered,
+            on_first_poll,
+        }
+    }
+}
+
+impl<T, S> CommHandle<T, S> {
+    fn buffered(&self) -> Option<Arc<Buffered>> {
+        self.buffered.clone()
+    }
+
+    // The occupancy of the channel, if it is subscribed and tracked.
+    fn occupancy(&self) -> Option<StreamOccupancy> {
+        match self.state {
+            SubscriptionState::NotSubscribed | SubscriptionState::Closed => None,
+            SubscriptionState::Subscribed(_) => {
+                self.buffered.as_ref().map(|buffered| StreamOccupancy {
+                    len: buffered.items.load(Ordering::SeqCst),
+                    capacity: CHANNEL_CAPACITY,
+                })
+            }
+        }
+    }
+
+    // Checks the occupancy of the channel against the watermark, which
+    // is only known for the channels created with `subscribe`. The
+    // occupancy is sampled when this is called, items polled out of the
+    // stream in between are not seen.
+    fn check_watermark(&mut self, watermark: &OccupancyWatermark) -> Option<WatermarkCrossing> {
+        let occupancy = self.occupancy()?;
+        if !self.above_watermark && watermark.is_high(&occupancy) {
+            self.above_watermark = true;
+            Some(WatermarkCrossing::Raised(occupancy))
+        } else if self.above_watermark && watermark.is_low(&occupancy) {
+            self.above_watermark = false;
+            Some(WatermarkCrossing::Cleared(occupancy))
+        } else {
+            None
+        }
+    }
+
+    // Tells whether an item sent now would be accepted, without sending
+    // anything. The channel occupancy is only known for the channels
+    // created with `subscribe`, items sent to other channels are assumed
+    // to be accepted.
+    fn probe(&self) -> Result<(), ErrorKind> {
+        match self.state {
+            SubscriptionState::NotSubscribed => Err(ErrorKind::NotSubscribed),
+            SubscriptionState::Closed => Err(ErrorKind::SubscriptionClosed),
+            SubscriptionState::Subscribed(_) => match &self.buffered {
+                Some(buffered) if buffered.closed.load(Ordering::SeqCst) => {
+                    Err(ErrorKind::SubscriptionClosed)
+                }
+                Some(buffered) if buffered.items.load(Ordering::SeqCst) >= CHANNEL_CAPACITY => {
+                    Err(ErrorKind::StreamOverflow)
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+impl<T, S> CommHandle<T, S>
+where
+    S: SubscriptionSender<T>,
+{
+    /// Drops the sending half of the subscription channel, if subscribed.
+    ///
+    /// The subscription stream terminates once the items already
+    /// buffered are consumed.
+    pub fn close(&mut self) {
+        self.state = SubscriptionState::NotSubscribed;
+        self.buffered = None;
+        self.above_watermark = false;
+    }
+
+    /// Closes the subscription if its stream has items buffered but has not
+    /// been polled for at least `timeout`, meaning the connection task is
+    /// not draining it. Returns whether the subscription has been closed.
+    ///
+    /// The sending half of the channel is dropped and the items sent
+    /// afterwards fail with `SubscriptionClosed`, until the peer
+    /// subscribes again.
+    pub fn close_if_idle(&mut self, timeout: Duration) -> bool {
+        let idle = match (&self.state, &self.buffered) {
+            (SubscriptionState::Subscribed(_), Some(buffered)) => buffered.is_idle(timeout),
+            _ => false,
+        };
+        if idle {
+            self.state = SubscriptionState::Closed;
+            self.buffered = None;
+            self.above_watermark = false;
+        }
+        idle
+    }
+
+    /// Sets the sending half of the subscription channel.
+    ///
+    /// If the handle was subscribed, the previous sender is dropped and
+    /// the replacement is counted.
+    /// The items buffered in a channel set this way are not tracked
+    /// by `PeerComms::flush`.
+    pub fn subscribe_with(&mut self, sender: S) {
+        if let SubscriptionState::Subscribed(_) = self.state {
+            self.replacements += 1;
+        }
+        self.state = SubscriptionState::Subscribed(sender);
+        self.buffered = None;
+        self.above_watermark = false;
     }
 
     // Try sending the item to the subscriber.
@@ -99,31 +540,90 @@ impl<T> CommHandle<T> {
                 kind: ErrorKind::NotSubscribed,
                 item,
             }),
-            SubscriptionState::Subscribed(ref mut sender) => sender.try_send(item).map_err(|e| {
-                if e.is_disconnected() {
-                    PropagateError {
-                        kind: ErrorKind::SubscriptionClosed,
-                        item: e.into_inner(),
+            SubscriptionState::Closed => Err(PropagateError {
+                kind: ErrorKind::SubscriptionClosed,
+                item,
+            }),
+            SubscriptionState::Subscribed(ref mut sender) => {
+                // count the item before sending it, the subscription
+                // stream may poll it out before we get the chance to
+                if let Some(buffered) = &self.buffered {
+                    // the stream is not expected to be polled while
+                    // there is nothing to poll out
+                    if buffered.items.fetch_add(1, Ordering::SeqCst) == 0 {
+                        buffered.touch();
                     }
-                } else if e.is_full() {
-                    PropagateError {
-                        kind: ErrorKind::StreamOverflow,
-                        item: e.into_inner(),
+                }
+                let buffered = &self.buffered;
+                sender.try_send(item).map_err(|e| {
+                    if let Some(buffered) = buffered {
+                        buffered.items.fetch_sub(1, Ordering::SeqCst);
                     }
-                } else {
-                    PropagateError {
-                        kind: ErrorKind::Unexpected,
-                        item: e.into_inner(),
+                    match e {
+                        TrySendError::Disconnected(item) => PropagateError {
+                            kind: ErrorKind::SubscriptionClosed,
+                            item,
+                        },
+                        TrySendError::Full(item) => PropagateError {
+                            kind: ErrorKind::StreamOverflow,
+                            item,
+                        },
                     }
-                }
-            }),
+                })
+            }
         }
     }
 }
 
-enum SubscriptionState<T> {
+enum SubscriptionState<S> {
     NotSubscribed,
-    Subscribed(mpsc::Sender<T>),
+    Subscribed(S),
+    // closed on our side, the peer is still expected to be subscribed
+    Closed,
+}
+
+/// The number of items buffered in a subscription stream, waiting to be
+/// sent to the peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct StreamOccupancy {
+    pub len: usize,
+    /// the number of items that can be buffered before the stream
+    /// overflows
+    pub capacity: usize,
+}
+
+/// The occupancy of each subscription stream to a peer, `None` for the
+/// streams that are not subscribed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct PeerCommsOccupancy {
+    pub block_announcements: Option<StreamOccupancy>,
+    pub block_solicitations: Option<StreamOccupancy>,
+    pub messages: Option<StreamOccupancy>,
+    pub gossip: Option<StreamOccupancy>,
+}
+
+/// The number of times each subscription stream to a peer has been
+/// replaced by a new subscription while it was still subscribed. A high
+/// rate tells a flapping peer, repeatedly subscribing again.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SubscriptionReplacements {
+    pub block_announcements: u64,
+    pub block_solicitations: u64,
+    pub messages: u64,
+    pub gossip: u64,
+}
+
+impl SubscriptionReplacements {
+    pub fn total(&self) -> u64 {
+        self.block_announcements + self.block_solicitations + self.messages + self.gossip
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.block_announcements += other.block_announcements;
+        self.block_solicitations += other.block_solicitations;
+        self.messages += other.messages;
+        self.gossip += other.gossip;
+    }
 }
 
 /// State of the communication streams that a single peer connection polls
@@ -135,9 +635,19 @@ enum SubscriptionState<T> {
 #[derive(Default)]
 pub struct PeerComms {
     block_announcements: CommHandle<Header>,
-    block_solicitations: CommHandle<Vec<HeaderHash>>,
+    block_solicitations: CommHandle<Solicitation>,
     messages: CommHandle<Message>,
     gossip: CommHandle<Gossip<topology::Node>>,
+    // when the peer last subscribed or was sent an item
+    last_active: Option<Instant>,
+    // when the comms were inserted in the peer map
+    connected_since: Option<Instant>,
+    // the chain length of the block last announced by the peer
+    tip: Option<ChainLength>,
+    // kept in the peer map on propagation failures, see `PeerMap::pin_peer`
+    pinned: bool,
+    // the minor misbehaviors of the peer, see `PeerMap::add_strikes`
+    strikes: u64,
 }
 
 impl PeerComms {
@@ -147,39 +657,310 @@ impl PeerComms {
         }
     }
 
+    fn mark_connected(&mut self) {
+        self.connected_since = Some(Instant::now());
+    }
+
+    /// How long the peer has been in the peer map, `None` if it is not
+    /// in the map.
+    pub fn uptime(&self) -> Option<Duration> {
+        self.connected_since.map(|since| since.elapsed())
+    }
+
+    // the streams of a peer just connected may not be set up yet
+    fn in_warmup(&self, warmup: Duration) -> bool {
+        self.uptime().map_or(false, |uptime| uptime < warmup)
+    }
+
+    fn mark_active<T>(&mut self, res: Result<(), T>) -> Result<(), T> {
+        if res.is_ok() {
+            self.last_active = Some(Instant::now());
+        }
+        res
+    }
+
     pub fn try_send_block_announcement(
         &mut self,
         header: Header,
     ) -> Result<(), PropagateError<Header>> {
-        self.block_announcements.try_send(header)
+        let res = self.block_announcements.try_send(header);
+        self.mark_active(res)
     }
 
     pub fn try_send_message(&mut self, message: Message) -> Result<(), PropagateError<Message>> {
-        self.messages.try_send(message)
+        let res = self.messages.try_send(message);
+        self.mark_active(res)
     }
 
     pub fn try_send_gossip(
         &mut self,
         gossip: Gossip<topology::Node>,
     ) -> Result<(), PropagateError<Gossip<topology::Node>>> {
-        self.gossip.try_send(gossip)
+        let res = self.gossip.try_send(gossip);
+        self.mark_active(res)
+    }
+
+    pub fn try_send_block_solicitation(
+        &mut self,
+        solicitation: Solicitation,
+    ) -> Result<(), PropagateError<Solicitation>> {
+        let res = self.block_solicitations.try_send(solicitation);
+        self.mark_active(res)
     }
 
     pub fn subscribe_to_block_announcements(&mut self) -> Subscription<Header> {
+        self.last_active = Some(Instant::now());
         self.block_announcements.subscribe()
     }
 
-    pub fn subscribe_to_block_solicitations(&mut self) -> Subscription<Vec<HeaderHash>> {
+    pub fn subscribe_to_block_solicitations(&mut self) -> Subscription<Solicitation> {
+        self.last_active = Some(Instant::now());
         self.block_solicitations.subscribe()
     }
 
     pub fn subscribe_to_messages(&mut self) -> Subscription<Message> {
+        self.last_active = Some(Instant::now());
         self.messages.subscribe()
     }
 
     pub fn subscribe_to_gossip(&mut self) -> Subscription<Gossip<topology::Node>> {
+        self.last_active = Some(Instant::now());
         self.gossip.subscribe()
     }
+
+    /// Closes all the subscription streams at once, rather than relying
+    /// on the drop order of the handles.
+    pub fn close_all(&mut self) {
+        self.block_announcements.close();
+        self.block_solicitations.close();
+        self.messages.close();
+        self.gossip.close();
+    }
+
+    pub fn occupancy(&self) -> PeerCommsOccupancy {
+        PeerCommsOccupancy {
+            block_announcements: self.block_announcements.occupancy(),
+            block_solicitations: self.block_solicitations.occupancy(),
+            messages: self.messages.occupancy(),
+            gossip: self.gossip.occupancy(),
+        }
+    }
+
+    pub fn subscription_replacements(&self) -> SubscriptionReplacements {
+        SubscriptionReplacements {
+            block_announcements: self.block_announcements.replacements,
+            block_solicitations: self.block_solicitations.replacements,
+            messages: self.messages.replacements,
+            gossip: self.gossip.replacements,
+        }
+    }
+
+    /// Closes the subscriptions whose streams have not been drained
+    /// for at least `timeout`, returning the streams closed.
+    pub fn close_idle(&mut self, timeout: Duration) -> Vec<&'static str> {
+        vec![
+            ("block", self.block_announcements.close_if_idle(timeout)),
+            (
+                "block_solicitation",
+                self.block_solicitations.close_if_idle(timeout),
+            ),
+            ("message", self.messages.close_if_idle(timeout)),
+            ("gossip", self.gossip.close_if_idle(timeout)),
+        ]
+        .into_iter()
+        .filter_map(|(stream, closed)| if closed { Some(stream) } else { None })
+        .collect()
+    }
+
+    /// Checks the occupancy of each subscription stream against the
+    /// watermark, returning the streams crossing one of its thresholds
+    /// since the last check.
+    pub fn check_watermarks(
+        &mut self,
+        watermark: &OccupancyWatermark,
+    ) -> Vec<(&'static str, WatermarkCrossing)> {
+        vec![
+            ("block", self.block_announcements.check_watermark(watermark)),
+            (
+                "block_solicitation",
+                self.block_solicitations.check_watermark(watermark),
+            ),
+            ("message", self.messages.check_watermark(watermark)),
+            ("gossip", self.gossip.check_watermark(watermark)),
+        ]
+        .into_iter()
+        .filter_map(|(stream, crossing)| crossing.map(|crossing| (stream, crossing)))
+        .collect()
+    }
+
+    /// Returns a future that resolves once all the items currently
+    /// buffered in the subscription streams have been consumed by
+    /// the connection tasks, or fails if this does not happen within
+    /// the given timeout.
+    pub fn flush(&self, timeout: Duration) -> impl Future<Item = (), Error = FlushError> {
+        let buffered = vec![
+            self.block_announcements.buffered(),
+            self.block_solicitations.buffered(),
+            self.messages.buffered(),
+            self.gossip.buffered(),
+        ]
+        .into_iter()
+        .filter_map(|buffered| buffered)
+        .collect::<Vec<_>>();
+
+        let wait = Interval::new_interval(FLUSH_POLL_INTERVAL)
+            .take_while(move |_| Ok(!buffered.iter().all(|buffered| buffered.is_flushed())))
+            .for_each(|_| Ok(()));
+
+        Timeout::new(wait, timeout).map_err(|e| {
+            if e.is_elapsed() {
+                FlushError::Timeout
+            } else if e.is_inner() {
+                FlushError::Timer(e.into_inner().unwrap())
+            } else {
+                FlushError::Timer(e.into_timer().unwrap())
+            }
+        })
+    }
+}
+
+/// Severity of the log entries emitted when propagating to a peer fails,
+/// for each kind of failure.
+///
+/// Some failures are routine on a busy node (a peer not keeping up with
+/// the stream), this allows to log them at a different level than the
+/// more significant ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PropagationLogPolicy {
+    #[serde(with = "log_level")]
+    pub not_subscribed: Level,
+    #[serde(with = "log_level")]
+    pub subscription_closed: Level,
+    #[serde(with = "log_level")]
+    pub stream_overflow: Level,
+}
+
+// the log levels by their name in lower case, e.g. `debug`
+mod log_level {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use slog::Level;
+
+    pub fn serialize<S: Serializer>(level: &Level, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&level.as_str().to_lowercase())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Level, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse()
+            .map_err(|()| D::Error::custom(format!("unknown log level '{}'", name)))
+    }
+}
+
+impl Default for PropagationLogPolicy {
+    fn default() -> Self {
+        PropagationLogPolicy {
+            not_subscribed: Level::Info,
+            subscription_closed: Level::Info,
+            stream_overflow: Level::Info,
+        }
+    }
+}
+
+impl PropagationLogPolicy {
+    pub fn level(&self, kind: ErrorKind) -> Level {
+        match kind {
+            ErrorKind::NotSubscribed => self.not_subscribed,
+            ErrorKind::SubscriptionClosed => self.subscription_closed,
+            ErrorKind::StreamOverflow => self.stream_overflow,
+        }
+    }
+}
+
+// tells in the logs when a peer connection starts consuming a stream,
+// to tell apart the peers stuck after subscribing
+fn log_first_poll(
+    logger: &Logger,
+    peer: topology::NodeId,
+    stream: &'static str,
+) -> impl FnMut() + Send + 'static {
+    let logger = logger.new(o!(
+        "peer" => peer.to_string(),
+        "stream" => stream,
+    ));
+    move || debug!(logger, "peer started consuming the subscription stream")
+}
+
+// logs the failure to send to a peer, with the peer, the kind of error
+// and the stream as fields for the log processors to filter on
+fn log_propagation_failure(
+    logger: &Logger,
+    level: Level,
+    message: &str,
+    peer: topology::NodeId,
+    kind: ErrorKind,
+    stream: &'static str,
+) {
+    let logger = logger.new(o!(
+        "peer" => peer.to_string(),
+        "error_kind" => kind.tag(),
+        "stream" => stream,
+    ));
+    match level {
+        Level::Critical => crit!(logger, "{}", message),
+        Level::Error => error!(logger, "{}", message),
+        Level::Warning => warn!(logger, "{}", message),
+        Level::Info => info!(logger, "{}", message),
+        Level::Debug => debug!(logger, "{}", message),
+        Level::Trace => trace!(logger, "{}", message),
+    }
+}
+
+/// Outcome of `PeerMap::would_propagate`: the target nodes classified by
+/// what would currently happen if an item was propagated to them.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PropagationForecast {
+    /// nodes that would accept the item
+    pub reachable: Vec<topology::NodeId>,
+    /// nodes that would be skipped, as they are not subscribed
+    pub not_subscribed: Vec<topology::NodeId>,
+    /// nodes whose subscription stream is full
+    pub full: Vec<topology::NodeId>,
+    /// nodes that are not connected, or whose subscription is closed
+    pub unreachable: Vec<topology::NodeId>,
+}
+
+/// Limits on the peers subscribing to the node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerLimits {
+    /// the maximum number of peers in the map, if limited; the
+    /// subscriptions of new peers are rejected beyond it
+    pub max_peers: Option<usize>,
+    /// rather than rejecting a new peer, evict the peer the least
+    /// recently subscribed or sent to
+    pub evict_least_active: bool,
+    /// the time after a peer is inserted in the map during which it is
+    /// not evicted when its streams are not subscribed or overflow, the
+    /// items to send to it are skipped instead
+    pub eviction_warmup: Duration,
+    /// the percentage of the peers of a single propagation whose streams
+    /// overflow beyond which the node itself is taken as the bottleneck,
+    /// the overflowing peers are then not evicted for this propagation
+    pub propagation_breaker_percent: Option<u8>,
+}
+
+/// The number of peers in a `PeerMap`, along with the limit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerMapStats {
+    pub peer_count: usize,
+    pub max_peers: Option<usize>,
+    /// the peers not evicted on propagation failures, see
+    /// `PeerMap::pin_peer`
+    pub pinned_peers: Vec<topology::NodeId>,
+    /// the subscription replacements of all the peers since the node
+    /// started, including the peers no longer in the map, so the counts
+    /// only grow
+    pub subscription_replacements: SubscriptionReplacements,
 }
 
 /// The map of currently connected peer nodes.
@@ -188,162 +969,1429 @@ impl PeerComms {
 /// all network connection tasks.
 pub struct PeerMap {
     mutex: Mutex<HashMap<topology::NodeId, PeerComms>>,
+    log_policy: PropagationLogPolicy,
+    watermark: OccupancyWatermark,
+    limits: PeerLimits,
+    // the peers pinned as soon as they are in the map, see
+    // `with_pinned_peers`
+    pinned_on_connect: HashSet<topology::NodeId>,
+    // the size from which the node records sent in the gossip are
+    // compressed, see `with_gossip_compression`
+    gossip_compression: usize,
+    // locked after the map when both are locked
+    gossip_limiter: Option<Mutex<GossipLimiter>>,
+    // the streams whose last propagation has tripped the breaker, locked
+    // after the map
+    degraded: Mutex<HashSet<&'static str>>,
+    // the subscription replacements of the peer comms no longer in the
+    // map, locked after the map
+    past_replacements: Mutex<SubscriptionReplacements>,
+    on_evict: Box<dyn Fn(topology::NodeId) + Send + Sync>,
     logger: Logger,
 }
 
-fn ensure_peer_comms<'a>(
-    map: &'a mut HashMap<topology::NodeId, PeerComms>,
-    id: topology::NodeId,
-) -> &'a mut PeerComms {
-    map.entry(id).or_insert(PeerComms::new())
+// whether the overflows in a propagation are too many to be blamed on
+// the peers, see `PeerLimits::propagation_breaker_percent`
+fn breaker_trips(percent: u8, targets: usize, overflows: usize) -> bool {
+    targets >= BREAKER_MIN_TARGETS && overflows * 100 > targets * usize::from(percent)
+}
+
+// logs the subscription streams of the peer crossing the thresholds of
+// the watermark, ahead of the overflows
+fn log_watermarks(
+    logger: &Logger,
+    watermark: &OccupancyWatermark,
+    peer: topology::NodeId,
+    comms: &mut PeerComms,
+) {
+    for (stream, crossing) in comms.check_watermarks(watermark) {
+        match crossing {
+            WatermarkCrossing::Raised(occupancy) => {
+                warn!(logger, "subscription stream to peer is backing up";
+                    "peer" => peer.to_string(),
+                    "stream" => stream,
+                    "len" => occupancy.len,
+                    "capacity" => occupancy.capacity,
+                );
+            }
+            WatermarkCrossing::Cleared(occupancy) => {
+                info!(logger, "subscription stream to peer has drained";
+                    "peer" => peer.to_string(),
+                    "stream" => stream,
+                    "len" => occupancy.len,
+                    "capacity" => occupancy.capacity,
+                );
+            }
+        }
+    }
+}
+
+// The weight of the peer when sampling the gossip targets: one for a peer
+// just connected, and one more for each minute it has stayed connected,
+// up to `GOSSIP_WEIGHT_MAX_UPTIME`.
+fn gossip_weight(comms: &PeerComms) -> f64 {
+    let uptime = comms.uptime().unwrap_or(Duration::from_secs(0));
+    let uptime = cmp::min(uptime, GOSSIP_WEIGHT_MAX_UPTIME);
+    1.0 + (uptime.as_secs() / 60) as f64
+}
+
+// Samples up to `n` of the peers without replacement, each chosen with a
+// probability proportional to its weight: every peer gets the random key
+// `u ^ (1 / weight)` and the peers with the largest keys are taken.
+fn weighted_sample<R: Rng>(
+    mut weighted: Vec<(topology::NodeId, f64)>,
+    n: usize,
+    rng: &mut R,
+) -> Vec<topology::NodeId> {
+    // the order of the map is random, the sample must only depend on `rng`
+    weighted.sort_by_key(|(id, _)| *id);
+    let mut keyed = weighted
+        .into_iter()
+        .map(|(id, weight)| (rng.gen::<f64>().powf(1.0 / weight), id))
+        .collect::<Vec<_>>();
+    keyed.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap());
+    keyed.truncate(n);
+    keyed.into_iter().map(|(_, id)| id).collect()
+}
+
+fn sample_targets<R: Rng>(
+    map: &HashMap<topology::NodeId, PeerComms>,
+    n: usize,
+    rng: &mut R,
+) -> Vec<topology::NodeId> {
+    let weighted = map
+        .iter()
+        .map(|(id, comms)| (*id, gossip_weight(comms)))
+        .collect();
+    weighted_sample(weighted, n, rng)
 }
 
 impl PeerMap {
-    pub fn new(logger: Logger) -> Self {
+    /// Creates an empty peer map.
+    ///
+    /// The `on_evict` callback is invoked with the id of every peer
+    /// removed from the map, be it due to a propagation failure or
+    /// on shutdown. It is called with the map locked, so it must not
+    /// call back into the `PeerMap`.
+    ///
+    /// The subscription streams crossing the thresholds of `watermark`
+    /// are logged as items are sent to them. The peers subscribing beyond
+    /// `limits` are rejected, or make room by evicting another peer.
+    /// The gossip sent to all the peers is limited by `gossip_budget`,
+    /// if set.
+    pub fn new<F>(
+        log_policy: PropagationLogPolicy,
+        watermark: OccupancyWatermark,
+        limits: PeerLimits,
+        gossip_budget: Option<GossipBudget>,
+        on_evict: F,
+        logger: Logger,
+    ) -> Self
+    where
+        F: Fn(topology::NodeId) + Send + Sync + 'static,
+    {
         PeerMap {
             mutex: Mutex::new(HashMap::new()),
+            log_policy,
+            watermark,
+            limits,
+            pinned_on_connect: HashSet::new(),
+            gossip_compression: 0,
+            gossip_limiter: gossip_budget.map(|budget| Mutex::new(GossipLimiter::new(budget))),
+            degraded: Mutex::new(HashSet::new()),
+            past_replacements: Mutex::new(SubscriptionReplacements::default()),
+            on_evict: Box::new(on_evict),
             logger,
         }
     }
 
-    pub fn insert_peer(&self, id: topology::NodeId, handles: PeerComms) {
-        let mut map = self.mutex.lock().unwrap();
-        map.insert(id, handles);
-    }
-
-    pub fn subscribe_to_block_events(&self, id: topology::NodeId) -> BlockEventSubscription {
-        let mut map = self.mutex.lock().unwrap();
-        let handles = ensure_peer_comms(&mut map, id);
-        let announce_events: BlockEventAnnounceStream = handles
-            .block_announcements
-            .subscribe()
-            .map(BlockEvent::Announce);
-        let solicit_events: BlockEventSolicitStream = handles
-            .block_solicitations
-            .subscribe()
-            .map(BlockEvent::Solicit);
-        announce_events.select(solicit_events)
+    /// Pins the given peers, see `pin_peer`, each time they are inserted
+    /// in the map, e.g. the trusted peers of the node. Unlike the peers
+    /// pinned with `pin_peer`, they are pinned again when they reconnect
+    /// after being removed.
+    pub fn with_pinned_peers<I>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = topology::NodeId>,
+    {
+        self.pinned_on_connect.extend(ids);
+        self
     }
 
-    pub fn subscribe_to_messages(&self, id: topology::NodeId) -> Subscription<Message> {
-        let mut map = self.mutex.lock().unwrap();
-        let handles = ensure_peer_comms(&mut map, id);
-        handles.messages.subscribe()
+    /// Compresses the node records of the gossip admitted from now on,
+    /// see `admit_gossip`, `None` to send them plain. The peers have to
+    /// decode the compressed records, see `GossipCompression`.
+    pub fn with_gossip_compression(mut self, compression: Option<GossipCompression>) -> Self {
+        self.gossip_compression = gossip_compression::threshold(compression);
+        self
     }
 
-    pub fn subscribe_to_gossip(
+    // whether the failure to send to the peer is forgiven, see
+    // `PeerLimits::eviction_warmup` and `pin_peer`
+    fn spared(
         &self,
         id: topology::NodeId,
-    ) -> Subscription<Gossip<topology::Node>> {
-        let mut map = self.mutex.lock().unwrap();
-        let handles = ensure_peer_comms(&mut map, id);
-        handles.gossip.subscribe()
+        comms: &PeerComms,
+        kind: ErrorKind,
+        stream: &'static str,
+    ) -> bool {
+        if comms.pinned {
+            log_propagation_failure(
+                &self.logger,
+                self.log_policy.level(kind),
+                "propagation to pinned peer failed, keeping the peer",
+                id,
+                kind,
+                stream,
+            );
+            return true;
+        }
+        let spared = match kind {
+            ErrorKind::NotSubscribed | ErrorKind::StreamOverflow => {
+                comms.in_warmup(self.limits.eviction_warmup)
+            }
+            _ => false,
+        };
+        if spared {
+            debug!(self.logger, "peer is warming up, skipping";
+                "peer" => id.to_string(),
+                "error_kind" => kind.tag(),
+                "stream" => stream,
+            );
+        }
+        spared
     }
 
-    fn propagate_with<T, F>(
+    fn evict(&self, entry: hash_map::OccupiedEntry<topology::NodeId, PeerComms>) {
+        let (id, mut comms) = entry.remove_entry();
+        self.retire(&comms);
+        debug!(self.logger, "unsubscribing peer"; "peer" => id.to_string());
+        comms.close_all();
+        (self.on_evict)(id);
+    }
+
+    // keeps the count of the subscription replacements of the comms
+    // leaving the map
+    fn retire(&self, comms: &PeerComms) {
+        self.past_replacements
+            .lock()
+            .unwrap()
+            .add(&comms.subscription_replacements());
+    }
+
+    // the comms of the subscribing peer, added to the map if within
+    // the limits
+    fn ensure_peer_comms<'a>(
         &self,
-        nodes: Vec<topology::Node>,
+        map: &'a mut HashMap<topology::NodeId, PeerComms>,
+        id: topology::NodeId,
+    ) -> Result<&'a mut PeerComms, SubscriptionRejected> {
+        if !map.contains_key(&id) {
+            match self.limits.max_peers {
+                Some(limit) if map.len() >= limit => {
+                    let least_active = if self.limits.evict_least_active {
+                        map.iter()
+                            .filter(|(_, comms)| !comms.pinned)
+                            .min_by_key(|(_, comms)| comms.last_active)
+                            .map(|(peer, _)| *peer)
+                    } else {
+                        None
+                    };
+                    match least_active {
+                        Some(least_active) => {
+                            if let hash_map::Entry::Occupied(entry) = map.entry(least_active) {
+                                self.evict(entry);
+                            }
+                        }
+                        None => {
+                            info!(self.logger, "rejecting subscription, too many peers";
+                                "peer" => id.to_string(),
+                                "limit" => limit,
+                            );
+                            return Err(SubscriptionRejected::CapacityReached { limit });
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        let pinned = self.pinned_on_connect.contains(&id);
+        Ok(map.entry(id).or_insert_with(|| {
+            let mut comms = PeerComms::new();
+            comms.mark_connected();
+            comms.pinned = pinned;
+            comms
+        }))
+    }
+
+    /// the number of peers in the map and the limit
+    pub fn stats(&self) -> PeerMapStats {
+        let map = self.mutex.lock().unwrap();
+        let mut subscription_replacements = *self.past_replacements.lock().unwrap();
+        for comms in map.values() {
+            subscription_replacements.add(&comms.subscription_replacements());
+        }
+        PeerMapStats {
+            peer_count: map.len(),
+            max_peers: self.limits.max_peers,
+            pinned_peers: map
+                .iter()
+                .filter(|(_, comms)| comms.pinned)
+                .map(|(id, _)| *id)
+                .collect(),
+            subscription_replacements,
+        }
+    }
+
+    /// Keeps the peer in the map when propagating to it fails, e.g. for
+    /// the relays of the node operator to survive a transient congestion.
+    /// The items that cannot be sent to a pinned peer are still dropped,
+    /// and a pinned peer is not evicted to make room for another peer.
+    ///
+    /// The peer stays pinned until it is removed with `remove_peer`.
+    /// Returns whether the peer is in the map.
+    pub fn pin_peer(&self, id: topology::NodeId) -> bool {
+        let mut map = self.mutex.lock().unwrap();
+        match map.get_mut(&id) {
+            Some(comms) => {
+                comms.pinned = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the peer is in the map.
+    pub fn contains_peer(&self, id: topology::NodeId) -> bool {
+        self.mutex.lock().unwrap().contains_key(&id)
+    }
+
+    /// Removes the peer from the map, closing all its subscription streams.
+    /// Returns whether the peer was in the map.
+    pub fn remove_peer(&self, id: topology::NodeId) -> bool {
+        let mut map = self.mutex.lock().unwrap();
+        match map.entry(id) {
+            hash_map::Entry::Occupied(entry) => {
+                self.evict(entry);
+                true
+            }
+            hash_map::Entry::Vacant(_) => false,
+        }
+    }
+
+    /// Closes the subscription streams of the peers not draining them for
+    /// at least `timeout`. The peers are evicted by the next propagation
+    /// to the closed streams.
+    pub fn close_idle_subscriptions(&self, timeout: Duration) {
+        let mut map = self.mutex.lock().unwrap();
+        for (id, comms) in map.iter_mut() {
+            for stream in comms.close_idle(timeout) {
+                warn!(self.logger, "closing the subscription stream not consumed by peer";
+                    "peer" => id.to_string(),
+                    "stream" => stream,
+                );
+            }
+        }
+    }
+
+    /// the ids of the peers currently in the map
+    pub fn node_ids(&self) -> Vec<topology::NodeId> {
+        let map = self.mutex.lock().unwrap();
+        map.keys().cloned().collect()
+    }
+
+    /// the occupancy of the subscription streams of each peer, to find
+    /// out which streams are backing up
+    pub fn occupancy(&self) -> Vec<(topology::NodeId, PeerCommsOccupancy)> {
+        let map = self.mutex.lock().unwrap();
+        map.iter()
+            .map(|(id, comms)| (*id, comms.occupancy()))
+            .collect()
+    }
+
+    /// the subscription replacements of each peer, see
+    /// `SubscriptionReplacements`
+    pub fn subscription_replacements(&self) -> Vec<(topology::NodeId, SubscriptionReplacements)> {
+        let map = self.mutex.lock().unwrap();
+        map.iter()
+            .map(|(id, comms)| (*id, comms.subscription_replacements()))
+            .collect()
+    }
+
+    /// how long each peer has been in the map, telling the stable peers
+    /// from the ones reconnecting frequently
+    pub fn uptimes(&self) -> Vec<(topology::NodeId, Duration)> {
+        let map = self.mutex.lock().unwrap();
+        map.iter()
+            .filter_map(|(id, comms)| comms.uptime().map(|uptime| (*id, uptime)))
+            .collect()
+    }
+
+    /// Counts minor misbehaviors against the peer, e.g. sending gossip
+    /// about our own node, not enough to disconnect from it. The strikes
+    /// are forgotten when the peer leaves the map.
+    pub fn add_strikes(&self, id: topology::NodeId, strikes: u64) {
+        let mut map = self.mutex.lock().unwrap();
+        if let Some(comms) = map.get_mut(&id) {
+            comms.strikes = comms.strikes.saturating_add(strikes);
+        }
+    }
+
+    /// the strikes counted against each peer having some, see
+    /// `add_strikes`
+    pub fn strikes(&self) -> Vec<(topology::NodeId, u64)> {
+        let map = self.mutex.lock().unwrap();
+        map.iter()
+            .filter(|(_, comms)| comms.strikes > 0)
+            .map(|(id, comms)| (*id, comms.strikes))
+            .collect()
+    }
+
+    /// Samples up to `n` distinct peers to send gossip to, weighted by
+    /// their uptime: the peers that have stayed connected for a while are
+    /// sampled more often, the peers just connected still have a chance.
+    pub fn sample_gossip_targets(&self, n: usize) -> Vec<topology::NodeId> {
+        self.sample_gossip_targets_with(n, &mut rand::thread_rng())
+    }
+
+    /// Same as `sample_gossip_targets`, drawing from the given random
+    /// number generator.
+    pub fn sample_gossip_targets_with<R: Rng>(
+        &self,
+        n: usize,
+        rng: &mut R,
+    ) -> Vec<topology::NodeId> {
+        let map = self.mutex.lock().unwrap();
+        sample_targets(&map, n, rng)
+    }
+
+    pub fn insert_peer(&self, id: topology::NodeId, mut handles: PeerComms) {
+        let mut map = self.mutex.lock().unwrap();
+        handles.mark_connected();
+        // a pinned peer reconnecting stays pinned
+        if map.get(&id).map_or(false, |comms| comms.pinned) || self.pinned_on_connect.contains(&id)
+        {
+            handles.pinned = true;
+        }
+        if let Some(previous) = map.insert(id, handles) {
+            self.retire(&previous);
+        }
+    }
+
+    pub fn subscribe_to_block_events(
+        &self,
+        id: topology::NodeId,
+    ) -> Result<BlockEventSubscription, SubscriptionRejected> {
+        let mut map = self.mutex.lock().unwrap();
+        let handles = self.ensure_peer_comms(&mut map, id)?;
+        handles.last_active = Some(Instant::now());
+        let announce_events: BlockEventAnnounceStream = handles
+            .block_announcements
+            .subscribe_notifying(log_first_poll(&self.logger, id, "block"))
+            .map(BlockEvent::Announce);
+        let solicit_events: BlockEventSolicitStream = handles
+            .block_solicitations
+            .subscribe_notifying(log_first_poll(&self.logger, id, "block_solicitation"))
+            .filter_map(Solicitation::into_event);
+        Ok(announce_events.select(solicit_events))
+    }
+
+    pub fn subscribe_to_messages(
+        &self,
+        id: topology::NodeId,
+    ) -> Result<Subscription<Message>, SubscriptionRejected> {
+        let mut map = self.mutex.lock().unwrap();
+        let handles = self.ensure_peer_comms(&mut map, id)?;
+        handles.last_active = Some(Instant::now());
+        Ok(handles
+            .messages
+            .subscribe_notifying(log_first_poll(&self.logger, id, "message")))
+    }
+
+    pub fn subscribe_to_gossip(
+        &self,
+        id: topology::NodeId,
+    ) -> Result<Subscription<Gossip<topology::Node>>, SubscriptionRejected> {
+        let mut map = self.mutex.lock().unwrap();
+        let handles = self.ensure_peer_comms(&mut map, id)?;
+        handles.last_active = Some(Instant::now());
+        Ok(handles
+            .gossip
+            .subscribe_notifying(log_first_poll(&self.logger, id, "gossip")))
+    }
+
+    // whether the overflows of the propagation are put on the node, see
+    // `PeerLimits::propagation_breaker_percent`; the changes are logged
+    fn breaker_tripped(&self, targets: usize, overflows: usize, stream: &'static str) -> bool {
+        let percent = match self.limits.propagation_breaker_percent {
+            Some(percent) => percent,
+            None => return false,
+        };
+        let degraded = breaker_trips(percent, targets, overflows);
+        let mut degraded_streams = self.degraded.lock().unwrap();
+        if degraded {
+            degraded_streams.insert(stream);
+            warn!(self.logger, "propagation degraded, the streams to most peers overflow, not evicting them";
+                "stream" => stream,
+                "overflows" => overflows,
+                "targets" => targets,
+            );
+        } else if degraded_streams.remove(stream) {
+            info!(self.logger, "propagation recovered, evicting the failing peers again";
+                "stream" => stream,
+            );
+        }
+        degraded
+    }
+
+    // Sends an item to the given nodes with the closure `f`, `stream`
+    // naming the stream in the logs.
+    // The peers that are not subscribed to the stream are skipped, the
+    // peers to which sending has failed are removed from the map, unless
+    // the streams of too many of them overflow, see `breaker_tripped`.
+    fn propagate_with<T, F>(
+        &self,
+        nodes: Vec<topology::Node>,
+        stream: &'static str,
         f: F,
-    ) -> Result<(), Vec<topology::Node>>
+    ) -> Result<(), PropagationError>
     where
         F: Fn(&mut PeerComms) -> Result<(), PropagateError<T>>,
     {
+        if nodes.is_empty() {
+            return Err(PropagationError::NoTargets);
+        }
         let mut map = self.mutex.lock().unwrap();
-        let unreached_nodes = nodes
-            .into_iter()
-            .filter(|node| {
-                let id = node.id();
-                if let hash_map::Entry::Occupied(mut entry) = map.entry(id) {
-                    match f(entry.get_mut()) {
-                        Ok(()) => false,
-                        Err(e) => {
-                            info!(
-                                self.logger,
-                                "propagation to peer {} failed: {:?}",
-                                id,
-                                e.kind()
-                            );
-                            debug!(self.logger, "unsubscribing peer {}", id);
-                            entry.remove_entry();
-                            true
-                        }
+        let mut targets = 0;
+        let mut failed = Vec::new();
+        let mut unreached_nodes = Vec::new();
+        for node in nodes {
+            let id = node.id();
+            let comms = match map.get_mut(&id) {
+                Some(comms) => comms,
+                None => {
+                    unreached_nodes.push(node);
+                    continue;
+                }
+            };
+            match f(comms) {
+                Ok(()) => {
+                    targets += 1;
+                    log_watermarks(&self.logger, &self.watermark, id, comms);
+                }
+                // a peer that has not subscribed to this stream
+                // is not failing, it is not interested
+                Err(ref e) if e.kind() == ErrorKind::NotSubscribed => {
+                    debug!(self.logger, "peer is not subscribed, skipping";
+                        "peer" => id.to_string(),
+                        "stream" => stream,
+                    );
+                }
+                Err(e) => {
+                    targets += 1;
+                    if !self.spared(id, comms, e.kind(), stream) {
+                        failed.push((node, e.kind()));
                     }
-                } else {
-                    true
                 }
-            })
-            .collect::<Vec<_>>();
+            }
+        }
+
+        let overflows = failed
+            .iter()
+            .filter(|(_, kind)| *kind == ErrorKind::StreamOverflow)
+            .count();
+        let degraded = self.breaker_tripped(targets, overflows, stream);
+        for (node, kind) in failed {
+            if degraded && kind == ErrorKind::StreamOverflow {
+                continue;
+            }
+            let id = node.id();
+            log_propagation_failure(
+                &self.logger,
+                self.log_policy.level(kind),
+                "propagation to peer failed",
+                id,
+                kind,
+                stream,
+            );
+            if let hash_map::Entry::Occupied(entry) = map.entry(id) {
+                self.evict(entry);
+            }
+            unreached_nodes.push(node);
+        }
         if unreached_nodes.is_empty() {
             Ok(())
         } else {
-            Err(unreached_nodes)
+            Err(PropagationError::Unreached(unreached_nodes))
         }
     }
 
+    /// Classifies the nodes by whether a block announcement could be sent
+    /// to them right now, without sending anything.
+    ///
+    /// The outcome may be different by the time an actual propagation
+    /// takes place, it is meant to help figuring out why blocks do not
+    /// reach some of the peers.
+    pub fn would_propagate(&self, nodes: Vec<topology::Node>) -> PropagationForecast {
+        let map = self.mutex.lock().unwrap();
+        let mut forecast = PropagationForecast::default();
+        for node in nodes {
+            let id = node.id();
+            let res = match map.get(&id) {
+                Some(comms) => comms.block_announcements.probe(),
+                None => Err(ErrorKind::SubscriptionClosed),
+            };
+            match res {
+                Ok(()) => forecast.reachable.push(id),
+                Err(ErrorKind::NotSubscribed) => forecast.not_subscribed.push(id),
+                Err(ErrorKind::StreamOverflow) => forecast.full.push(id),
+                Err(_) => forecast.unreachable.push(id),
+            }
+        }
+        forecast
+    }
+
     pub fn propagate_block(
         &self,
         nodes: Vec<topology::Node>,
         header: Header,
-    ) -> Result<(), Vec<topology::Node>> {
-        self.propagate_with(nodes, |handles| {
+    ) -> Result<(), PropagationError> {
+        self.propagate_with(nodes, "block", |handles| {
             handles.try_send_block_announcement(header.clone())
         })
     }
 
+    /// Records the chain length of the block announced by the peer, as
+    /// the last known tip of the peer.
+    pub fn record_tip(&self, id: topology::NodeId, chain_length: ChainLength) {
+        let mut map = self.mutex.lock().unwrap();
+        if let Some(comms) = map.get_mut(&id) {
+            comms.tip = Some(chain_length);
+        }
+    }
+
+    /// Same as `propagate_block`, the peers known to be behind the block
+    /// being sent it first, the most behind first, then the peers with no
+    /// known tip. The peers known to be ahead of the block are skipped.
+    pub fn propagate_block_to_peers_behind(
+        &self,
+        nodes: Vec<topology::Node>,
+        header: Header,
+    ) -> Result<(), PropagationError> {
+        if nodes.is_empty() {
+            return Err(PropagationError::NoTargets);
+        }
+        let chain_length = header.chain_length();
+        let mut targets = {
+            let map = self.mutex.lock().unwrap();
+            nodes
+                .into_iter()
+                .map(|node| {
+                    let tip = map.get(&node.id()).and_then(|comms| comms.tip);
+                    (tip, node)
+                })
+                .filter(|(tip, _)| tip.map_or(true, |tip| tip <= chain_length))
+                .collect::<Vec<_>>()
+        };
+        if targets.is_empty() {
+            debug!(
+                self.logger,
+                "all the peers are ahead of the block, skipping"
+            );
+            return Ok(());
+        }
+        targets.sort_by_key(|(tip, _)| (tip.is_none(), *tip));
+        let targets = targets.into_iter().map(|(_, node)| node).collect();
+        self.propagate_block(targets, header)
+    }
+
     pub fn propagate_message(
         &self,
         nodes: Vec<topology::Node>,
         message: Message,
-    ) -> Result<(), Vec<topology::Node>> {
-        self.propagate_with(nodes, |handles| handles.try_send_message(message.clone()))
+    ) -> Result<(), PropagationError> {
+        self.propagate_with(nodes, "message", |handles| {
+            handles.try_send_message(message.clone())
+        })
+    }
+
+    /// Propagates the message only to the nodes advertising one of the
+    /// given roles in their node record.
+    pub fn propagate_message_to_roles(
+        &self,
+        nodes: Vec<topology::Node>,
+        message: Message,
+        roles: &[topology::NodeRole],
+    ) -> Result<(), PropagationError> {
+        let nodes = nodes
+            .into_iter()
+            .filter(|node| roles.iter().any(|role| node.has_role(*role)))
+            .collect();
+        self.propagate_message(nodes, message)
+    }
+
+    /// Gives back the gossip to send to the peer if it fits in the gossip
+    /// budget, the gossip is otherwise dropped or deferred, see
+    /// `GossipBudget`.
+    ///
+    /// This is done by the methods of the map sending gossip, the gossip
+    /// sent to a peer by other means must be admitted first. The node
+    /// records of the admitted gossip are compressed as set with
+    /// `with_gossip_compression`.
+    pub fn admit_gossip(
+        &self,
+        target: topology::NodeId,
+        gossip: Gossip<topology::Node>,
+    ) -> Option<Gossip<topology::Node>> {
+        let gossip = if self.gossip_compression == 0 {
+            gossip
+        } else {
+            let threshold = self.gossip_compression;
+            Gossip::from_nodes(
+                gossip
+                    .into_nodes()
+                    .map(|node| node.compressed_from(threshold)),
+            )
+        };
+        match &self.gossip_limiter {
+            None => Some(gossip),
+            Some(limiter) => limiter.lock().unwrap().admit(target, gossip),
+        }
+    }
+
+    /// the gossip not sent when due because of the gossip budget
+    pub fn gossip_over_budget(&self) -> OverBudgetCounts {
+        match &self.gossip_limiter {
+            None => OverBudgetCounts::default(),
+            Some(limiter) => limiter.lock().unwrap().counts(),
+        }
+    }
+
+    /// Sends the gossip deferred by the gossip budget that now fits in it.
+    /// The gossip deferred to the peers no longer in the map is dropped.
+    pub fn send_deferred_gossip(&self) {
+        let limiter = match &self.gossip_limiter {
+            None => return,
+            Some(limiter) => limiter,
+        };
+        let mut map = self.mutex.lock().unwrap();
+        let deferred = limiter.lock().unwrap().take_deferred();
+        for (id, gossip) in deferred {
+            if let hash_map::Entry::Occupied(mut entry) = map.entry(id) {
+                match entry.get_mut().try_send_gossip(gossip) {
+                    Ok(()) => log_watermarks(&self.logger, &self.watermark, id, entry.get_mut()),
+                    Err(ref e) if e.kind() == ErrorKind::NotSubscribed => {}
+                    Err(ref e) if self.spared(id, entry.get(), e.kind(), "gossip") => {}
+                    Err(e) => {
+                        log_propagation_failure(
+                            &self.logger,
+                            self.log_policy.level(e.kind()),
+                            "deferred gossip propagation to peer failed",
+                            id,
+                            e.kind(),
+                            "gossip",
+                        );
+                        self.evict(entry);
+                    }
+                }
+            }
+        }
     }
 
+    /// Sends the gossip to the peer, if it is in the map; the gossip is
+    /// given back in the error otherwise, as `NotSubscribed`, or if sending
+    /// has failed.
+    ///
+    /// The peer is removed from the map if sending has failed, unless the
+    /// failure is spared, see `pin_peer`: use `contains_peer` to tell. The
+    /// gossip beyond the gossip budget is not sent, without failing.
     pub fn propagate_gossip_to(
         &self,
         target: topology::NodeId,
         gossip: Gossip<topology::Node>,
-    ) -> Result<(), Gossip<topology::Node>> {
+    ) -> Result<(), PropagateError<Gossip<topology::Node>>> {
         let mut map = self.mutex.lock().unwrap();
         if let hash_map::Entry::Occupied(mut entry) = map.entry(target) {
+            let gossip = match self.admit_gossip(target, gossip) {
+                Some(gossip) => gossip,
+                None => return Ok(()),
+            };
             let res = {
                 let handles = entry.get_mut();
                 handles.try_send_gossip(gossip)
             };
+            let spared = match &res {
+                Ok(()) => {
+                    log_watermarks(&self.logger, &self.watermark, target, entry.get_mut());
+                    false
+                }
+                Err(e) => self.spared(target, entry.get(), e.kind(), "gossip"),
+            };
+            if spared {
+                return res;
+            }
             res.map_err(|e| {
-                info!(
-                    self.logger,
-                    "gossip propagation to peer {} failed: {:?}",
+                log_propagation_failure(
+                    &self.logger,
+                    self.log_policy.level(e.kind()),
+                    "gossip propagation to peer failed",
                     target,
-                    e.kind()
+                    e.kind(),
+                    "gossip",
                 );
-                debug!(self.logger, "unsubscribing peer {}", target);
-                entry.remove_entry();
-                e.into_item()
+                self.evict(entry);
+                e
             })
         } else {
-            Err(gossip)
+            Err(PropagateError {
+                kind: ErrorKind::NotSubscribed,
+                item: gossip,
+            })
+        }
+    }
+
+    /// Removes all the peers from the map, flushing their subscription
+    /// streams before dropping them, which closes the connections.
+    ///
+    /// The returned future resolves once all the peers are dropped,
+    /// the peers that fail to flush in time are dropped anyway.
+    pub fn shutdown(&self, timeout: Duration) -> impl Future<Item = (), Error = ()> {
+        let peers = {
+            let mut map = self.mutex.lock().unwrap();
+            map.drain().collect::<Vec<_>>()
+        };
+        for (id, _) in &peers {
+            (self.on_evict)(*id);
         }
+        let logger = self.logger.clone();
+        let flushes = peers
+            .into_iter()
+            .map(move |(id, comms)| {
+                let logger = logger.clone();
+                let mut comms = comms;
+                comms.flush(timeout).then(move |res| {
+                    if let Err(e) = res {
+                        warn!(logger, "failed to flush the streams to peer";
+                            "peer" => id.to_string(),
+                            "reason" => format!("{:?}", e),
+                        );
+                    }
+                    comms.close_all();
+                    Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+        future::join_all(flushes).map(|_| ())
+    }
+
+    /// Sends the gossip to up to `fanout` of the connected peers, chosen
+    /// as with `sample_gossip_targets`, and returns the outcome for each
+    /// of them.
+    ///
+    /// The peers to which sending has failed are removed from the map.
+    /// The gossip beyond the gossip budget is not sent, without failing.
+    /// Use `propagate_gossip_to` to send gossip to a given peer.
+    pub fn propagate_gossip(
+        &self,
+        gossip: Gossip<topology::Node>,
+        fanout: usize,
+    ) -> Vec<(topology::NodeId, Result<(), ErrorKind>)> {
+        let mut map = self.mutex.lock().unwrap();
+        let ids = sample_targets(&map, fanout, &mut rand::thread_rng());
+
+        ids.into_iter()
+            .map(|id| {
+                let res = match map.entry(id) {
+                    hash_map::Entry::Occupied(mut entry) => {
+                        let gossip = match self.admit_gossip(id, gossip.clone()) {
+                            Some(gossip) => gossip,
+                            None => return (id, Ok(())),
+                        };
+                        match entry.get_mut().try_send_gossip(gossip) {
+                            Ok(()) => Ok(()),
+                            Err(ref e) if e.kind() == ErrorKind::NotSubscribed => {
+                                Err(ErrorKind::NotSubscribed)
+                            }
+                            Err(ref e) if self.spared(id, entry.get(), e.kind(), "gossip") => {
+                                Err(e.kind())
+                            }
+                            Err(e) => {
+                                log_propagation_failure(
+                                    &self.logger,
+                                    self.log_policy.level(e.kind()),
+                                    "gossip propagation to peer failed",
+                                    id,
+                                    e.kind(),
+                                    "gossip",
+                                );
+                                self.evict(entry);
+                                Err(e.kind())
+                            }
+                        }
+                    }
+                    // the targets are sampled from the map held locked,
+                    // this is only defensive
+                    hash_map::Entry::Vacant(_) => Err(ErrorKind::NotSubscribed),
+                };
+                (id, res)
+            })
+            .collect()
     }
 
-    pub fn solicit_blocks(&self, node_id: topology::NodeId, hashes: Vec<HeaderHash>) {
+    /// Queues a solicitation for blocks with the given hashes to the peer.
+    ///
+    /// Returns a handle that can be used to cancel the solicitation, e.g.
+    /// when it is superseded by a newer one. If the peer is not connected,
+    /// the solicitation is given back in the error, so the caller can
+    /// connect to the peer and send it.
+    pub fn solicit_blocks(
+        &self,
+        node_id: topology::NodeId,
+        hashes: Vec<HeaderHash>,
+    ) -> Result<SolicitationHandle, SolicitBlocksError> {
+        let (solicitation, handle) = Solicitation::new(hashes);
         let mut map = self.mutex.lock().unwrap();
         match map.get_mut(&node_id) {
-            Some(comms) => comms
-                .block_solicitations
-                .try_send(hashes)
-                .unwrap_or_else(|e| {
-                    warn!(
-                        self.logger,
-                        "block solicitation from {} failed: {:?}", node_id, e
+            Some(comms) => match comms.try_send_block_solicitation(solicitation) {
+                Ok(()) => {
+                    log_watermarks(&self.logger, &self.watermark, node_id, comms);
+                    Ok(handle)
+                }
+                Err(e) => {
+                    warn!(self.logger, "block solicitation from peer failed";
+                        "peer" => node_id.to_string(),
+                        "error_kind" => e.kind().tag(),
+                        "stream" => "block_solicitation",
                     );
-                }),
-            None => {
-                // TODO: connect and request on demand?
-                warn!(
-                    self.logger,
-                    "peer {} not available to solicit blocks from", node_id
-                );
+                    Err(SolicitBlocksError::Failed(e.kind()))
+                }
+            },
+            None => Err(SolicitBlocksError::NotConnected(solicitation)),
+        }
+    }
+}
+
+/// Helpers to exercise the propagation of items to a subscription
+/// without a connection task polling its stream.
+#[cfg(any(test, feature = "testing"))]
+pub mod testing {
+    use super::*;
+
+    /// A subscribed `CommHandle` along with the stream of its
+    /// subscription, which is only polled out on demand.
+    pub struct SubscriptionProbe<T> {
+        handle: CommHandle<T>,
+        subscription: Option<Subscription<T>>,
+    }
+
+    impl<T> Default for SubscriptionProbe<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<T> SubscriptionProbe<T> {
+        pub fn new() -> Self {
+            let mut handle = CommHandle::default();
+            let subscription = Some(handle.subscribe());
+            SubscriptionProbe {
+                handle,
+                subscription,
+            }
+        }
+
+        pub fn handle(&mut self) -> &mut CommHandle<T> {
+            &mut self.handle
+        }
+
+        pub fn try_send(&mut self, item: T) -> Result<(), PropagateError<T>> {
+            self.handle.try_send(item)
+        }
+
+        /// Sends the items until one is refused, the error of the refused
+        /// item is returned. The items left in the iterator are not sent.
+        pub fn fill<I>(&mut self, items: I) -> Option<PropagateError<T>>
+        where
+            I: IntoIterator<Item = T>,
+        {
+            for item in items {
+                if let Err(e) = self.handle.try_send(item) {
+                    return Some(e);
+                }
             }
+            None
+        }
+
+        /// Polls out all the items buffered in the subscription, in the
+        /// order they were sent.
+        pub fn drain(&mut self) -> Vec<T> {
+            let subscription = match &mut self.subscription {
+                Some(subscription) => subscription,
+                None => return Vec::new(),
+            };
+            future::poll_fn(|| {
+                let mut items = Vec::new();
+                while let Ok(Async::Ready(Some(item))) = subscription.poll() {
+                    items.push(item);
+                }
+                Ok::<_, ()>(Async::Ready(items))
+            })
+            .wait()
+            .unwrap()
+        }
+
+        /// Drops the subscription stream, as the connection task of
+        /// a disconnected peer does.
+        pub fn disconnect(&mut self) {
+            self.subscription = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::SubscriptionProbe;
+    use super::*;
+
+    enum MockBehavior {
+        Accept,
+        Overflow,
+        Disconnect,
+    }
+
+    struct MockSender {
+        behavior: MockBehavior,
+    }
+
+    impl SubscriptionSender<u32> for MockSender {
+        fn try_send(&mut self, item: u32) -> Result<(), TrySendError<u32>> {
+            match self.behavior {
+                MockBehavior::Accept => Ok(()),
+                MockBehavior::Overflow => Err(TrySendError::Full(item)),
+                MockBehavior::Disconnect => Err(TrySendError::Disconnected(item)),
+            }
+        }
+    }
+
+    fn mock_handle(behavior: MockBehavior) -> CommHandle<u32, MockSender> {
+        let mut handle = CommHandle::default();
+        handle.subscribe_with(MockSender { behavior });
+        handle
+    }
+
+    #[test]
+    fn not_subscribed() {
+        let mut handle: CommHandle<u32, MockSender> = CommHandle::default();
+        let err = handle.try_send(42).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotSubscribed);
+        assert_eq!(err.into_item(), 42);
+    }
+
+    #[test]
+    fn sent_to_subscriber() {
+        let mut handle = mock_handle(MockBehavior::Accept);
+        handle.try_send(42).unwrap();
+    }
+
+    #[test]
+    fn stream_overflow() {
+        let mut handle = mock_handle(MockBehavior::Overflow);
+        let err = handle.try_send(42).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::StreamOverflow);
+        assert_eq!(err.into_item(), 42);
+    }
+
+    #[test]
+    fn subscription_closed() {
+        let mut handle = mock_handle(MockBehavior::Disconnect);
+        let err = handle.try_send(42).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::SubscriptionClosed);
+        assert_eq!(err.into_item(), 42);
+    }
+
+    #[test]
+    fn error_kind_tags() {
+        let tags = [
+            (ErrorKind::NotSubscribed, "not_subscribed"),
+            (ErrorKind::SubscriptionClosed, "subscription_closed"),
+            (ErrorKind::StreamOverflow, "stream_overflow"),
+        ];
+        for (kind, tag) in tags.iter() {
+            assert_eq!(serde_json::to_value(kind).unwrap(), *tag);
+            assert_eq!(kind.tag(), *tag);
+        }
+    }
+
+    #[test]
+    fn subscription_overflow() {
+        let mut probe = SubscriptionProbe::new();
+        let err = probe.fill(0..).unwrap();
+        assert_eq!(err.kind(), ErrorKind::StreamOverflow);
+        let refused = err.into_item();
+        assert!(refused as usize >= BUFFER_LEN);
+
+        assert_eq!(probe.drain(), (0..refused).collect::<Vec<u32>>());
+        probe.try_send(refused).unwrap();
+        assert_eq!(probe.drain(), vec![refused]);
+    }
+
+    #[test]
+    fn subscription_disconnected() {
+        let mut probe = SubscriptionProbe::new();
+        probe.try_send(42).unwrap();
+        probe.disconnect();
+
+        let err = probe.try_send(43).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::SubscriptionClosed);
+        assert_eq!(err.into_item(), 43);
+        assert!(probe.drain().is_empty());
+    }
+
+    #[test]
+    fn buffered_items_are_tracked() {
+        let mut handle: CommHandle<u32> = CommHandle::default();
+        let subscription = handle.subscribe();
+        let buffered = handle.buffered().unwrap();
+        assert!(buffered.is_flushed());
+
+        handle.try_send(42).unwrap();
+        assert!(!buffered.is_flushed());
+
+        let (item, subscription) = subscription.into_future().wait().ok().unwrap();
+        assert_eq!(item, Some(42));
+        assert!(buffered.is_flushed());
+
+        handle.try_send(43).unwrap();
+        drop(subscription);
+        assert!(buffered.is_flushed());
+    }
+
+    #[test]
+    fn watermark_crossings_are_reported_once() {
+        let watermark = OccupancyWatermark::default();
+        let mut handle: CommHandle<u32> = CommHandle::default();
+        let mut subscription = handle.subscribe();
+
+        for i in 0..5 {
+            handle.try_send(i).unwrap();
+            assert_eq!(handle.check_watermark(&watermark), None);
+        }
+        handle.try_send(5).unwrap();
+        match handle.check_watermark(&watermark) {
+            Some(WatermarkCrossing::Raised(occupancy)) => assert_eq!(occupancy.len, 6),
+            crossing => panic!("unexpected crossing: {:?}", crossing),
+        }
+        handle.try_send(6).unwrap();
+        assert_eq!(handle.check_watermark(&watermark), None);
+
+        for _ in 0..4 {
+            let (_, rest) = subscription.into_future().wait().ok().unwrap();
+            subscription = rest;
+            assert_eq!(handle.check_watermark(&watermark), None);
+        }
+        let (_, _subscription) = subscription.into_future().wait().ok().unwrap();
+        match handle.check_watermark(&watermark) {
+            Some(WatermarkCrossing::Cleared(occupancy)) => assert_eq!(occupancy.len, 2),
+            crossing => panic!("unexpected crossing: {:?}", crossing),
+        }
+    }
+
+    #[test]
+    fn idle_subscription_is_closed() {
+        let mut handle: CommHandle<u32> = CommHandle::default();
+        let _subscription = handle.subscribe();
+
+        // nothing to drain, the stream is not idle
+        assert!(!handle.close_if_idle(Duration::from_secs(0)));
+
+        handle.try_send(42).unwrap();
+        assert!(!handle.close_if_idle(Duration::from_secs(3600)));
+        assert!(handle.close_if_idle(Duration::from_secs(0)));
+
+        let err = handle.try_send(43).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::SubscriptionClosed);
+        assert_eq!(handle.occupancy(), None);
+    }
+
+    fn limited_peer_map(evict_least_active: bool) -> PeerMap {
+        let limits = PeerLimits {
+            max_peers: Some(1),
+            evict_least_active,
+            eviction_warmup: Duration::from_secs(0),
+            propagation_breaker_percent: None,
+        };
+        PeerMap::new(
+            PropagationLogPolicy::default(),
+            OccupancyWatermark::default(),
+            limits,
+            None,
+            |_| {},
+            Logger::root(slog::Discard, o!()),
+        )
+    }
+
+    #[test]
+    fn gossip_targets_are_sampled_by_weight() {
+        use rand::SeedableRng;
+        use rand_chacha::ChaChaRng;
+
+        let (stable, new) = (topology::NodeId::generate(), topology::NodeId::generate());
+        let weighted = vec![(stable, 10.0), (new, 1.0)];
+        let mut rng = ChaChaRng::from_seed([7; 32]);
+
+        let mut sampled = weighted_sample(weighted.clone(), 5, &mut rng);
+        sampled.sort();
+        let mut all = vec![stable, new];
+        all.sort();
+        assert_eq!(sampled, all);
+
+        let mut stable_count = 0;
+        let mut new_count = 0;
+        for _ in 0..1000 {
+            match weighted_sample(weighted.clone(), 1, &mut rng)[..] {
+                [id] if id == stable => stable_count += 1,
+                [id] if id == new => new_count += 1,
+                ref sample => panic!("unexpected sample: {:?}", sample),
+            }
+        }
+        assert!(stable_count > 800);
+        assert!(new_count > 0);
+
+        let sample = |seed| weighted_sample(weighted.clone(), 1, &mut ChaChaRng::from_seed(seed));
+        assert_eq!(sample([1; 32]), sample([1; 32]));
+    }
+
+    #[test]
+    fn uptime_starts_when_the_peer_is_inserted() {
+        let peers = limited_peer_map(false);
+        let (first, second) = (topology::NodeId::generate(), topology::NodeId::generate());
+        assert!(PeerComms::new().uptime().is_none());
+
+        assert!(peers.subscribe_to_gossip(first).is_ok());
+        assert_eq!(
+            peers
+                .uptimes()
+                .iter()
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>(),
+            vec![first]
+        );
+
+        peers.remove_peer(first);
+        peers.insert_peer(second, PeerComms::new());
+        assert_eq!(
+            peers
+                .uptimes()
+                .iter()
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>(),
+            vec![second]
+        );
+    }
+
+    #[test]
+    fn subscribing_again_counts_a_replacement() {
+        let peers = limited_peer_map(false);
+        let id = topology::NodeId::generate();
+
+        assert!(peers.subscribe_to_messages(id).is_ok());
+        assert_eq!(peers.stats().subscription_replacements.total(), 0);
+
+        assert!(peers.subscribe_to_messages(id).is_ok());
+        assert_eq!(
+            peers.subscription_replacements(),
+            vec![(
+                id,
+                SubscriptionReplacements {
+                    messages: 1,
+                    ..Default::default()
+                }
+            )]
+        );
+
+        // the count of the node does not go down when the peer reconnects
+        peers.insert_peer(id, PeerComms::new());
+        assert_eq!(peers.stats().subscription_replacements.messages, 1);
+        peers.remove_peer(id);
+        assert_eq!(peers.stats().subscription_replacements.messages, 1);
+    }
+
+    #[test]
+    fn subscriptions_beyond_the_limit_are_rejected() {
+        let peers = limited_peer_map(false);
+        let (first, second) = (topology::NodeId::generate(), topology::NodeId::generate());
+
+        assert!(peers.subscribe_to_gossip(first).is_ok());
+        // the peers already in the map can subscribe again
+        assert!(peers.subscribe_to_gossip(first).is_ok());
+        match peers.subscribe_to_gossip(second) {
+            Err(SubscriptionRejected::CapacityReached { limit }) => assert_eq!(limit, 1),
+            Ok(_) => panic!("subscription beyond the limit accepted"),
+        }
+        assert_eq!(
+            peers.stats(),
+            PeerMapStats {
+                peer_count: 1,
+                max_peers: Some(1),
+                pinned_peers: Vec::new(),
+                subscription_replacements: SubscriptionReplacements {
+                    gossip: 1,
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn least_active_peer_is_evicted() {
+        let peers = limited_peer_map(true);
+        let (first, second) = (topology::NodeId::generate(), topology::NodeId::generate());
+
+        assert!(peers.subscribe_to_gossip(first).is_ok());
+        assert!(peers.subscribe_to_gossip(second).is_ok());
+        assert_eq!(peers.node_ids(), vec![second]);
+    }
+
+    #[test]
+    fn pinned_peer_is_not_evicted() {
+        let peers = limited_peer_map(true);
+        let (first, second) = (topology::NodeId::generate(), topology::NodeId::generate());
+
+        assert!(!peers.pin_peer(first));
+        assert!(peers.subscribe_to_gossip(first).is_ok());
+        assert!(peers.pin_peer(first));
+        match peers.subscribe_to_gossip(second) {
+            Err(SubscriptionRejected::CapacityReached { limit }) => assert_eq!(limit, 1),
+            Ok(_) => panic!("pinned peer evicted"),
+        }
+        assert_eq!(peers.stats().pinned_peers, vec![first]);
+
+        assert!(peers.remove_peer(first));
+        assert!(peers.stats().pinned_peers.is_empty());
+
+        let peers = limited_peer_map(true).with_pinned_peers(vec![second]);
+        assert!(peers.subscribe_to_gossip(second).is_ok());
+        assert_eq!(peers.stats().pinned_peers, vec![second]);
+        assert!(peers.subscribe_to_gossip(first).is_err());
+    }
+
+    #[test]
+    fn breaker_trips_over_the_percentage_of_overflows() {
+        assert!(!breaker_trips(50, 10, 5));
+        assert!(breaker_trips(50, 10, 6));
+        // too few targets to tell
+        assert!(!breaker_trips(50, 3, 3));
+        assert!(breaker_trips(0, BREAKER_MIN_TARGETS, 1));
+        assert!(!breaker_trips(100, 10, 10));
+    }
+
+    #[test]
+    fn cancelled_solicitation_is_not_an_event() {
+        let (solicitation, handle) = Solicitation::new(Vec::new());
+        assert!(!solicitation.is_cancelled());
+        handle.cancel();
+        assert!(solicitation.is_cancelled());
+        assert!(solicitation.into_event().is_none());
+
+        let (solicitation, _handle) = Solicitation::new(Vec::new());
+        match solicitation.into_event() {
+            Some(BlockEvent::Solicit(hashes)) => assert!(hashes.is_empty()),
+            _ => panic!("expected a solicitation event"),
+        }
+    }
+
+    #[test]
+    fn probe_does_not_send() {
+        let mut handle: CommHandle<u32> = CommHandle::default();
+        assert_eq!(handle.probe(), Err(ErrorKind::NotSubscribed));
+
+        let subscription = handle.subscribe();
+        assert_eq!(handle.probe(), Ok(()));
+        let buffered = handle.buffered().unwrap();
+        assert!(buffered.is_flushed());
+
+        for i in 0..BUFFER_LEN as u32 {
+            handle.try_send(i).unwrap();
+        }
+        // the slot of the sender is still free
+        assert_eq!(handle.probe(), Ok(()));
+        handle.try_send(BUFFER_LEN as u32).unwrap();
+        assert_eq!(handle.probe(), Err(ErrorKind::StreamOverflow));
+        assert_eq!(
+            handle.try_send(0).map_err(|e| e.kind()),
+            Err(ErrorKind::StreamOverflow)
+        );
+
+        drop(subscription);
+        assert_eq!(handle.probe(), Err(ErrorKind::SubscriptionClosed));
+    }
+
+    #[test]
+    fn first_poll_is_notified() {
+        let mut handle: CommHandle<u32> = CommHandle::default();
+        let polled = Arc::new(AtomicUsize::new(0));
+        let subscription = {
+            let polled = polled.clone();
+            handle.subscribe_notifying(move || {
+                polled.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        assert_eq!(polled.load(Ordering::SeqCst), 0);
+
+        handle.try_send(42).unwrap();
+        handle.try_send(43).unwrap();
+        let (_, subscription) = subscription.into_future().wait().ok().unwrap();
+        assert_eq!(polled.load(Ordering::SeqCst), 1);
+        let (_, _subscription) = subscription.into_future().wait().ok().unwrap();
+        assert_eq!(polled.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn close_all_ends_the_streams() {
+        let mut comms = PeerComms::new();
+        let announcements = comms.subscribe_to_block_announcements();
+        let messages = comms.subscribe_to_messages();
+        let gossip = comms.subscribe_to_gossip();
+
+        comms.close_all();
+        assert_eq!(comms.occupancy().block_announcements, None);
+        let (item, _) = announcements.into_future().wait().ok().unwrap();
+        assert!(item.is_none());
+        let (item, _) = messages.into_future().wait().ok().unwrap();
+        assert!(item.is_none());
+        let (item, _) = gossip.into_future().wait().ok().unwrap();
+        assert!(item.is_none());
+    }
+
+    #[test]
+    fn occupancy_is_tracked() {
+        let mut handle: CommHandle<u32> = CommHandle::default();
+        assert_eq!(handle.occupancy(), None);
+
+        let subscription = handle.subscribe();
+        handle.try_send(42).unwrap();
+        assert_eq!(
+            handle.occupancy(),
+            Some(StreamOccupancy {
+                len: 1,
+                capacity: CHANNEL_CAPACITY
+            })
+        );
+
+        let (_, subscription) = subscription.into_future().wait().ok().unwrap();
+        assert_eq!(handle.occupancy().map(|occupancy| occupancy.len), Some(0));
+
+        // the stream is full once it holds its capacity
+        for i in 0..CHANNEL_CAPACITY as u32 {
+            handle.try_send(i).unwrap();
         }
+        assert_eq!(
+            handle.occupancy().map(|occupancy| occupancy.len),
+            Some(CHANNEL_CAPACITY)
+        );
+        assert_eq!(
+            handle.try_send(0).map_err(|e| e.kind()),
+            Err(ErrorKind::StreamOverflow)
+        );
+        drop(subscription);
     }
 }
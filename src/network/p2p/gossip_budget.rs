@@ -0,0 +1,186 @@
+//! node-wide budget of the gossip sent to the peers
+
+use super::topology;
+use network_core::gossip::Gossip;
+use std::{cmp, collections::HashMap, time::Instant};
+
+/// A budget of the bytes of gossip sent to all the peers of the node per
+/// second, so a node on a metered connection keeps its bandwidth within
+/// bounds during a gossip storm. This is on top of the limits applying to
+/// each peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GossipBudget {
+    /// the bytes of gossip sent per second, also the largest burst
+    pub bytes_per_second: u64,
+    /// what becomes of the gossip beyond the budget
+    #[serde(default)]
+    pub over_budget: OverBudget,
+}
+
+/// What becomes of the gossip beyond the `GossipBudget`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverBudget {
+    /// drop the gossip, the next gossip rounds send fresher node records
+    Drop,
+    /// keep the last gossip to each peer, to send it once the budget
+    /// allows; older gossip deferred to the same peer is dropped
+    Defer,
+}
+
+impl Default for OverBudget {
+    fn default() -> Self {
+        OverBudget::Drop
+    }
+}
+
+/// The number of gossip messages that have not been sent when they were
+/// due because of the `GossipBudget`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct OverBudgetCounts {
+    pub dropped: usize,
+    pub deferred: usize,
+}
+
+// A token bucket of bytes, refilled at a constant rate up to one second
+// worth of bytes.
+struct TokenBucket {
+    rate: u64,
+    tokens: u64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        TokenBucket {
+            rate,
+            tokens: rate,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.refilled_at);
+        let refill = elapsed.as_secs().saturating_mul(self.rate)
+            + u64::from(elapsed.subsec_nanos()) * self.rate / 1_000_000_000;
+        // the time elapsed too short to be worth a byte is not lost
+        if refill > 0 {
+            self.tokens = cmp::min(self.rate, self.tokens.saturating_add(refill));
+            self.refilled_at = now;
+        }
+    }
+
+    // A full bucket lets through an amount larger than the rate, so a
+    // large gossip message is delayed rather than never sent.
+    fn try_take(&mut self, amount: u64, now: Instant) -> bool {
+        self.refill(now);
+        if amount <= self.tokens {
+            self.tokens -= amount;
+            true
+        } else if self.tokens == self.rate {
+            self.tokens = 0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The state of the `GossipBudget` shared by the gossip send paths.
+pub(super) struct GossipLimiter {
+    over_budget: OverBudget,
+    bucket: TokenBucket,
+    deferred: HashMap<topology::NodeId, Gossip<topology::Node>>,
+    counts: OverBudgetCounts,
+}
+
+// the bytes of the node records of the gossip, as encoded on the wire
+fn gossip_size(gossip: &Gossip<topology::Node>) -> u64 {
+    gossip
+        .clone()
+        .into_nodes()
+        .map(|node| node.encoded_size())
+        .sum()
+}
+
+impl GossipLimiter {
+    pub fn new(budget: GossipBudget) -> Self {
+        GossipLimiter {
+            over_budget: budget.over_budget,
+            bucket: TokenBucket::new(budget.bytes_per_second, Instant::now()),
+            deferred: HashMap::new(),
+            counts: OverBudgetCounts::default(),
+        }
+    }
+
+    pub fn counts(&self) -> OverBudgetCounts {
+        self.counts
+    }
+
+    /// Gives back the gossip if it fits in the budget, the gossip is
+    /// otherwise dropped or deferred.
+    pub fn admit(
+        &mut self,
+        target: topology::NodeId,
+        gossip: Gossip<topology::Node>,
+    ) -> Option<Gossip<topology::Node>> {
+        if self.bucket.try_take(gossip_size(&gossip), Instant::now()) {
+            return Some(gossip);
+        }
+        match self.over_budget {
+            OverBudget::Drop => self.counts.dropped += 1,
+            OverBudget::Defer => {
+                if self.deferred.insert(target, gossip).is_some() {
+                    self.counts.dropped += 1;
+                }
+                self.counts.deferred += 1;
+            }
+        }
+        None
+    }
+
+    /// Takes the deferred gossip that now fits in the budget.
+    pub fn take_deferred(&mut self) -> Vec<(topology::NodeId, Gossip<topology::Node>)> {
+        let now = Instant::now();
+        let mut admitted = Vec::new();
+        let targets = self.deferred.keys().cloned().collect::<Vec<_>>();
+        for target in targets {
+            let size = gossip_size(&self.deferred[&target]);
+            if !self.bucket.try_take(size, now) {
+                break;
+            }
+            let gossip = self.deferred.remove(&target).unwrap();
+            admitted.push((target, gossip));
+        }
+        admitted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn bucket_refills_at_the_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+
+        assert!(bucket.try_take(600, start));
+        assert!(!bucket.try_take(600, start));
+        assert!(bucket.try_take(600, start + Duration::from_millis(200)));
+        // never more than a second worth of bytes
+        assert!(bucket.try_take(1000, start + Duration::from_secs(10)));
+        assert!(!bucket.try_take(1, start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn full_bucket_lets_a_large_amount_through() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(100, start);
+
+        assert!(bucket.try_take(250, start));
+        assert!(!bucket.try_take(250, start + Duration::from_millis(500)));
+        assert!(bucket.try_take(250, start + Duration::from_secs(1)));
+    }
+}
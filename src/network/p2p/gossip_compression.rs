@@ -0,0 +1,101 @@
+//! compression of the node records sent in the gossip
+//!
+//! The node records are framed one by one in the gossip messages, so a
+//! compressed record starts with a marker for the receiver to tell it from
+//! a plain record. The records are decoded in both forms whatever the
+//! configuration, only the compression of the records sent is configured,
+//! see `PeerMap::with_gossip_compression`.
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use std::{
+    cmp,
+    io::{Read, Write},
+};
+
+// the marker starting a node record compressed with deflate, a plain record
+// starts with the length of the node id
+const DEFLATE_MARKER: &[u8] = b"\0jgz";
+
+// the largest node record decompressed, a peer does not get to make the
+// node allocate more than this for one record
+const MAX_RECORD_LEN: u64 = 64 * 1024;
+
+/// The compression of the node records sent in the gossip, off unless
+/// configured. The peers of the node need to decode the compressed records,
+/// so this is only to be set once all the peers of the network run a
+/// version that does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GossipCompression {
+    /// the compression applied to the node records
+    pub codec: GossipCodec,
+    /// the size in bytes of the encoded node record from which it is
+    /// compressed, the smaller records gaining little from it
+    pub threshold: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GossipCodec {
+    Deflate,
+}
+
+/// the size from which the node records sent are compressed, `0` to send
+/// them plain
+pub fn threshold(compression: Option<GossipCompression>) -> usize {
+    match compression {
+        Some(GossipCompression {
+            codec: GossipCodec::Deflate,
+            threshold,
+        }) => cmp::max(threshold, 1),
+        None => 0,
+    }
+}
+
+/// the bytes to send for the encoded node record: compressed if the
+/// record is at least of the threshold size, see `threshold`, and it gets
+/// smaller, plain otherwise
+pub fn compress(record: Vec<u8>, threshold: usize) -> Vec<u8> {
+    if threshold == 0 || record.len() < threshold {
+        return record;
+    }
+    let mut encoder = DeflateEncoder::new(DEFLATE_MARKER.to_vec(), Compression::fast());
+    match encoder.write_all(&record).and_then(|()| encoder.finish()) {
+        Ok(compressed) if compressed.len() < record.len() => compressed,
+        _ => record,
+    }
+}
+
+/// the encoded node record from the bytes received if they are a compressed
+/// record, `None` if they are to be decoded as a plain record
+pub fn decompress(bytes: &[u8]) -> Option<Vec<u8>> {
+    if !bytes.starts_with(DEFLATE_MARKER) {
+        return None;
+    }
+    let mut record = Vec::new();
+    DeflateDecoder::new(&bytes[DEFLATE_MARKER.len()..])
+        .take(MAX_RECORD_LEN + 1)
+        .read_to_end(&mut record)
+        .ok()?;
+    if record.len() as u64 > MAX_RECORD_LEN {
+        return None;
+    }
+    Some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_compressed_from_the_threshold() {
+        let record = vec![7u8; 512];
+
+        assert_eq!(compress(record.clone(), 0), record);
+        assert_eq!(compress(record.clone(), 513), record);
+        assert_eq!(decompress(&record), None);
+
+        let compressed = compress(record.clone(), 512);
+        assert!(compressed.len() < record.len());
+        assert_eq!(decompress(&compressed), Some(record));
+    }
+}
@@ -1,2 +1,5 @@
 pub mod comm;
+pub mod gossip_budget;
+pub mod gossip_compression;
+pub mod snapshot;
 pub mod topology;
@@ -0,0 +1,72 @@
+//! saving the connected peers to a file, to reconnect to them
+//! first when the node restarts.
+//!
+
+use super::{
+    comm::PeerMap,
+    topology::{NodeId, P2pTopology},
+};
+use network_core::gossip::Node as _;
+use serde::{Deserialize, Serialize};
+use std::{fs::File, io, net::SocketAddr, path::Path};
+
+custom_error! {pub Error
+    Io { source: io::Error } = "cannot access the peer snapshot file",
+    Format { source: serde_yaml::Error } = "invalid peer snapshot",
+}
+
+/// the identities of the peers connected at some point in time,
+/// not their connection state
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PeerSnapshot {
+    peers: Vec<SnapshotPeer>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotPeer {
+    id: NodeId,
+    address: SocketAddr,
+    // the peer was pinned, see `PeerMap::pin_peer`, for the operator
+    // reading the snapshot; it is not pinned again from the snapshot
+    #[serde(default)]
+    pinned: bool,
+}
+
+impl PeerSnapshot {
+    /// collect the peers currently in the map whose address is
+    /// known from the topology
+    pub fn capture(peers: &PeerMap, topology: &P2pTopology) -> Self {
+        let ids = peers.node_ids();
+        let pinned = peers.stats().pinned_peers;
+        let peers = topology
+            .view()
+            .filter(|node| ids.contains(&node.id()))
+            .filter_map(|node| {
+                node.address().map(|address| SnapshotPeer {
+                    id: node.id(),
+                    address,
+                    pinned: pinned.contains(&node.id()),
+                })
+            })
+            .collect();
+        PeerSnapshot { peers }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        Ok(serde_yaml::from_reader(file)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let file = File::create(path)?;
+        Ok(serde_yaml::to_writer(file, self)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn addresses<'a>(&'a self) -> impl Iterator<Item = SocketAddr> + 'a {
+        self.peers.iter().map(|peer| peer.address)
+    }
+}
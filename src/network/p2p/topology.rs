@@ -1,28 +1,82 @@
 //! module defining the p2p topology management objects
 //!
 
+use super::gossip_compression;
 use bincode;
 use chain_core::property;
 use network_core::gossip::{self, Node as _};
 use poldercast::topology::{Cyclon, Module, Rings, Topology, Vicinity};
 use poldercast::Subscription;
 pub use poldercast::{Address, InterestLevel};
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{self, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 use slog::Logger;
-use std::{collections::BTreeMap, fmt, io, net::SocketAddr, sync::RwLock};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet, VecDeque},
+    fmt,
+    hash::{Hash, Hasher},
+    io,
+    net::SocketAddr,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
 
 pub const NEW_MESSAGES_TOPIC: u32 = 0u32;
 pub const NEW_BLOCKS_TOPIC: u32 = 1u32;
+pub const RELAY_ROLE_TOPIC: u32 = 2u32;
+pub const BLOCK_PRODUCER_ROLE_TOPIC: u32 = 3u32;
+
+// A node record received again within this window, from any peer, is
+// ignored rather than updating the topology and being gossiped on again.
+const GOSSIP_DEDUP_WINDOW: Duration = Duration::from_secs(10);
+
+// Maximum number of recently received node records remembered.
+const GOSSIP_DEDUP_CAPACITY: usize = 4096;
+
+/// the roles a node can advertise in its node record
+///
+/// The roles are advertised as subscriptions to dedicated topics, a node
+/// with no role is a passive node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeRole {
+    /// the node forwards blocks and fragments to the other nodes
+    Relay,
+    /// the node creates blocks
+    BlockProducer,
+}
+
+impl NodeRole {
+    const ALL: [NodeRole; 2] = [NodeRole::Relay, NodeRole::BlockProducer];
+
+    fn topic(self) -> u32 {
+        match self {
+            NodeRole::Relay => RELAY_ROLE_TOPIC,
+            NodeRole::BlockProducer => BLOCK_PRODUCER_ROLE_TOPIC,
+        }
+    }
+}
 
 custom_error! {pub Error
     Encoding { source: bincode::Error } = "Serialization error",
     Io { source: io::Error } = "I/O Error",
 }
 
+/// A node record, along with the size from which it is compressed when
+/// sent in the gossip, see `gossip_compression::threshold`.
 #[derive(Clone, Debug)]
-pub struct Node(poldercast::Node);
-
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Node(poldercast::Node, usize);
+
+/// The id of a node. It serializes to a string, rendered as in the logs,
+/// e.g. in the settings and the peer snapshot:
+///
+/// ```json
+/// "271828182845904523536028747135266249775"
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct NodeId(pub poldercast::Id);
 
 impl gossip::Node for Node {
@@ -41,10 +95,41 @@ impl gossip::Node for Node {
 
 impl gossip::NodeId for NodeId {}
 
+/// The node record in a stable format, independent of the gossip wire
+/// format, to show the peers to the node operator:
+///
+/// ```json
+/// {"id": "271828...", "address": "/ip4/10.0.0.1/tcp/8299", "roles": ["relay"]}
+/// ```
+///
+/// The id is given as a string, rendered as in the logs.
+impl Serialize for Node {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let roles = NodeRole::ALL
+            .iter()
+            .filter(|role| self.has_role(**role))
+            .collect::<Vec<_>>();
+        let mut state = serializer.serialize_struct("Node", 3)?;
+        state.serialize_field("id", &self.id().to_string())?;
+        state.serialize_field("address", &self.0.address().to_string())?;
+        state.serialize_field("roles", &roles)?;
+        state.end()
+    }
+}
+
 impl Node {
     #[inline]
     pub fn new(id: NodeId, address: Address) -> Self {
-        Node(poldercast::Node::new(id.0, address))
+        Node(poldercast::Node::new(id.0, address), 0)
+    }
+
+    /// the record compressed from the given size when sent in the gossip,
+    /// see `gossip_compression::threshold`
+    pub fn compressed_from(self, threshold: usize) -> Self {
+        Node(self.0, threshold)
     }
 
     pub fn add_message_subscription(&mut self, interest_level: InterestLevel) {
@@ -56,6 +141,24 @@ impl Node {
         self.0
             .add_subscription(Subscription::new(NEW_BLOCKS_TOPIC.into(), interest_level));
     }
+
+    /// advertise the given role in the node record
+    pub fn add_role(&mut self, role: NodeRole) {
+        self.0.add_subscription(Subscription::new(
+            role.topic().into(),
+            InterestLevel::Normal,
+        ));
+    }
+
+    /// check the node record advertises the given role
+    pub fn has_role(&self, role: NodeRole) -> bool {
+        self.0.subscriptions().contains(role.topic().into())
+    }
+
+    /// the size in bytes of the node record sent in gossip
+    pub fn encoded_size(&self) -> u64 {
+        bincode::serialized_size(&self.0).unwrap_or(0)
+    }
 }
 
 impl NodeId {
@@ -71,26 +174,156 @@ impl fmt::Display for NodeId {
     }
 }
 
+impl NodeId {
+    /// the node id rendered as the given number, `None` if it cannot be
+    /// made into an id
+    pub fn from_u128(value: u128) -> Option<Self> {
+        // poldercast has no constructor from the number, its id is
+        // encoded as the number by bincode
+        let bytes = bincode::serialize(&value).ok()?;
+        bincode::deserialize(&bytes).ok().map(NodeId)
+    }
+}
+
+impl Serialize for NodeId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct NodeIdVisitor;
+
+        impl<'de> Visitor<'de> for NodeIdVisitor {
+            type Value = NodeId;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a node id, as a string of decimal digits")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<NodeId, E> {
+                let value = value
+                    .parse()
+                    .map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))?;
+                self.visit_u128(value)
+            }
+
+            // the ids written as numbers are taken as well
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<NodeId, E> {
+                self.visit_u128(u128::from(value))
+            }
+
+            fn visit_u128<E: de::Error>(self, value: u128) -> Result<NodeId, E> {
+                NodeId::from_u128(value).ok_or_else(|| E::custom("invalid node id"))
+            }
+        }
+
+        deserializer.deserialize_any(NodeIdVisitor)
+    }
+}
+
 /// object holding the P2pTopology of the Node
 pub struct P2pTopology {
     lock: RwLock<Topology>,
+    our_id: NodeId,
+    /// the address and the subscriptions of our node as we announce them,
+    /// see `claimed_state`
+    our_state: Option<Vec<u8>>,
+    /// when the record of each node was last received in a gossip, only
+    /// kept for `max_gossip_age`
+    last_updates: RwLock<HashMap<NodeId, Instant>>,
+    max_gossip_age: Option<Duration>,
+    /// the nodes evicted from the peer map, left out of the view until
+    /// their record is received again
+    evicted: RwLock<HashSet<NodeId>>,
+    recent_gossip: Mutex<RecentGossip>,
     logger: Logger,
 }
 
+// the content hashes of the node records received recently, so the
+// copies of a record received from several peers are only taken once
+struct RecentGossip {
+    hashes: HashSet<u64>,
+    // oldest first
+    received: VecDeque<(u64, Instant)>,
+}
+
+impl RecentGossip {
+    fn new() -> Self {
+        RecentGossip {
+            hashes: HashSet::new(),
+            received: VecDeque::new(),
+        }
+    }
+
+    // remembers the record hash, returns false if it has been received
+    // within the window already
+    fn insert(&mut self, hash: u64, now: Instant) -> bool {
+        while let Some((oldest, received_at)) = self.received.front().cloned() {
+            let expired = now.duration_since(received_at) >= GOSSIP_DEDUP_WINDOW;
+            if !expired && self.received.len() < GOSSIP_DEDUP_CAPACITY {
+                break;
+            }
+            self.received.pop_front();
+            self.hashes.remove(&oldest);
+        }
+        if !self.hashes.insert(hash) {
+            return false;
+        }
+        self.received.push_back((hash, now));
+        true
+    }
+}
+
+// the part of the node record a peer could alter to divert the traffic of
+// the node: its address and its subscriptions, `None` if it cannot be
+// encoded
+fn claimed_state(node: &Node) -> Option<Vec<u8>> {
+    bincode::serialize(&(node.0.address(), node.0.subscriptions())).ok()
+}
+
+// the hash of the content of the node record, `None` if the record
+// cannot be encoded
+fn content_hash(node: &Node) -> Option<u64> {
+    let bytes = bincode::serialize(&node.0).ok()?;
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+// The node records are compressed from the threshold they are given with
+// `compressed_from`, the records received are decoded in both forms.
 impl property::Serialize for Node {
     type Error = Error;
 
-    fn serialize<W: std::io::Write>(&self, writer: W) -> Result<(), Self::Error> {
-        Ok(bincode::serialize_into(writer, &self.0)?)
+    fn serialize<W: std::io::Write>(&self, mut writer: W) -> Result<(), Self::Error> {
+        let record = gossip_compression::compress(bincode::serialize(&self.0)?, self.1);
+        writer.write_all(&record)?;
+        Ok(())
     }
 }
 
 impl property::Deserialize for Node {
     type Error = Error;
 
-    fn deserialize<R: std::io::BufRead>(reader: R) -> Result<Self, Self::Error> {
-        let inner = bincode::deserialize_from(reader)?;
-        Ok(Node(inner))
+    fn deserialize<R: std::io::BufRead>(mut reader: R) -> Result<Self, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        // a plain record may start with the marker of the compressed ones
+        if let Some(record) = gossip_compression::decompress(&bytes) {
+            if let Ok(inner) = bincode::deserialize(&record) {
+                return Ok(Node(inner, 0));
+            }
+        }
+        let inner = bincode::deserialize(&bytes)?;
+        Ok(Node(inner, 0))
     }
 }
 
@@ -115,9 +348,18 @@ impl P2pTopology {
     /// create a new P2pTopology for the given Address and Id
     ///
     /// The address is the public
-    pub fn new(node: Node, logger: Logger) -> Self {
+    ///
+    /// The node records not received again within `max_gossip_age`, if
+    /// set, are not gossiped on, see `retain_fresh_gossips`.
+    pub fn new(node: Node, max_gossip_age: Option<Duration>, logger: Logger) -> Self {
         P2pTopology {
+            our_id: node.id(),
+            our_state: claimed_state(&node),
             lock: RwLock::new(Topology::new(node.0)),
+            last_updates: RwLock::new(HashMap::new()),
+            max_gossip_age,
+            evicted: RwLock::new(HashSet::new()),
+            recent_gossip: Mutex::new(RecentGossip::new()),
             logger,
         }
     }
@@ -140,6 +382,8 @@ impl P2pTopology {
 
     /// Returns a list of neighbors selected in this turn
     /// to contact for event dissemination.
+    ///
+    /// The nodes evicted, see `evict_node`, are left out.
     pub fn view(&self) -> impl Iterator<Item = Node> {
         let topology = self.lock.read().unwrap();
         debug!(
@@ -147,20 +391,75 @@ impl P2pTopology {
             "loading P2P local topology view {:?}",
             topology.view()
         );
-        topology.view().into_iter().map(Node)
+        let evicted = self.evicted.read().unwrap();
+        topology
+            .view()
+            .into_iter()
+            .map(|node| Node(node, 0))
+            .filter(|node| !evicted.contains(&node.id()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// to call when the peer of the given node has been evicted from the
+    /// peer map. The node is left out of the view and of the gossip until
+    /// its record is received again.
+    pub fn evict_node(&self, id: NodeId) {
+        debug!(self.logger, "evicting node from the P2P local topology"; "node" => id.to_string());
+        self.evicted.write().unwrap().insert(id);
+        self.last_updates.write().unwrap().remove(&id);
     }
 
     /// this is the function to utilise when we receive a gossip in order
     /// to update the P2P Topology internal state
-    pub fn update<I>(&self, new_nodes: I)
+    ///
+    /// The node records already received within a short window, from
+    /// this peer or another one, are skipped.
+    ///
+    /// The records claiming to be our own node are never trusted, whatever
+    /// the peer says about us: they are skipped. A peer relaying our record
+    /// as we announce it is only echoing it, the number of the records of
+    /// our node claiming another address or other subscriptions is
+    /// returned.
+    pub fn update<I>(&self, new_nodes: I) -> usize
     where
         I: IntoIterator<Item = Node>,
     {
-        let tree = new_nodes
-            .into_iter()
-            .map(|node| (node.id().0, node.0))
-            .collect();
-        self.update_tree(tree)
+        let now = Instant::now();
+        let mut altered = 0;
+        let tree: BTreeMap<_, _> = {
+            let mut recent_gossip = self.recent_gossip.lock().unwrap();
+            new_nodes
+                .into_iter()
+                .filter(|node| {
+                    let is_ours = node.id() == self.our_id;
+                    if is_ours && claimed_state(node) != self.our_state {
+                        altered += 1;
+                    }
+                    !is_ours
+                })
+                .filter(|node| {
+                    content_hash(node).map_or(true, |hash| recent_gossip.insert(hash, now))
+                })
+                .map(|node| (node.id().0, node.0))
+                .collect()
+        };
+        {
+            let mut evicted = self.evicted.write().unwrap();
+            for id in tree.keys() {
+                evicted.remove(&NodeId(id.clone()));
+            }
+        }
+        if let Some(max_age) = self.max_gossip_age {
+            let mut last_updates = self.last_updates.write().unwrap();
+            // the records not received again since are stale anyway
+            last_updates.retain(|_, last_update| now.duration_since(*last_update) <= max_age);
+            for id in tree.keys() {
+                last_updates.insert(NodeId(id.clone()), now);
+            }
+        }
+        self.update_tree(tree);
+        altered
     }
 
     fn update_tree(&self, new_nodes: BTreeMap<poldercast::Id, poldercast::Node>) {
@@ -181,7 +480,38 @@ impl P2pTopology {
         topology
             .select_gossips(&gossip_recipient.0)
             .into_iter()
-            .map(|(_, v)| Node(v))
+            .map(|(_, v)| Node(v, 0))
+    }
+
+    /// keep only the node records that have been received in a gossip
+    /// less than `max_gossip_age` ago, all of them if not set. The records
+    /// of the nodes evicted, see `evict_node`, are never kept.
+    ///
+    /// The node records of the gossip carry no timestamp of their own,
+    /// the freshness of a record is the time it was last received. Our
+    /// own node record is always fresh, the records of the other nodes
+    /// not received within `max_gossip_age` are stale.
+    pub fn retain_fresh_gossips<I>(&self, gossips: I) -> Vec<Node>
+    where
+        I: IntoIterator<Item = Node>,
+    {
+        let evicted = self.evicted.read().unwrap();
+        let gossips = gossips
+            .into_iter()
+            .filter(|node| !evicted.contains(&node.id()));
+        let max_age = match self.max_gossip_age {
+            Some(max_age) => max_age,
+            None => return gossips.collect(),
+        };
+        let last_updates = self.last_updates.read().unwrap();
+        gossips
+            .filter(|node| {
+                node.id() == self.our_id
+                    || last_updates
+                        .get(&node.id())
+                        .map_or(false, |last_update| last_update.elapsed() <= max_age)
+            })
+            .collect()
     }
 }
 
@@ -225,3 +555,114 @@ pub mod modules {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_id_serializes_to_a_string() {
+        let id = NodeId::generate();
+        let json = serde_json::to_value(&id).unwrap();
+        assert_eq!(json, serde_json::Value::String(id.to_string()));
+        assert_eq!(serde_json::from_value::<NodeId>(json).unwrap(), id);
+
+        let id = NodeId::from_u128(42).unwrap();
+        assert_eq!(id.to_string(), "42");
+        assert_eq!(serde_json::from_str::<NodeId>("42").unwrap(), id);
+    }
+
+    // `from_u128` relies on the encoding of the poldercast id by bincode,
+    // this catches a change of it
+    #[test]
+    fn node_id_round_trips_through_u128() {
+        for _ in 0..16 {
+            let id = NodeId::generate();
+            assert_eq!(NodeId::from_u128(id.0.as_u128()), Some(id));
+        }
+        for value in [0, 1, u128::max_value()].iter() {
+            let id = NodeId::from_u128(*value).unwrap();
+            assert_eq!(id.0.as_u128(), *value);
+        }
+    }
+
+    #[test]
+    fn recent_gossip_is_deduplicated_within_the_window() {
+        let mut recent = RecentGossip::new();
+        let now = Instant::now();
+
+        assert!(recent.insert(1, now));
+        assert!(!recent.insert(1, now + Duration::from_secs(1)));
+        assert!(recent.insert(2, now + Duration::from_secs(1)));
+        assert!(recent.insert(1, now + GOSSIP_DEDUP_WINDOW));
+    }
+
+    #[test]
+    fn only_the_records_received_recently_are_fresh() {
+        let node = || {
+            Node::new(
+                NodeId::generate(),
+                "/ip4/127.0.0.1/tcp/8299".parse().unwrap(),
+            )
+        };
+        let (ours, received, unknown) = (node(), node(), node());
+        let topology = P2pTopology::new(
+            ours.clone(),
+            Some(Duration::from_secs(3600)),
+            Logger::root(slog::Discard, o!()),
+        );
+
+        topology.update(vec![received.clone()]);
+        let fresh = topology.retain_fresh_gossips(vec![ours.clone(), received.clone(), unknown]);
+        let fresh_ids = fresh.iter().map(|node| node.id()).collect::<Vec<_>>();
+        assert_eq!(fresh_ids, vec![ours.id(), received.id()]);
+    }
+
+    #[test]
+    fn only_the_altered_records_of_our_node_are_reported() {
+        let address = || "/ip4/127.0.0.1/tcp/8299".parse().unwrap();
+        let ours = Node::new(NodeId::generate(), address());
+        let topology = P2pTopology::new(ours.clone(), None, Logger::root(slog::Discard, o!()));
+
+        assert_eq!(topology.update(vec![ours.clone()]), 0);
+
+        let mut altered = Node::new(ours.id(), address());
+        altered.add_role(NodeRole::BlockProducer);
+        let moved = Node::new(ours.id(), "/ip4/10.0.0.1/tcp/8299".parse().unwrap());
+        assert_eq!(topology.update(vec![altered, moved]), 2);
+    }
+
+    #[test]
+    fn evicted_nodes_are_not_gossiped() {
+        let node = || {
+            Node::new(
+                NodeId::generate(),
+                "/ip4/127.0.0.1/tcp/8299".parse().unwrap(),
+            )
+        };
+        let (ours, evicted, kept) = (node(), node(), node());
+        let topology = P2pTopology::new(ours, None, Logger::root(slog::Discard, o!()));
+
+        topology.update(vec![evicted.clone(), kept.clone()]);
+        topology.evict_node(evicted.id());
+        let fresh = topology.retain_fresh_gossips(vec![evicted, kept.clone()]);
+        let fresh_ids = fresh.iter().map(|node| node.id()).collect::<Vec<_>>();
+        assert_eq!(fresh_ids, vec![kept.id()]);
+    }
+
+    #[test]
+    fn node_serializes_to_a_stable_json_shape() {
+        let id = NodeId::generate();
+        let mut node = Node::new(id, "/ip4/127.0.0.1/tcp/8299".parse().unwrap());
+        node.add_role(NodeRole::BlockProducer);
+
+        assert_eq!(
+            serde_json::to_value(&node).unwrap(),
+            json!({
+                "id": id.to_string(),
+                "address": "/ip4/127.0.0.1/tcp/8299",
+                "roles": ["block_producer"],
+            })
+        );
+    }
+}
@@ -113,9 +113,11 @@ impl BlockService for NodeService {
 
     fn get_blocks(&mut self, ids: &[Self::BlockId]) -> Self::GetBlocksFuture {
         let (handle, stream) = stream_reply(self.logger().clone());
+        let mut ids = ids.to_vec();
+        self.global_state.config.limit_block_solicitation(&mut ids);
         self.channels
             .client_box
-            .send_to(ClientMsg::GetBlocks(ids.into(), handle));
+            .send_to(ClientMsg::GetBlocks(ids, handle));
         future::ok(stream)
     }
 
@@ -163,18 +165,22 @@ impl BlockService for NodeService {
     where
         In: Stream<Item = Self::Header, Error = core_error::Error> + Send + 'static,
     {
-        subscription::process_block_announcements(
-            inbound,
-            subscriber,
-            self.channels.block_box.clone(),
-            self.logger().clone(),
-        );
-
+        // the inbound stream is only processed once the peer is accepted
         let subscription = self
             .global_state
             .peers
-            .subscribe_to_block_events(subscriber);
-        future::ok(subscription)
+            .subscribe_to_block_events(subscriber)
+            .map_err(|e| intercom::Error::from(e).into());
+        if subscription.is_ok() {
+            subscription::process_block_announcements(
+                inbound,
+                subscriber,
+                self.global_state.clone(),
+                self.channels.block_box.clone(),
+                self.logger().clone(),
+            );
+        }
+        future::result(subscription)
     }
 }
 
@@ -224,9 +230,19 @@ impl GossipService for NodeService {
     where
         In: Stream<Item = Gossip<Self::Node>, Error = core_error::Error> + Send + 'static,
     {
-        subscription::process_gossip(inbound, self.global_state.clone(), self.logger().clone());
-
-        let subscription = self.global_state.peers.subscribe_to_gossip(subscriber);
-        future::ok(subscription)
+        let subscription = self
+            .global_state
+            .peers
+            .subscribe_to_gossip(subscriber)
+            .map_err(|e| intercom::Error::from(e).into());
+        if subscription.is_ok() {
+            subscription::process_gossip(
+                inbound,
+                subscriber,
+                self.global_state.clone(),
+                self.logger().clone(),
+            );
+        }
+        future::result(subscription)
     }
 }
@@ -3,6 +3,7 @@ use super::{
     GlobalStateR,
 };
 use crate::{blockcfg::Header, intercom::BlockMsg, utils::async_msg::MessageBox};
+use chain_core::property::Header as _;
 use futures::prelude::*;
 use network_core::{error as core_error, gossip::Gossip};
 use slog::Logger;
@@ -10,6 +11,7 @@ use slog::Logger;
 pub fn process_block_announcements<S>(
     inbound: S,
     node_id: NodeId,
+    state: GlobalStateR,
     mut block_box: MessageBox<BlockMsg>,
     logger: Logger,
 ) -> tokio::executor::Spawn
@@ -19,6 +21,7 @@ where
     tokio::spawn(
         inbound
             .for_each(move |header| {
+                state.peers.record_tip(node_id, header.chain_length());
                 block_box
                     .try_send(BlockMsg::AnnouncedBlock(header, node_id))
                     .unwrap();
@@ -30,7 +33,12 @@ where
     )
 }
 
-pub fn process_gossip<S>(inbound: S, state: GlobalStateR, logger: Logger) -> tokio::executor::Spawn
+pub fn process_gossip<S>(
+    inbound: S,
+    node_id: NodeId,
+    state: GlobalStateR,
+    logger: Logger,
+) -> tokio::executor::Spawn
 where
     S: Stream<Item = Gossip<Node>, Error = core_error::Error> + Send + 'static,
 {
@@ -39,7 +47,16 @@ where
         inbound
             .for_each(move |gossip| {
                 debug!(logger, "received gossip: {:?}", gossip);
-                state.topology.update(gossip.into_nodes());
+                let altered = state.topology.update(gossip.into_nodes());
+                if altered > 0 {
+                    // the echoes of our own record are fine, the peer is
+                    // trying to alter the view of our node
+                    info!(logger, "ignoring the gossip altering our own node";
+                        "peer" => node_id.to_string(),
+                        "records" => altered,
+                    );
+                    state.peers.add_strikes(node_id, altered as u64);
+                }
                 Ok(())
             })
             .map_err(move |err| {
@@ -7,12 +7,20 @@ pub mod v0;
 pub use self::server::{Error, Server};
 
 use crate::blockchain::BlockchainR;
+use crate::fragment::{selection::SelectionHandle, Pool};
 use crate::settings::start::{Error as ConfigError, Rest};
+use crate::shutdown::ShutdownHandle;
+use slog::Logger;
 
 pub struct Context {
     pub stats_counter: v0::node::stats::StatsCounter,
     pub blockchain: BlockchainR,
     pub transaction_task: v0::message::Task,
+    pub network_task: v0::network::Task,
+    pub fragment_selection: SelectionHandle,
+    pub fragment_pool: Pool,
+    pub shutdown: ShutdownHandle,
+    pub logger: Logger,
 }
 
 pub fn start_rest_server(config: &Rest, context: Context) -> Result<Server, ConfigError> {
@@ -24,9 +32,25 @@ pub fn start_rest_server(config: &Rest, context: Context) -> Result<Server, Conf
     Server::builder(config.pkcs12.clone(), config.listen.clone(), prefix)
         .add_handler(v0::account::create_handler(context.blockchain.clone()))
         .add_handler(v0::block::create_handler(context.blockchain.clone()))
+        .add_handler(v0::fragment_selection::create_handler(
+            context.fragment_selection,
+            context.fragment_pool,
+            config.fragment_selection_update.unwrap_or(false),
+        ))
         .add_handler(v0::node::stats::create_handler(context.stats_counter))
+        .add_handler(v0::shutdown::create_handler(
+            context.shutdown,
+            config.shutdown_endpoint.unwrap_or(false),
+        ))
         .add_handler(v0::tip::create_handler(context.blockchain.clone()))
-        .add_handler(v0::message::create_handler(context.transaction_task))
+        .add_handler(v0::message::create_handler(
+            context.transaction_task,
+            context.logger.clone(),
+        ))
+        .add_handler(v0::network::create_handler(
+            context.network_task,
+            context.logger,
+        ))
         .add_handler(v0::utxo::create_handler(context.blockchain))
         .build()
         .map_err(|e| e.into())
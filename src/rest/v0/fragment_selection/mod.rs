@@ -0,0 +1,192 @@
+use crate::blockcfg::Value;
+use crate::fragment::selection::{
+    EstimateError, SelectionConfig, SelectionHandle, SelectionStrategy,
+};
+use crate::fragment::{metrics, Pool};
+use actix_web::error::{ErrorBadRequest, ErrorConflict, ErrorForbidden, ErrorInternalServerError};
+use actix_web::{App, Error as ActixError, HttpRequest, HttpResponse, Json, Responder, State};
+use futures::{future, Future};
+
+#[derive(Clone)]
+pub struct Context {
+    fragment_selection: SelectionHandle,
+    fragment_pool: Pool,
+    allow_update: bool,
+}
+
+/// the selection can only be changed with `allow_update`, it is only
+/// queried otherwise
+pub fn create_handler(
+    fragment_selection: SelectionHandle,
+    fragment_pool: Pool,
+    allow_update: bool,
+) -> impl Fn(&str) -> App<Context> + Send + Sync + Clone + 'static {
+    let context = Context {
+        fragment_selection,
+        fragment_pool,
+        allow_update,
+    };
+    move |prefix: &str| {
+        let app_prefix = format!("{}/v0/fragment_selection", prefix);
+        App::with_state(context.clone())
+            .prefix(app_prefix)
+            .resource("", |r| {
+                r.get().with(handle_get);
+                r.put().with(handle_put);
+            })
+            .resource("/inclusion_estimate", |r| {
+                r.get().a(handle_inclusion_estimate)
+            })
+            .resource("/fee_histogram", |r| r.get().a(handle_fee_histogram))
+            .resource("/metrics", |r| r.get().a(handle_metrics))
+    }
+}
+
+#[derive(Deserialize)]
+struct SelectionChange {
+    strategy: Option<SelectionStrategy>,
+    audit: Option<bool>,
+}
+
+fn handle_get(context: State<Context>) -> impl Responder {
+    let current = context.fragment_selection.current();
+    Json(json!({
+        "strategy": current.strategy,
+        "audit": current.audit,
+    }))
+}
+
+/// change the strategy or the audit mode of the next blocks, the other
+/// parameters of the selection are kept
+fn handle_put(
+    (context, change): (State<Context>, Json<SelectionChange>),
+) -> Result<impl Responder, ActixError> {
+    if !context.allow_update {
+        return Err(ErrorForbidden(
+            "the fragment selection cannot be changed, see the rest settings",
+        ));
+    }
+    let fragment_selection = &context.fragment_selection;
+    let current = fragment_selection.current();
+    let config = SelectionConfig {
+        strategy: change.strategy.unwrap_or(current.strategy),
+        audit: change.audit.unwrap_or(current.audit),
+        ..current
+    };
+    let previous = fragment_selection
+        .replace(config)
+        .map_err(|e| ErrorBadRequest(e.to_string()))?;
+    Ok(Json(json!({
+        "previous": previous.strategy,
+        "strategy": config.strategy,
+        "audit": config.audit,
+    })))
+}
+
+/// estimate the number of blocks until a fragment paying the `fee` given
+/// in the query would be included with the current selection
+fn handle_inclusion_estimate(
+    request: &HttpRequest<Context>,
+) -> impl Future<Item = impl Responder + 'static, Error = impl Into<ActixError> + 'static> + 'static
+{
+    let context = request.state().clone();
+    let fee = match request.query().get("fee").map(|fee| fee.parse::<u64>()) {
+        Some(Ok(fee)) => Value(fee),
+        _ => return future::Either::A(future::err(ErrorBadRequest("missing or invalid fee"))),
+    };
+    let estimate = context
+        .fragment_pool
+        .estimate_inclusion(context.fragment_selection.current(), fee)
+        .map(Json)
+        .map_err(estimate_error);
+    future::Either::B(estimate)
+}
+
+// the strategy not depending on the fee is the configuration of the node,
+// the fee of the query is at fault otherwise
+fn estimate_error(error: EstimateError) -> ActixError {
+    match error {
+        EstimateError::NotFeeAware => ErrorConflict(error.to_string()),
+        EstimateError::NeverSelected => ErrorBadRequest(error.to_string()),
+    }
+}
+
+/// the number of fragments pending in the pool in each fee range, the
+/// ranges being delimited by the comma separated `bounds` of the query
+fn handle_fee_histogram(
+    request: &HttpRequest<Context>,
+) -> impl Future<Item = impl Responder + 'static, Error = impl Into<ActixError> + 'static> + 'static
+{
+    let bounds = match request.query().get("bounds") {
+        None => Ok(Vec::new()),
+        Some(bounds) => bounds
+            .split(',')
+            .filter(|bound| !bound.is_empty())
+            .map(|bound| bound.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>(),
+    };
+    let bounds = match bounds {
+        Ok(bounds) => bounds,
+        Err(_) => return future::Either::A(future::err(ErrorBadRequest("invalid fee bounds"))),
+    };
+    let histogram = request
+        .state()
+        .fragment_pool
+        .fee_histogram(bounds)
+        .map(|histogram| {
+            let ranges = histogram
+                .into_iter()
+                .map(|(range, count)| {
+                    json!({
+                        "min": range.min,
+                        "max": range.max,
+                        "count": count,
+                    })
+                })
+                .collect::<Vec<_>>();
+            Json(ranges)
+        })
+        .map_err(|()| ErrorInternalServerError("cannot read the fragment pool"));
+    future::Either::B(histogram)
+}
+
+/// the totals of the selections and the usage of the pool, in the
+/// Prometheus text format
+fn handle_metrics(
+    request: &HttpRequest<Context>,
+) -> impl Future<Item = impl Responder + 'static, Error = impl Into<ActixError> + 'static> + 'static
+{
+    let totals = request.state().fragment_selection.totals();
+    request
+        .state()
+        .fragment_pool
+        .stats()
+        .map(move |pool| {
+            HttpResponse::Ok()
+                .content_type("text/plain; version=0.0.4")
+                .body(metrics::render_prometheus(&totals, &pool))
+        })
+        .map_err(|()| ErrorInternalServerError("cannot read the fragment pool usage"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    fn status(error: EstimateError) -> StatusCode {
+        estimate_error(error)
+            .as_response_error()
+            .error_response()
+            .status()
+    }
+
+    #[test]
+    fn estimate_without_fee_strategy_is_a_conflict() {
+        assert_eq!(status(EstimateError::NotFeeAware), StatusCode::CONFLICT);
+        assert_eq!(
+            status(EstimateError::NeverSelected),
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
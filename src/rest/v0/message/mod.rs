@@ -1,39 +1,96 @@
-use crate::fragment;
-use crate::intercom::TransactionMsg;
+use crate::blockcfg::BlockDate;
+use crate::fragment::{self, PoolInsertError};
+use crate::intercom::{self, TransactionMsg};
 use crate::utils::async_msg::MessageBox;
-use actix_web::error::ErrorBadRequest;
+use actix_web::error::{
+    ErrorBadRequest, ErrorConflict, ErrorInternalServerError, ErrorServiceUnavailable,
+};
 use actix_web::{App, Error as ActixError, HttpMessage, HttpRequest, Responder};
 use bytes::IntoBuf;
 use chain_core::property::Deserialize;
 use chain_impl_mockchain::message::Message;
 use futures::Future;
+use slog::Logger;
 use std::sync::{Arc, Mutex};
 
 pub type Task = Arc<Mutex<MessageBox<TransactionMsg>>>;
 
+#[derive(Clone)]
+pub struct State {
+    transaction_task: Task,
+    logger: Logger,
+}
+
 pub fn create_handler(
     transaction_task: Task,
-) -> impl Fn(&str) -> App<Task> + Send + Sync + Clone + 'static {
+    logger: Logger,
+) -> impl Fn(&str) -> App<State> + Send + Sync + Clone + 'static {
+    let state = State {
+        transaction_task,
+        logger,
+    };
     move |prefix: &str| {
         let app_prefix = format!("{}/v0/message", prefix);
-        App::with_state(transaction_task.clone())
+        App::with_state(state.clone())
             .prefix(app_prefix)
             .resource("", |r| r.post().a(handle_request))
     }
 }
 
+/// submit the fragment to the pool, the response tells whether it has
+/// entered the pool: 503 if the pool is full or the fragment task too busy
+/// to take it, 409 if the fragment is already known and 400 if it is
+/// rejected.
 fn handle_request(
-    request: &HttpRequest<Task>,
+    request: &HttpRequest<State>,
 ) -> impl Future<Item = impl Responder + 'static, Error = impl Into<ActixError> + 'static> + 'static
 {
-    let sender = request.state().clone();
-    request.body().map(move |message| -> Result<_, ActixError> {
-        let msg = Message::deserialize(message.into_buf()).map_err(|e| {
-            println!("{}", e);
-            ErrorBadRequest(e)
-        })?;
-        let msg = TransactionMsg::SendTransaction(fragment::Origin::Rest, vec![msg]);
-        sender.lock().unwrap().try_send(msg).unwrap();
-        Ok("")
-    })
+    let state = request.state().clone();
+    let valid_until = request
+        .query()
+        .get("valid_until")
+        .map(|date| parse_block_date(date));
+    request
+        .body()
+        .map_err(ActixError::from)
+        .and_then(move |message| -> Result<_, ActixError> {
+            let msg = Message::deserialize(message.into_buf()).map_err(|e| {
+                println!("{}", e);
+                ErrorBadRequest(e)
+            })?;
+            let valid_until = valid_until.map_or(Ok(None), |date| date.map(Some))?;
+            let (reply_handle, reply_future) =
+                intercom::unary_reply::<_, intercom::Error>(state.logger.clone());
+            state
+                .transaction_task
+                .lock()
+                .unwrap()
+                .try_send(TransactionMsg::SubmitTransaction(
+                    fragment::Origin::Rest,
+                    msg,
+                    valid_until,
+                    reply_handle,
+                ))
+                .map_err(|_| ErrorServiceUnavailable("the fragment task is not available"))?;
+            Ok(reply_future.map_err(ErrorInternalServerError))
+        })
+        .and_then(|reply_future| reply_future)
+        .and_then(|result| {
+            result.map(|()| "").map_err(|error| match error {
+                PoolInsertError::Full => ErrorServiceUnavailable(error),
+                PoolInsertError::Duplicate => ErrorConflict(error),
+                PoolInsertError::Invalid { .. } => ErrorBadRequest(error),
+            })
+        })
+}
+
+/// parse a block date given as `<epoch>.<slot>`
+fn parse_block_date(date: &str) -> Result<BlockDate, ActixError> {
+    let mut parts = date.splitn(2, '.');
+    let epoch = parts.next().and_then(|epoch| epoch.parse().ok());
+    let slot_id = parts.next().and_then(|slot_id| slot_id.parse().ok());
+    match (epoch, slot_id) {
+        (Some(epoch), Some(slot_id)) => Ok(BlockDate { epoch, slot_id }),
+        _ => Err(ErrorBadRequest(format!("invalid block date '{}'", date))),
+    }
 }
@@ -1,6 +1,9 @@
 pub mod account;
 pub mod block;
+pub mod fragment_selection;
 pub mod message;
+pub mod network;
 pub mod node;
+pub mod shutdown;
 pub mod tip;
 pub mod utxo;
@@ -0,0 +1,90 @@
+use crate::intercom::{self, NetworkMsg};
+use crate::network::NetworkStats;
+use crate::utils::async_msg::MessageBox;
+use actix_web::error::{ErrorInternalServerError, ErrorServiceUnavailable};
+use actix_web::{App, Error as ActixError, HttpRequest, Json, Responder};
+use futures::{future, Future};
+use serde_json::Value;
+use slog::Logger;
+use std::sync::{Arc, Mutex};
+
+pub type Task = Arc<Mutex<MessageBox<NetworkMsg>>>;
+
+#[derive(Clone)]
+pub struct State {
+    network_task: Task,
+    logger: Logger,
+}
+
+pub fn create_handler(
+    network_task: Task,
+    logger: Logger,
+) -> impl Fn(&str) -> App<State> + Send + Sync + Clone + 'static {
+    let state = State {
+        network_task,
+        logger,
+    };
+    move |prefix: &str| {
+        let app_prefix = format!("{}/v0/network", prefix);
+        App::with_state(state.clone())
+            .prefix(app_prefix)
+            .resource("/stats", |r| r.get().a(handle_stats))
+    }
+}
+
+/// the statistics of the peers of the node, 503 if the network task is
+/// too busy to be asked
+fn handle_stats(
+    request: &HttpRequest<State>,
+) -> impl Future<Item = impl Responder + 'static, Error = impl Into<ActixError> + 'static> + 'static
+{
+    let state = request.state();
+    let (reply_handle, reply_future) =
+        intercom::unary_reply::<_, intercom::Error>(state.logger.clone());
+    let sent = state
+        .network_task
+        .lock()
+        .unwrap()
+        .try_send(NetworkMsg::GetStats(reply_handle));
+    if sent.is_err() {
+        return future::Either::A(future::err(ErrorServiceUnavailable(
+            "the network task is not available",
+        )));
+    }
+    let stats = reply_future
+        .map(|stats| Json(stats_json(&stats)))
+        .map_err(ErrorInternalServerError);
+    future::Either::B(stats)
+}
+
+fn stats_json(stats: &NetworkStats) -> Value {
+    let peers = stats
+        .occupancy
+        .iter()
+        .map(|(id, occupancy)| {
+            let strikes = stats
+                .strikes
+                .iter()
+                .find(|(struck, _)| struck == id)
+                .map_or(0, |(_, strikes)| *strikes);
+            json!({
+                "id": id.to_string(),
+                "occupancy": occupancy,
+                "strikes": strikes,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "peer_count": stats.peers.peer_count,
+        "max_peers": stats.peers.max_peers,
+        "pinned_peers": stats
+            .peers
+            .pinned_peers
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>(),
+        "subscription_replacements": stats.peers.subscription_replacements,
+        "gossip_over_budget": stats.gossip_over_budget,
+        "peers": peers,
+    })
+}
@@ -16,9 +16,21 @@ pub fn create_handler(
 }
 
 fn handle_request(stats: State<StatsCounter>) -> impl Responder {
+    let last_broadcast_block = stats.get_last_broadcast_block().map(|(hash, date)| {
+        json!({
+            "hash": hash.to_string(),
+            "date": date.to_string(),
+        })
+    });
     Json(json!({
         "txRecvCnt": stats.get_tx_recv_cnt(),
         "blockRecvCnt": stats.get_block_recv_cnt(),
+        "networkBlocksApplied": stats.get_network_blocks_applied(),
+        "networkBlocksRejected": stats.get_network_blocks_rejected(),
+        "leadershipBlocksProduced": stats.get_leadership_blocks_produced(),
+        "lastBroadcastBlock": last_broadcast_block,
+        "lastBlockAge": stats.get_time_since_last_block().as_secs(),
+        "tipStalled": stats.is_tip_stalled(),
         "uptime": stats.get_uptime_sec(),
     }))
 }
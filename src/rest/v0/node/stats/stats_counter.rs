@@ -1,6 +1,7 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::time::Instant;
+use crate::blockcfg::{BlockDate, HeaderHash};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[derive(Clone, Debug, Default)]
 pub struct StatsCounter {
@@ -11,6 +12,12 @@ pub struct StatsCounter {
 struct StatsCounterImpl {
     tx_recv_cnt: AtomicUsize,
     block_recv_cnt: AtomicUsize,
+    network_blocks_applied: AtomicUsize,
+    network_blocks_rejected: AtomicUsize,
+    leadership_blocks_produced: AtomicUsize,
+    last_broadcast_block: Mutex<Option<(HeaderHash, BlockDate)>>,
+    last_block_time: Mutex<Instant>,
+    tip_stalled: AtomicBool,
     start_time: Instant,
 }
 
@@ -19,6 +26,12 @@ impl Default for StatsCounterImpl {
         Self {
             tx_recv_cnt: AtomicUsize::default(),
             block_recv_cnt: AtomicUsize::default(),
+            network_blocks_applied: AtomicUsize::default(),
+            network_blocks_rejected: AtomicUsize::default(),
+            leadership_blocks_produced: AtomicUsize::default(),
+            last_broadcast_block: Mutex::new(None),
+            last_block_time: Mutex::new(Instant::now()),
+            tip_stalled: AtomicBool::new(false),
             start_time: Instant::now(),
         }
     }
@@ -43,6 +56,70 @@ impl StatsCounter {
         self.stats.block_recv_cnt.load(Ordering::Relaxed) as u64
     }
 
+    /// count a block received from the network and added to the blockchain
+    pub fn add_network_block_applied(&self) {
+        self.stats
+            .network_blocks_applied
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_network_blocks_applied(&self) -> u64 {
+        self.stats.network_blocks_applied.load(Ordering::Relaxed) as u64
+    }
+
+    /// count a block received from the network and rejected
+    pub fn add_network_block_rejected(&self) {
+        self.stats
+            .network_blocks_rejected
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_network_blocks_rejected(&self) -> u64 {
+        self.stats.network_blocks_rejected.load(Ordering::Relaxed) as u64
+    }
+
+    /// count a block created by the node and added to the blockchain
+    pub fn add_leadership_block_produced(&self) {
+        self.stats
+            .leadership_blocks_produced
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_leadership_blocks_produced(&self) -> u64 {
+        self.stats
+            .leadership_blocks_produced
+            .load(Ordering::Relaxed) as u64
+    }
+
+    /// record the last block created by the node and sent to the network
+    pub fn set_last_broadcast_block(&self, hash: HeaderHash, date: BlockDate) {
+        *self.stats.last_broadcast_block.lock().unwrap() = Some((hash, date));
+    }
+
+    pub fn get_last_broadcast_block(&self) -> Option<(HeaderHash, BlockDate)> {
+        self.stats.last_broadcast_block.lock().unwrap().clone()
+    }
+
+    /// record that a block has just been applied to the blockchain
+    pub fn set_last_block_time(&self, time: Instant) {
+        *self.stats.last_block_time.lock().unwrap() = time;
+    }
+
+    /// time elapsed since the last block was applied to the blockchain,
+    /// or since the node has started if no block was applied yet
+    pub fn get_time_since_last_block(&self) -> Duration {
+        self.stats.last_block_time.lock().unwrap().elapsed()
+    }
+
+    /// set whether the tip is considered stalled, returns the previous value
+    pub fn set_tip_stalled(&self, stalled: bool) -> bool {
+        self.stats.tip_stalled.swap(stalled, Ordering::Relaxed)
+    }
+
+    pub fn is_tip_stalled(&self) -> bool {
+        self.stats.tip_stalled.load(Ordering::Relaxed)
+    }
+
     pub fn get_uptime_sec(&self) -> u64 {
         self.stats.start_time.elapsed().as_secs()
     }
@@ -0,0 +1,36 @@
+use crate::shutdown::ShutdownHandle;
+use actix_web::error::ErrorForbidden;
+use actix_web::{App, Error as ActixError, Json, Responder, State};
+
+#[derive(Clone)]
+pub struct Context {
+    shutdown: ShutdownHandle,
+    enabled: bool,
+}
+
+/// the node can only be shut down with `enabled`, the end point refuses
+/// the requests otherwise
+pub fn create_handler(
+    shutdown: ShutdownHandle,
+    enabled: bool,
+) -> impl Fn(&str) -> App<Context> + Send + Sync + Clone + 'static {
+    let context = Context { shutdown, enabled };
+    move |prefix: &str| {
+        App::with_state(context.clone())
+            .prefix(format!("{}/v0/shutdown", prefix))
+            .resource("", |r| r.post().with(handle_post))
+    }
+}
+
+/// start the shutdown sequence of the node, the result tells whether
+/// it was already started
+fn handle_post(context: State<Context>) -> Result<impl Responder, ActixError> {
+    if !context.enabled {
+        return Err(ErrorForbidden(
+            "the node cannot be shut down with the REST API, see the rest settings",
+        ));
+    }
+    Ok(Json(json!({
+        "already_started": !context.shutdown.trigger(),
+    })))
+}
@@ -1,5 +1,14 @@
 use crate::{
-    network::p2p::topology::{NodeId, NEW_BLOCKS_TOPIC, NEW_MESSAGES_TOPIC},
+    fragment::{
+        selection::{BestOfStrategies, FeeTiers, SelectionStrategy, SelectionWeights},
+        EvictionPolicy,
+    },
+    network::p2p::{
+        comm::{OccupancyWatermark, PropagationLogPolicy},
+        gossip_budget::GossipBudget,
+        gossip_compression::GossipCompression,
+        topology::{NodeId, NEW_BLOCKS_TOPIC, NEW_MESSAGES_TOPIC},
+    },
     settings::logging::LogFormat,
 };
 
@@ -17,6 +26,18 @@ pub struct Config {
     pub logger: Option<ConfigLogSettings>,
     pub rest: Option<Rest>,
     pub peer_2_peer: P2pConfig,
+    pub fragment_selection: Option<FragmentSelection>,
+    pub fragment_pool: Option<FragmentPool>,
+    /// the time without any new block after which the node is
+    /// considered stalled
+    pub stale_tip_threshold: Option<Duration>,
+    /// the number of threads validating the blocks received from the
+    /// network, 0 to validate them in the block task
+    pub block_validation_threads: Option<usize>,
+    /// read back the blocks created by the node from the storage before
+    /// announcing them to the network. It gives no guarantee the block
+    /// survives a crash of the node, the storage is not flushed.
+    pub confirm_block_readable: Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -30,6 +51,69 @@ pub struct Rest {
     pub listen: SocketAddr,
     pub prefix: Option<String>,
     pub pkcs12: Option<PathBuf>,
+    /// allow the fragment selection to be changed with the REST API,
+    /// `false` if not set
+    pub fragment_selection_update: Option<bool>,
+    /// allow the node to be shut down with the REST API, `false` if not set
+    pub shutdown_endpoint: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FragmentSelection {
+    /// the algorithm selecting the fragments to put in the blocks
+    /// (`oldest_first`, `highest_fee`, `earliest_expiry_first`,
+    /// `largest_first`, `fee_tiered`, `dependency_packages`, `weighted`
+    /// or `best_of`)
+    pub strategy: Option<SelectionStrategy>,
+
+    /// the maximum number of fragments in a block
+    pub max_per_block: Option<usize>,
+
+    /// the maximum number of fragments of a block spending from the
+    /// same account
+    pub max_per_account: Option<usize>,
+
+    /// the maximum total size, in bytes, of the fragments in a block
+    pub max_block_size: Option<usize>,
+
+    /// the minimum fee of the fragments included in the blocks created
+    /// by the node, a local policy and not a consensus rule
+    pub min_fee: Option<u64>,
+
+    /// the maximum number of inputs of the fragments included in the
+    /// blocks created by the node, a local policy and not a consensus rule
+    pub max_inputs: Option<usize>,
+
+    /// the maximum number of outputs of the fragments included in the
+    /// blocks created by the node, a local policy and not a consensus rule
+    pub max_outputs: Option<usize>,
+
+    /// the maximum number of certificate fragments in a block
+    pub max_certificates_per_block: Option<usize>,
+
+    /// the fee tiers of the `fee_tiered` strategy
+    pub fee_tiers: Option<FeeTiers>,
+
+    /// the weights of the `weighted` strategy
+    pub weights: Option<SelectionWeights>,
+
+    /// the strategies compared by the `best_of` strategy
+    pub best_of: Option<BestOfStrategies>,
+
+    /// log the decisions taken on each fragment examined for the blocks
+    /// created by the node
+    pub audit: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FragmentPool {
+    /// the maximum total size, in bytes, of the fragments held in the
+    /// pool, the pool is not limited if not set
+    pub max_memory_bytes: Option<usize>,
+
+    /// the fragments evicted first when the pool is over its memory
+    /// limit (`lowest_fee` or `oldest`)
+    pub eviction: Option<EvictionPolicy>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -55,6 +139,81 @@ pub struct P2pConfig {
     /// help the different modules of the P2P topology engine to determine the
     /// best possible neighborhood.
     pub topics_of_interests: Option<BTreeMap<Topic, InterestLevel>>,
+
+    /// the maximum age of the node records to forward in gossip
+    ///
+    /// Records we have not heard about again for longer than this are
+    /// not forwarded to other peers. If not set, all records are
+    /// forwarded.
+    pub max_gossip_age: Option<Duration>,
+
+    /// the maximum number of blocks to send in response to a single
+    /// block solicitation from a peer
+    ///
+    /// Peers soliciting more blocks only receive the first ones and
+    /// solicit the rest again. If not set, all solicited blocks are sent.
+    pub max_blocks_per_solicitation: Option<usize>,
+
+    /// the file where the connected peers are regularly saved
+    ///
+    /// When the node starts, it connects first to the peers saved in
+    /// this file, if it exists, before relying on the gossip.
+    pub peer_snapshot: Option<PathBuf>,
+
+    /// the occupancy of the subscription streams to the peers, in percent
+    /// of their capacity, from which a stream is logged as backing up, and
+    /// to which it must drop back before it is logged again
+    pub occupancy_watermark: Option<OccupancyWatermark>,
+
+    /// the log level of the failures to propagate to a peer, for each
+    /// kind of failure
+    pub propagation_log_levels: Option<PropagationLogPolicy>,
+
+    /// the time after which a subscription stream to a peer is closed
+    /// if the peer does not consume the items waiting in it
+    ///
+    /// This reclaims the resources held for the peers that subscribe and
+    /// then stop consuming. If not set, the streams are left open.
+    pub subscription_idle_timeout: Option<Duration>,
+
+    /// the maximum number of peers subscribed to the node at the same time
+    ///
+    /// The subscriptions of new peers are rejected beyond it, the peers
+    /// can retry later. If not set, the number of peers is not limited.
+    pub max_subscribed_peers: Option<usize>,
+
+    /// once `max_subscribed_peers` is reached, evict the peer the least
+    /// recently active to make room for a new one instead of rejecting it
+    pub evict_least_active_peer: Option<bool>,
+
+    /// the time after a peer connects during which it is not evicted
+    /// because its subscription streams are not set up yet or overflow,
+    /// the items to send to it are skipped instead
+    pub eviction_warmup: Option<Duration>,
+
+    /// the percentage of the peers of a single propagation whose streams
+    /// overflow beyond which the node is taken as the bottleneck, the
+    /// overflowing peers are then not evicted
+    pub propagation_breaker_percent: Option<u8>,
+
+    /// keep the trusted peers connected when propagating to them fails,
+    /// rather than evicting them as the other peers
+    pub pin_trusted_peers: Option<bool>,
+
+    /// connect on demand to the peers to solicit blocks from, rather than
+    /// only soliciting the peers already connected
+    pub connect_to_solicit_blocks: Option<bool>,
+
+    /// propagate the blocks first to the peers whose last announced block
+    /// is behind, skipping the peers already ahead
+    pub prefer_peers_behind: Option<bool>,
+
+    /// the bytes of gossip sent per second to all the peers, and whether
+    /// the gossip beyond it is dropped or deferred
+    pub gossip_budget: Option<GossipBudget>,
+
+    /// the compression of the node records sent in the gossip
+    pub gossip_compression: Option<GossipCompression>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -66,6 +225,10 @@ pub struct Topic(pub poldercast::Topic);
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct InterestLevel(pub poldercast::InterestLevel);
 
+/// A duration in human readable format (example: "15min 30s")
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Duration(pub std::time::Duration);
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TrustedPeer {
     pub address: Address,
@@ -114,6 +277,15 @@ impl Serialize for InterestLevel {
     }
 }
 
+impl Serialize for Duration {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&humantime::format_duration(self.0).to_string())
+    }
+}
+
 impl<'de> Deserialize<'de> for Address {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -202,3 +374,32 @@ impl<'de> Deserialize<'de> for InterestLevel {
         deserializer.deserialize_str(InterestLevelVisitor)
     }
 }
+
+impl<'de> Deserialize<'de> for Duration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct DurationVisitor;
+        impl<'de> Visitor<'de> for DurationVisitor {
+            type Value = Duration;
+
+            fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+                write!(fmt, "Duration (example: 15min 30s)")
+            }
+
+            fn visit_str<'a, E>(self, v: &'a str) -> std::result::Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                use serde::de::Unexpected;
+
+                match humantime::parse_duration(v) {
+                    Err(_err) => Err(E::invalid_value(Unexpected::Str(v), &self)),
+                    Ok(duration) => Ok(Duration(duration)),
+                }
+            }
+        }
+        deserializer.deserialize_str(DurationVisitor)
+    }
+}
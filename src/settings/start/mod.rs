@@ -4,12 +4,17 @@ pub mod network;
 pub use self::config::Rest;
 use self::config::{Config, ConfigLogSettings};
 use self::network::Protocol;
+use crate::blockcfg::Value;
+use crate::fragment::{
+    selection::{BuildSelectorError, SelectionConfig, SelectionParams},
+    MemoryLimit,
+};
 use crate::rest::Error as RestError;
 use crate::settings::logging::{self, LogSettings};
 use crate::settings::{command_arguments::*, Block0Info};
 use slog::Logger;
 
-use std::{collections::BTreeMap, fs::File, path::PathBuf};
+use std::{collections::BTreeMap, fs::File, path::PathBuf, time::Duration};
 
 custom_error! {pub Error
    ConfigIo { source: std::io::Error } = "Cannot read the node configuration file: {source}",
@@ -17,8 +22,17 @@ custom_error! {pub Error
    Rest { source: RestError } = "The Rest configuration is invalid: {source}",
    ExpectedBlock0Info = "Cannot start the node without the information to retrieve the genesis block",
    TooMuchBlock0Info = "Use only `--genesis-block-hash' or `--genesis-block'",
+   FragmentSelection { source: BuildSelectorError } = "The fragment selection configuration is invalid: {source}",
+   NoGossipBudget = "The gossip budget cannot be 0 bytes per second, leave it out not to limit the gossip",
+   BreakerPercentOutOfRange { percent: u8 } = "The propagation breaker percentage cannot be over 100, got {percent}",
+   WatermarkOutOfRange { high_percent: u8, low_percent: u8 } = "The occupancy watermark percentages cannot be over 100 and the low one cannot be over the high one, got high {high_percent} and low {low_percent}",
 }
 
+/// Time without any new block after which the node is considered
+/// stalled, if not configured. This amounts to many missed slots with
+/// the usual slot durations.
+const DEFAULT_STALE_TIP_THRESHOLD: Duration = Duration::from_secs(600);
+
 /// Overall Settings for node
 pub struct Settings {
     pub network: network::Configuration,
@@ -26,6 +40,11 @@ pub struct Settings {
     pub block_0: Block0Info,
     pub leadership: Vec<PathBuf>,
     pub rest: Option<Rest>,
+    pub fragment_selection: SelectionConfig,
+    pub fragment_pool_memory_limit: Option<MemoryLimit>,
+    pub stale_tip_threshold: Duration,
+    pub block_validation_threads: usize,
+    pub confirm_block_readable: bool,
 }
 
 pub struct RawSettings {
@@ -78,7 +97,7 @@ impl RawSettings {
             config,
         } = self;
         let command_arguments = &command_line.start_arguments;
-        let network = generate_network(&command_arguments, &config);
+        let network = generate_network(&command_arguments, &config)?;
 
         let storage = match (command_arguments.storage.as_ref(), config.storage) {
             (Some(path), _) => Some(path.clone()),
@@ -108,12 +127,54 @@ impl RawSettings {
             (None, Some(hash)) => Block0Info::Hash(hash.clone()),
         };
 
+        let fragment_selection = match config.fragment_selection {
+            None => SelectionConfig::default(),
+            Some(selection) => {
+                let defaults = SelectionParams::default();
+                SelectionConfig {
+                    strategy: selection.strategy.unwrap_or_default(),
+                    params: SelectionParams {
+                        max_per_block: selection.max_per_block.unwrap_or(defaults.max_per_block),
+                        max_per_account: selection.max_per_account,
+                        max_block_size: selection.max_block_size,
+                        min_fee: selection.min_fee.map(Value),
+                        max_inputs: selection.max_inputs,
+                        max_outputs: selection.max_outputs,
+                        max_certificates_per_block: selection.max_certificates_per_block,
+                        fee_tiers: selection.fee_tiers,
+                        weights: selection.weights,
+                        best_of: selection.best_of,
+                    },
+                    audit: selection.audit.unwrap_or(false),
+                }
+            }
+        };
+        // check the selection can be built now rather than when
+        // producing the first block
+        fragment_selection.build()?;
+
+        let fragment_pool_memory_limit = config.fragment_pool.and_then(|pool| {
+            let eviction = pool.eviction.unwrap_or_default();
+            pool.max_memory_bytes.map(|max_bytes| MemoryLimit {
+                max_bytes,
+                eviction,
+            })
+        });
+
         Ok(Settings {
             storage: storage,
             block_0: block0_info,
             network: network,
             leadership,
             rest: config.rest,
+            fragment_selection,
+            fragment_pool_memory_limit,
+            stale_tip_threshold: config
+                .stale_tip_threshold
+                .map(|threshold| threshold.0)
+                .unwrap_or(DEFAULT_STALE_TIP_THRESHOLD),
+            block_validation_threads: config.block_validation_threads.unwrap_or(0),
+            confirm_block_readable: config.confirm_block_readable.unwrap_or(false),
         })
     }
 }
@@ -121,9 +182,27 @@ impl RawSettings {
 fn generate_network(
     _command_arguments: &StartArguments,
     config: &Config,
-) -> network::Configuration {
+) -> Result<network::Configuration, Error> {
     let p2p = &config.peer_2_peer;
-    network::Configuration {
+    if let Some(budget) = p2p.gossip_budget {
+        if budget.bytes_per_second == 0 {
+            return Err(Error::NoGossipBudget);
+        }
+    }
+    if let Some(percent) = p2p.propagation_breaker_percent {
+        if percent > 100 {
+            return Err(Error::BreakerPercentOutOfRange { percent });
+        }
+    }
+    if let Some(watermark) = p2p.occupancy_watermark {
+        if watermark.high_percent > 100 || watermark.low_percent > watermark.high_percent {
+            return Err(Error::WatermarkOutOfRange {
+                high_percent: watermark.high_percent,
+                low_percent: watermark.low_percent,
+            });
+        }
+    }
+    Ok(network::Configuration {
         public_id: p2p.public_id.clone(),
         public_address: p2p.public_address.clone(),
         listen: p2p.listen.clone(),
@@ -135,5 +214,23 @@ fn generate_network(
             .clone()
             .unwrap_or(BTreeMap::new()),
         timeout: std::time::Duration::from_secs(15),
-    }
+        max_gossip_age: p2p.max_gossip_age.map(|age| age.0),
+        max_blocks_per_solicitation: p2p.max_blocks_per_solicitation,
+        peer_snapshot: p2p.peer_snapshot.clone(),
+        occupancy_watermark: p2p.occupancy_watermark.unwrap_or_default(),
+        propagation_log_policy: p2p.propagation_log_levels.unwrap_or_default(),
+        subscription_idle_timeout: p2p.subscription_idle_timeout.map(|timeout| timeout.0),
+        max_subscribed_peers: p2p.max_subscribed_peers,
+        evict_least_active_peer: p2p.evict_least_active_peer.unwrap_or(false),
+        eviction_warmup: p2p
+            .eviction_warmup
+            .map(|warmup| warmup.0)
+            .unwrap_or(std::time::Duration::from_secs(0)),
+        propagation_breaker_percent: p2p.propagation_breaker_percent,
+        pin_trusted_peers: p2p.pin_trusted_peers.unwrap_or(false),
+        connect_to_solicit_blocks: p2p.connect_to_solicit_blocks.unwrap_or(false),
+        prefer_peers_behind: p2p.prefer_peers_behind.unwrap_or(false),
+        gossip_budget: p2p.gossip_budget,
+        gossip_compression: p2p.gossip_compression,
+    })
 }
@@ -1,7 +1,12 @@
-use std::{collections::BTreeMap, net::SocketAddr, str, time::Duration};
+use std::{collections::BTreeMap, net::SocketAddr, path::PathBuf, str, time::Duration};
 
 use crate::{
-    network::p2p::topology::NodeId,
+    network::p2p::{
+        comm::{OccupancyWatermark, PropagationLogPolicy},
+        gossip_budget::GossipBudget,
+        gossip_compression::GossipCompression,
+        topology::NodeId,
+    },
     settings::start::config::{Address, InterestLevel, Topic, TrustedPeer},
 };
 
@@ -67,6 +72,61 @@ pub struct Configuration {
 
     /// the default value for the timeout for inactive connection
     pub timeout: Duration,
+
+    /// the maximum age of the node records forwarded in gossip,
+    /// if limited
+    pub max_gossip_age: Option<Duration>,
+
+    /// the maximum number of blocks served in response to a single
+    /// solicitation, if limited
+    pub max_blocks_per_solicitation: Option<usize>,
+
+    /// the file to save the connected peers to, and to read the peers
+    /// to reconnect to at startup from
+    pub peer_snapshot: Option<PathBuf>,
+
+    /// the occupancy thresholds of the subscription streams to the peers
+    /// at which the streams backing up are logged
+    pub occupancy_watermark: OccupancyWatermark,
+
+    /// the log level of the failures to propagate to a peer
+    pub propagation_log_policy: PropagationLogPolicy,
+
+    /// the time after which the subscription streams to a peer not
+    /// draining them are closed, if limited
+    pub subscription_idle_timeout: Option<Duration>,
+
+    /// the maximum number of peers subscribed to the node, if limited
+    pub max_subscribed_peers: Option<usize>,
+
+    /// evict the least active peer to make room for a new subscriber
+    /// rather than rejecting it, once `max_subscribed_peers` is reached
+    pub evict_least_active_peer: bool,
+
+    /// the time after a peer connects during which the failures to send
+    /// to it for its streams not set up or overflowing are forgiven
+    pub eviction_warmup: Duration,
+
+    /// the percentage of the peers of a propagation overflowing beyond
+    /// which they are not evicted, if set
+    pub propagation_breaker_percent: Option<u8>,
+
+    /// pin the trusted peers in the peer map, see `PeerMap::pin_peer`
+    pub pin_trusted_peers: bool,
+
+    /// connect to the peers known to the topology when blocks are to be
+    /// solicited from them while they are not connected
+    pub connect_to_solicit_blocks: bool,
+
+    /// propagate the blocks first to the peers known to be behind them,
+    /// skipping the peers known to be ahead
+    pub prefer_peers_behind: bool,
+
+    /// the node-wide budget of the gossip sent to the peers, if limited
+    pub gossip_budget: Option<GossipBudget>,
+
+    /// the compression of the node records sent in the gossip, if enabled
+    pub gossip_compression: Option<GossipCompression>,
 }
 
 impl Peer {
@@ -107,4 +167,15 @@ impl Configuration {
                 .and_then(|address| address.to_socketaddr()))
             .map(|addr| Listen::new(addr, self.protocol))
     }
+
+    /// Drops the block ids beyond the number of blocks we serve
+    /// in response to a single solicitation.
+    ///
+    /// The peer soliciting the blocks is expected to solicit the
+    /// remaining ones again.
+    pub fn limit_block_solicitation<T>(&self, block_ids: &mut Vec<T>) {
+        if let Some(max) = self.max_blocks_per_solicitation {
+            block_ids.truncate(max);
+        }
+    }
 }
@@ -0,0 +1,140 @@
+//! coordinated shutdown of the node
+//!
+//! The tasks are stopped in sequence, each step is acknowledged by
+//! the task before the next one starts:
+//!
+//! 1. the block task stops accepting new blocks, once the blocks it
+//!    has already received are processed and their propagation has
+//!    been queued to the network task;
+//! 2. the network task flushes the pending propagations to the peers
+//!    and closes their subscriptions.
+//!
+//! So a clean stop reliably sends the last block to the peers before
+//! the connections are torn down. The sequence is triggered by the REST
+//! API, if enabled, or by the `SIGINT` and `SIGTERM` signals.
+
+use crate::intercom::{self, BlockMsg, NetworkMsg, ReplyHandle};
+use crate::utils::async_msg::MessageBox;
+
+use futures::sync::oneshot;
+use futures::{Future, Stream};
+use slog::Logger;
+use std::sync::{Arc, Mutex};
+use tokio_signal::IoStream;
+
+/// the shutdown sequence, waiting to be triggered with a `ShutdownHandle`
+pub struct Shutdown {
+    block_box: MessageBox<BlockMsg>,
+    network_box: MessageBox<NetworkMsg>,
+    trigger: oneshot::Receiver<()>,
+}
+
+/// triggers the shutdown sequence of the node
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<Mutex<Option<oneshot::Sender<()>>>>);
+
+custom_error! {pub SequenceError
+    BlockTask = "the block task has not acknowledged the shutdown",
+    NetworkTask = "the network task has not acknowledged the shutdown",
+}
+
+impl Shutdown {
+    pub fn new(
+        block_box: MessageBox<BlockMsg>,
+        network_box: MessageBox<NetworkMsg>,
+    ) -> (Self, ShutdownHandle) {
+        let (sender, trigger) = oneshot::channel();
+        let shutdown = Shutdown {
+            block_box,
+            network_box,
+            trigger,
+        };
+        (shutdown, ShutdownHandle(Arc::new(Mutex::new(Some(sender)))))
+    }
+
+    /// wait for the shutdown to be triggered then run the sequence,
+    /// blocking the current thread.
+    ///
+    /// Returns `false` without running the sequence if all the handles
+    /// have been dropped.
+    pub fn run(self, logger: &Logger) -> Result<bool, SequenceError> {
+        let Shutdown {
+            mut block_box,
+            mut network_box,
+            trigger,
+        } = self;
+        if trigger.wait().is_err() {
+            return Ok(false);
+        }
+
+        info!(logger, "shutting down: stopping the block task");
+        step(&mut block_box, BlockMsg::Shutdown, logger).map_err(|()| SequenceError::BlockTask)?;
+
+        info!(
+            logger,
+            "shutting down: flushing the propagations to the peers"
+        );
+        step(&mut network_box, NetworkMsg::Shutdown, logger)
+            .map_err(|()| SequenceError::NetworkTask)?;
+
+        info!(logger, "shutdown sequence complete");
+        Ok(true)
+    }
+}
+
+/// send the shutdown message to a task and wait for its acknowledgment
+fn step<Msg, F>(msg_box: &mut MessageBox<Msg>, make_msg: F, logger: &Logger) -> Result<(), ()>
+where
+    F: FnOnce(ReplyHandle<()>) -> Msg,
+{
+    let (reply_handle, reply_future) = intercom::unary_reply::<(), intercom::Error>(logger.clone());
+    msg_box
+        .send_blocking(make_msg(reply_handle))
+        .map_err(|_| ())?;
+    reply_future.wait().map_err(|_| ())
+}
+
+impl ShutdownHandle {
+    /// start the shutdown sequence, returns `false` if it has already
+    /// been started
+    pub fn trigger(&self) -> bool {
+        match self.0.lock().unwrap().take() {
+            Some(sender) => sender.send(()).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// trigger the shutdown sequence when the process is interrupted by
+/// `SIGINT`, or `SIGTERM` on Unix. Another signal received while the
+/// sequence runs exits the process right away.
+pub fn trigger_on_signals(
+    handle: ShutdownHandle,
+    logger: Logger,
+) -> impl Future<Item = (), Error = ()> {
+    let error_logger = logger.clone();
+    signals()
+        .for_each(move |()| {
+            if handle.trigger() {
+                info!(logger, "signal received, shutting down");
+            } else {
+                warn!(logger, "signal received again, exiting");
+                std::process::exit(1);
+            }
+            Ok(())
+        })
+        .map_err(move |e| error!(error_logger, "cannot listen to the signals: {}", e))
+}
+
+#[cfg(unix)]
+fn signals() -> IoStream<()> {
+    use tokio_signal::unix::{Signal, SIGTERM};
+
+    let sigterm = Signal::new(SIGTERM).flatten_stream().map(|_| ());
+    Box::new(tokio_signal::ctrl_c().flatten_stream().select(sigterm))
+}
+
+#[cfg(not(unix))]
+fn signals() -> IoStream<()> {
+    Box::new(tokio_signal::ctrl_c().flatten_stream())
+}
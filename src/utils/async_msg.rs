@@ -2,22 +2,62 @@
 //! asynchronous reading.
 
 use futures::prelude::*;
-use futures::sync::mpsc::{self, Receiver, Sender, TrySendError};
+use futures::sync::mpsc::{self, Receiver, SendError, Sender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 /// The output end of an in-memory FIFO channel.
-pub struct MessageBox<Msg>(Sender<Msg>);
+pub struct MessageBox<Msg> {
+    sender: Sender<Msg>,
+    congestion: Congestion,
+}
 
 /// The input end of an in-memory FIFO channel.
 /// This can be read asynchronously in a Tokio task using its
 /// Stream implementation.
-pub struct MessageQueue<Msg>(Receiver<Msg>);
+pub struct MessageQueue<Msg> {
+    receiver: Receiver<Msg>,
+    congestion: Congestion,
+}
+
+// Since when the channel is congested: set when a message cannot be sent
+// as the channel is full, cleared when the receiving task has drained
+// the channel.
+#[derive(Clone, Default)]
+struct Congestion(Arc<Mutex<Option<Instant>>>);
+
+impl Congestion {
+    fn set(&self) {
+        let mut since = self.0.lock().unwrap();
+        if since.is_none() {
+            *since = Some(Instant::now());
+        }
+    }
+
+    fn clear(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    fn since(&self) -> Option<Instant> {
+        *self.0.lock().unwrap()
+    }
+}
 
 /// Constructs an in-memory channel and returns the output and input halves.
 /// The parameter specifies the number of messages that are allowed
 /// to be pending in the channel.
 pub fn channel<Msg>(buffer: usize) -> (MessageBox<Msg>, MessageQueue<Msg>) {
     let (tx, rx) = mpsc::channel(buffer);
-    (MessageBox(tx), MessageQueue(rx))
+    let congestion = Congestion::default();
+    let message_box = MessageBox {
+        sender: tx,
+        congestion: congestion.clone(),
+    };
+    let message_queue = MessageQueue {
+        receiver: rx,
+        congestion,
+    };
+    (message_box, message_queue)
 }
 
 impl<Msg> MessageBox<Msg> {
@@ -29,9 +69,39 @@ impl<Msg> MessageBox<Msg> {
     /// # Errors
     ///
     /// If the channel is full or the receiving MessageQueue has been dropped,
-    /// an error is returned in `Err`.
+    /// an error is returned in `Err`. A full channel is reported as
+    /// congested by all the message boxes of the channel, see
+    /// `congested_since`.
     pub fn try_send(&mut self, a: Msg) -> Result<(), TrySendError<Msg>> {
-        self.0.try_send(a)
+        self.sender.try_send(a).map_err(|e| {
+            if e.is_full() {
+                self.congestion.set();
+            }
+            e
+        })
+    }
+
+    /// The time at which a message was first found not to fit in the
+    /// channel, if the receiving task has not drained the channel since.
+    ///
+    /// This tells the senders that the receiving task is not keeping up
+    /// with them, before or besides their own messages being refused.
+    pub fn congested_since(&self) -> Option<Instant> {
+        self.congestion.since()
+    }
+
+    /// Sends a message over the channel, blocking the current thread
+    /// until there is room in the channel.
+    ///
+    /// This is meant for the threads outside of the Tokio runtime,
+    /// it must not be called from a task.
+    ///
+    /// # Errors
+    ///
+    /// If the receiving MessageQueue has been dropped,
+    /// an error is returned in `Err`.
+    pub fn send_blocking(&mut self, a: Msg) -> Result<(), SendError<Msg>> {
+        (&mut self.sender).send(a).wait().map(|_| ())
     }
 }
 
@@ -39,12 +109,46 @@ impl<Msg> Stream for MessageQueue<Msg> {
     type Item = Msg;
     type Error = ();
     fn poll(&mut self) -> Poll<Option<Msg>, ()> {
-        self.0.poll()
+        let res = self.receiver.poll();
+        if let Ok(Async::NotReady) = res {
+            self.congestion.clear();
+        }
+        res
     }
 }
 
 impl<Msg> Clone for MessageBox<Msg> {
     fn clone(&self) -> Self {
-        MessageBox(self.0.clone())
+        MessageBox {
+            sender: self.sender.clone(),
+            congestion: self.congestion.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+
+    #[test]
+    fn congestion_is_cleared_once_drained() {
+        let (mut message_box, mut queue) = channel(0);
+        let observer = message_box.clone();
+
+        message_box.try_send(1).unwrap();
+        assert!(observer.congested_since().is_none());
+        assert!(message_box.try_send(2).unwrap_err().is_full());
+        assert!(observer.congested_since().is_some());
+
+        future::poll_fn(|| {
+            assert_eq!(queue.poll(), Ok(Async::Ready(Some(1))));
+            assert!(observer.congested_since().is_some());
+            assert_eq!(queue.poll(), Ok(Async::NotReady));
+            Ok::<_, ()>(Async::Ready(()))
+        })
+        .wait()
+        .unwrap();
+        assert!(observer.congested_since().is_none());
     }
 }
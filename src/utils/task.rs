@@ -8,6 +8,7 @@
 use crate::utils::async_msg::{self, MessageBox};
 use slog::Logger;
 use std::{
+    collections::VecDeque,
     sync::mpsc::{self, Sender},
     thread,
     time::{Duration, Instant},
@@ -79,6 +80,43 @@ pub enum Input<Msg> {
     Input(Msg),
 }
 
+/// the inputs waiting to be handled by a service, the urgent ones first,
+/// see `Services::spawn_with_prioritized_inputs`
+struct PrioritizedInputs<Msg, P> {
+    is_urgent: P,
+    urgent: VecDeque<Msg>,
+    others: VecDeque<Msg>,
+}
+
+impl<Msg, P> PrioritizedInputs<Msg, P>
+where
+    P: Fn(&Msg) -> bool,
+{
+    fn new(is_urgent: P) -> Self {
+        PrioritizedInputs {
+            is_urgent,
+            urgent: VecDeque::new(),
+            others: VecDeque::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.urgent.is_empty() && self.others.is_empty()
+    }
+
+    fn push(&mut self, msg: Msg) {
+        if (self.is_urgent)(&msg) {
+            self.urgent.push_back(msg)
+        } else {
+            self.others.push_back(msg)
+        }
+    }
+
+    fn pop(&mut self) -> Option<Msg> {
+        self.urgent.pop_front().or_else(|| self.others.pop_front())
+    }
+}
+
 enum Inner {
     Tokio { runtime: runtime::Runtime },
     Thread { handler: thread::JoinHandle<()> },
@@ -155,6 +193,62 @@ impl Services {
         TaskMessageBox(tx)
     }
 
+    /// spawn a service that will be launched for every given inputs, the
+    /// urgent inputs as told by `is_urgent` first, then the other ones,
+    /// each in the order they were received
+    ///
+    /// The function may give back an input to be handled after the inputs
+    /// waiting at that time, e.g. the rest of a long request, so a long
+    /// request does not hold up the other ones. The service stops as
+    /// `spawn_with_inputs` once all the inputs have been handled.
+    pub fn spawn_with_prioritized_inputs<P, F, Msg>(
+        &mut self,
+        name: &'static str,
+        is_urgent: P,
+        mut f: F,
+    ) -> TaskMessageBox<Msg>
+    where
+        P: Fn(&Msg) -> bool,
+        P: Send + 'static,
+        F: FnMut(&ThreadServiceInfo, Input<Msg>) -> Option<Msg>,
+        F: Send + 'static,
+        Msg: Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel::<Msg>();
+
+        self.spawn(name, move |info| {
+            let mut inputs = PrioritizedInputs::new(is_urgent);
+            loop {
+                if inputs.is_empty() {
+                    match rx.recv() {
+                        Ok(msg) => inputs.push(msg),
+                        Err(err) => {
+                            warn!(
+                                info.logger,
+                                "Shutting down service {} (up since {}): {}",
+                                name,
+                                humantime::format_duration(info.up_time()),
+                                err
+                            );
+                            f(&info, Input::Shutdown);
+                            break;
+                        }
+                    }
+                }
+                // the inputs received meanwhile compete for their turn
+                while let Ok(msg) = rx.try_recv() {
+                    inputs.push(msg);
+                }
+                let msg = inputs.pop().expect("at least one input is waiting");
+                if let Some(later) = f(&info, Input::Input(msg)) {
+                    inputs.push(later);
+                }
+            }
+        });
+
+        TaskMessageBox(tx)
+    }
+
     /// Spawn the given Future in a new dedicated runtime
     ///
     /// * utilising one thread only;
@@ -257,6 +351,18 @@ impl ThreadServiceInfo {
 }
 
 impl TokioServiceInfo {
+    /// the information of a service running on the given executor, for
+    /// the tests calling the functions of a service outside of it
+    #[cfg(feature = "testing")]
+    pub fn for_tests(name: &'static str, logger: Logger, executor: runtime::TaskExecutor) -> Self {
+        TokioServiceInfo {
+            name,
+            up_time: Instant::now(),
+            logger,
+            executor,
+        }
+    }
+
     /// get the time this service has been running since
     #[inline]
     pub fn up_time(&self) -> Duration {
@@ -327,3 +433,23 @@ impl<Msg> TaskMessageBox<Msg> {
         self.0.send(a).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urgent_inputs_are_handled_first() {
+        let mut inputs = PrioritizedInputs::new(|msg: &u32| *msg < 10);
+        for msg in vec![100, 1, 200, 2] {
+            inputs.push(msg);
+        }
+
+        let mut order = Vec::new();
+        while let Some(msg) = inputs.pop() {
+            order.push(msg);
+        }
+        assert_eq!(order, vec![1, 2, 100, 200]);
+        assert!(inputs.is_empty());
+    }
+}
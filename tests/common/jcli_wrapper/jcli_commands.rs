@@ -142,6 +142,22 @@ pub fn get_address_account_command(public_key: &str, discrimination: Discriminat
     command
 }
 
+/// Get adress account command, deriving the public key from a secret key file.
+pub fn get_address_account_from_secret_file_command(
+    secret_file: &PathBuf,
+    discrimination: Discrimination,
+) -> Command {
+    let mut command = Command::new(configuration::get_jcli_app().as_os_str());
+    command
+        .arg("address")
+        .arg("account")
+        .arg("--secret-file")
+        .arg(secret_file.as_os_str());
+    add_discrimination(&mut command, discrimination);
+    println!("Run address acccount command: {:?}", &command);
+    command
+}
+
 fn add_discrimination(command: &mut Command, discrimination: Discrimination) {
     if discrimination == Discrimination::Test {
         command.arg("--testing");
@@ -172,6 +188,25 @@ pub fn get_address_delegation_command(
     command
 }
 
+/// Get adress group command.
+pub fn get_address_group_command(
+    spending_key: &str,
+    delegation_key: &str,
+    discrimination: Discrimination,
+) -> Command {
+    let mut command = Command::new(configuration::get_jcli_app().as_os_str());
+    command
+        .arg("address")
+        .arg("group")
+        .arg("--spending-key")
+        .arg(&spending_key)
+        .arg("--delegation-key")
+        .arg(&delegation_key);
+    add_discrimination(&mut command, discrimination);
+    println!("Run address group command: {:?}", &command);
+    command
+}
+
 /// Get post transaction command.
 pub fn get_post_transaction_command(transaction_hash: &str, host: &str) -> Command {
     let transaction_hash_file_path =
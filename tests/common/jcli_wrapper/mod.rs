@@ -141,6 +141,19 @@ pub fn assert_address_delegation(
     single_line
 }
 
+pub fn assert_address_group(
+    spending_key: &str,
+    delegation_key: &str,
+    discrimination: Discrimination,
+) -> String {
+    let output = process_utils::run_process_and_get_output(
+        jcli_commands::get_address_group_command(&spending_key, &delegation_key, discrimination),
+    );
+    let single_line = output.as_single_line();
+    process_assert::assert_process_exited_successfully(output);
+    single_line
+}
+
 pub fn assert_address_account(public_key: &str, discrimination: Discrimination) -> String {
     let output = process_utils::run_process_and_get_output(
         jcli_commands::get_address_account_command(&public_key, discrimination),
@@ -150,6 +163,19 @@ pub fn assert_address_account(public_key: &str, discrimination: Discrimination)
     single_line
 }
 
+pub fn assert_address_account_from_secret(
+    private_key: &str,
+    discrimination: Discrimination,
+) -> String {
+    let secret_file = file_utils::create_file_in_temp("account.sk", &private_key);
+    let output = process_utils::run_process_and_get_output(
+        jcli_commands::get_address_account_from_secret_file_command(&secret_file, discrimination),
+    );
+    let single_line = output.as_single_line();
+    process_assert::assert_process_exited_successfully(output);
+    single_line
+}
+
 pub fn assert_post_transaction(transaction_hash: &str, host: &str) -> () {
     let output = process_utils::run_process_and_get_output(
         jcli_commands::get_post_transaction_command(&transaction_hash, &host),
@@ -31,3 +31,20 @@ pub fn test_account_address_made_of_ed25519_extended_key() {
     let account_address = jcli_wrapper::assert_address_account(&public_key, Discrimination::Test);
     assert_ne!(account_address, "", "generated account address is empty");
 }
+
+#[test]
+pub fn test_account_address_made_of_ed25519_extended_secret_key() {
+    let private_key = jcli_wrapper::assert_key_generate("ed25519Extended");
+    println!("private key: {}", &private_key);
+
+    let public_key = jcli_wrapper::assert_key_to_public_default(&private_key);
+    println!("public key: {}", &public_key);
+
+    let expected_address = jcli_wrapper::assert_address_account(&public_key, Discrimination::Test);
+    let account_address =
+        jcli_wrapper::assert_address_account_from_secret(&private_key, Discrimination::Test);
+    assert_eq!(
+        account_address, expected_address,
+        "address from secret key differs from the address from the public key"
+    );
+}
@@ -0,0 +1,28 @@
+#![cfg(feature = "integration-test")]
+
+use common::jcli_wrapper;
+use common::jcli_wrapper::Discrimination;
+
+#[test]
+pub fn test_group_address_is_the_same_as_delegation_address() {
+    let private_key = jcli_wrapper::assert_key_generate("ed25519Extended");
+    let spending_key = jcli_wrapper::assert_key_to_public_default(&private_key);
+    println!("spending key: {}", &spending_key);
+
+    let private_key = jcli_wrapper::assert_key_generate("ed25519Extended");
+    let delegation_key = jcli_wrapper::assert_key_to_public_default(&private_key);
+    println!("delegation key: {}", &delegation_key);
+
+    let group_address =
+        jcli_wrapper::assert_address_group(&spending_key, &delegation_key, Discrimination::Test);
+    let delegation_address = jcli_wrapper::assert_address_delegation(
+        &spending_key,
+        &delegation_key,
+        Discrimination::Test,
+    );
+    assert_eq!(group_address, delegation_address);
+
+    let info = jcli_wrapper::assert_get_address_info(&group_address);
+    assert_eq!(info.get("public key"), Some(&spending_key));
+    assert_eq!(info.get("group key"), Some(&delegation_key));
+}
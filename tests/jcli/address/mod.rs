@@ -1,3 +1,4 @@
 pub mod account;
+pub mod group;
 pub mod info;
 pub mod single;